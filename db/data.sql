@@ -1,4 +1,4 @@
-INSERT INTO users (username, email, first_name, last_name, password, role, avatar_url) VALUES ('demo', 'demo@example.com', 'John', 'Doe', '12345678', 'admin', 'https://secure.gravatar.com/avatar/e1930bd4d635a8ed77450426e269eaa9?s=32&d=mm&r=g');
+INSERT INTO users (username, email, first_name, last_name, password, role, avatar_url) VALUES ('demo', 'demo@example.com', 'John', 'Doe', '$argon2id$v=19$m=19456,t=2,p=1$fyoz3MN/19WaW0jmRofIPA$Qv/bAwKu6n/BiTve31e+d3PZE1lE8XUEQs4vyEVAhyA', 'admin', 'https://secure.gravatar.com/avatar/e1930bd4d635a8ed77450426e269eaa9?s=32&d=mm&r=g');
 
 -- INSERT INTO categories (name, slug, parent) VALUES
 -- ('Uncategorized', 'uncategorized', 0),
@@ -1,14 +1,28 @@
 -- psql -W -U store_admin -d mystoredb -a -w -f schema.sql
 -- drop tables if exists
+DROP TABLE IF EXISTS reviews;
+DROP TABLE IF EXISTS product_revisions;
 DROP TABLE IF EXISTS user_sessions;
 DROP TABLE IF EXISTS customer_sessions;
 DROP TABLE IF EXISTS settings;
 DROP TABLE IF EXISTS users;
 DROP TABLE IF EXISTS tokens;
+DROP TABLE IF EXISTS password_resets;
+DROP TABLE IF EXISTS email_verifications;
+DROP TABLE IF EXISTS login_attempts;
+DROP TABLE IF EXISTS login_throttles;
+DROP TABLE IF EXISTS recovery_codes;
+DROP TABLE IF EXISTS audit_log;
 DROP TABLE IF EXISTS dimentions;
+DROP TABLE IF EXISTS grouped_products;
+DROP TABLE IF EXISTS linked_products;
+DROP TABLE IF EXISTS product_variations;
+DROP TABLE IF EXISTS product_attributes;
 DROP TABLE IF EXISTS product_media;
 DROP TABLE IF EXISTS media;
 DROP TABLE IF EXISTS product_categories;
+DROP TABLE IF EXISTS product_tags;
+DROP TABLE IF EXISTS tags;
 DROP TABLE IF EXISTS categories;
 DROP TABLE IF EXISTS products;
 DROP TABLE IF EXISTS orders;
@@ -17,49 +31,127 @@ DROP TYPE type;
 DROP TYPE status;
 DROP TYPE stock_status;
 DROP TYPE catalog_visibility;
+DROP TYPE link_type;
+DROP TYPE review_status;
 DROP TYPE user_roles;
 DROP TYPE order_status;
 DROP TYPE currency;
 DROP TYPE iso_contry_code;
 
 CREATE TYPE type AS ENUM ('simple', 'grouped', 'external', 'variable');
-CREATE TYPE status AS ENUM ('draft', 'pending', 'private', 'publish');
+CREATE TYPE status AS ENUM ('draft', 'pending', 'private', 'publish', 'trash');
 CREATE TYPE stock_status AS ENUM ('instock', 'outofstock', 'onbackorder');
 CREATE TYPE catalog_visibility AS ENUM ('visible', 'catalog', 'search', 'hidden');
-CREATE TYPE user_roles AS ENUM ('admin', 'customer', 'guest');
+CREATE TYPE link_type AS ENUM ('upsell', 'cross_sell');
+CREATE TYPE review_status AS ENUM ('pending', 'approved', 'spam', 'trash');
+CREATE TYPE user_roles AS ENUM ('admin', 'shop_manager', 'editor', 'support', 'customer', 'guest');
 CREATE TYPE order_status AS ENUM ('pending', 'processing', 'onhold', 'completed', 'cancelled', 'refunded', 'failed', 'trash');
 CREATE TYPE currency AS ENUM ('EUR', 'USD');
 CREATE TYPE iso_contry_code AS ENUM ('FR', 'ES', 'PT', 'US');
 
 CREATE TABLE settings (
     id SERIAL PRIMARY KEY,
-    smtp_server VARCHAR(255) NOT NULL,
+    smtp_server VARCHAR(255) NOT NULL DEFAULT '', -- empty when email is not configured
     smtp_port INTEGER DEFAULT 0,
-    smtp_username VARCHAR(255) NOT NULL,
-    smtp_password VARCHAR(255) NOT NULL,
-    smtp_use_tls BOOLEAN DEFAULT FALSE
+    smtp_username VARCHAR(255) NOT NULL DEFAULT '',
+    smtp_password VARCHAR(255) NOT NULL DEFAULT '',
+    smtp_use_tls BOOLEAN DEFAULT FALSE,
+    require_admin_2fa BOOLEAN NOT NULL DEFAULT FALSE, -- admins must use two-factor authentication
+    site_url VARCHAR(512) NOT NULL DEFAULT '', -- address of the store in the emailed links, https://shop.example.com
+    trash_days INTEGER NOT NULL DEFAULT 30 -- products in the trash longer are deleted for good, 0 keeps them
 );
 
+-- one row per logged in device, only the sha256 of the token of the cookie is stored
 CREATE TABLE tokens (
-    token VARCHAR(128) NOT NULL,
-    user_id INTEGER UNIQUE NOT NULL,
+    id SERIAL PRIMARY KEY,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    user_id INTEGER NOT NULL,
+    user_agent VARCHAR(512) NOT NULL DEFAULT '',
+    ip_address VARCHAR(45) NOT NULL DEFAULT '',
+    last_seen TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     expires TIMESTAMP DEFAULT CURRENT_TIMESTAMP + interval '24 hours'
 );
 
+CREATE INDEX tokens_user_id_idx ON tokens (user_id);
+
+-- only the sha256 of the emailed token is stored, rows are deleted when used
+CREATE TABLE password_resets (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    expires TIMESTAMP DEFAULT CURRENT_TIMESTAMP + interval '1 hour'
+);
+
+-- proves that a customer owns an email address before the guest orders
+-- placed with it are added to the account, only the sha256 of the token is stored
+CREATE TABLE email_verifications (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    expires TIMESTAMP DEFAULT CURRENT_TIMESTAMP + interval '48 hours'
+);
+
+-- log of every login attempt
+CREATE TABLE login_attempts (
+    id SERIAL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    ip_address VARCHAR(45) NOT NULL,
+    success BOOLEAN NOT NULL DEFAULT FALSE,
+    date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX login_attempts_username_idx ON login_attempts (username);
+
+-- consecutive failures by username ('user:...') and by ip address ('ip:...')
+CREATE TABLE login_throttles (
+    key VARCHAR(300) PRIMARY KEY,
+    failures INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMP,
+    last_failure TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
 CREATE TABLE users (
     id SERIAL PRIMARY KEY,
     username VARCHAR(128) UNIQUE NOT NULL,
     email VARCHAR(255) UNIQUE NOT NULL,
-    phone VARCHAR(255) UNIQUE NOT NULL DEFAULT '',
+    phone VARCHAR(255) NOT NULL DEFAULT '',
     password TEXT NOT NULL,
     first_name VARCHAR(255) NOT NULL DEFAULT '',
     last_name VARCHAR(255) NOT NULL DEFAULT '',
     role user_roles DEFAULT 'guest',
     avatar_url VARCHAR(255) NOT NULL DEFAULT '',
+    billing JSONB, -- saved billing address
+    shipping JSONB, -- saved shipping address
+    totp_secret VARCHAR(64), -- base32, set when two-factor authentication is enabled
+    totp_last_step BIGINT NOT NULL DEFAULT 0, -- last time step used, codes can not be replayed
     date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     date_modified TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
 
+-- single-use two-factor recovery codes, only the sha256 is stored
+CREATE TABLE recovery_codes (
+    user_id INTEGER NOT NULL,
+    code_hash VARCHAR(64) NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+-- who changed what in the backend, before and after only hold the changed fields
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    actor_id INTEGER, -- NULL when the change was not made from the backend, e.g. the command line
+    actor VARCHAR(255) NOT NULL DEFAULT 'system', -- username at the time of the change
+    ip_address VARCHAR(45) NOT NULL DEFAULT '',
+    action VARCHAR(64) NOT NULL, -- e.g. product.update
+    entity_type VARCHAR(32) NOT NULL,
+    entity_id INTEGER NOT NULL,
+    before JSONB,
+    after JSONB,
+    date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_entity_idx ON audit_log (entity_type, entity_id);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor_id);
+
 CREATE TABLE products (
     id SERIAL PRIMARY KEY,
     sku VARCHAR(255) NOT NULL,
@@ -85,9 +177,25 @@ CREATE TABLE products (
     date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     date_modified TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     primary_category INTEGER NOT NULL DEFAULT 0,
+    external_url VARCHAR(2048) NOT NULL DEFAULT '', -- external products are bought on another site
+    button_text VARCHAR(255) NOT NULL DEFAULT '',
+    average_rating NUMERIC(3, 2) NOT NULL DEFAULT 0.00, -- of the approved reviews, see reviews
+    rating_count INT NOT NULL DEFAULT 0,
+    total_sales INT NOT NULL DEFAULT 0, -- items of the processing and completed orders, see orders
+    trashed_status status, -- the status before the product was moved to the trash, restored with it
+    date_trashed TIMESTAMP, -- deleted for good after the trash_days of the settings
+    -- full-text search, the name and the SKU rank above the descriptions
+    search_vector TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', COALESCE(name, '')), 'A') ||
+        setweight(to_tsvector('simple', COALESCE(sku, '')), 'A') ||
+        setweight(to_tsvector('english', COALESCE(short_description, '')), 'B') ||
+        setweight(to_tsvector('english', COALESCE(description, '')), 'C')
+    ) STORED,
     UNIQUE(sku, slug)
 );
 
+CREATE INDEX products_search_vector_idx ON products USING GIN (search_vector);
+
 CREATE TABLE media (
     id SERIAL PRIMARY KEY,
     src VARCHAR(512) NOT NULL,
@@ -114,6 +222,22 @@ CREATE TABLE product_categories (
     UNIQUE(product_id, category_id)
 );
 
+CREATE TABLE tags (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    slug VARCHAR(255) NOT NULL UNIQUE,
+    description VARCHAR(512) NOT NULL DEFAULT ''
+);
+
+-- A product can have multiple tags
+CREATE TABLE product_tags (
+    product_id INT REFERENCES products(id) ON DELETE CASCADE,
+    tag_id INT REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, tag_id)
+);
+
+CREATE INDEX product_tags_tag_id_idx ON product_tags (tag_id);
+
 CREATE TABLE product_media (
     product_id INT REFERENCES products(id) ON DELETE CASCADE,
     media_id INT REFERENCES media(id) ON DELETE CASCADE,
@@ -121,6 +245,80 @@ CREATE TABLE product_media (
     PRIMARY KEY (product_id, media_id)
 );
 
+-- Attributes a product varies by, e.g. Size: S | M | L
+CREATE TABLE product_attributes (
+    id SERIAL PRIMARY KEY,
+    product_id INT NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    options JSONB NOT NULL DEFAULT '[]', -- ["S", "M", "L"]
+    position INTEGER NOT NULL DEFAULT 0,
+    UNIQUE(product_id, name)
+);
+
+-- One purchasable combination of the attributes of a variable product
+CREATE TABLE product_variations (
+    id SERIAL PRIMARY KEY,
+    product_id INT NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    sku VARCHAR(255) NOT NULL DEFAULT '',
+    attributes JSONB NOT NULL DEFAULT '{}', -- {"Size": "M", "Colour": "Red"}
+    price NUMERIC(10, 2) NOT NULL DEFAULT 0.00,
+    regular_price NUMERIC(10, 2) NOT NULL DEFAULT 0.00,
+    sale_price NUMERIC(10, 2) NOT NULL DEFAULT 0.00,
+    on_sale BOOLEAN NOT NULL DEFAULT FALSE,
+    stock_quantity INT NOT NULL DEFAULT 0,
+    stock_status stock_status NOT NULL DEFAULT 'outofstock',
+    weight INT NOT NULL DEFAULT 0,
+    media_id INT NOT NULL DEFAULT 0, -- 0 uses the product image
+    position INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX product_variations_product_id_idx ON product_variations (product_id);
+
+-- The products sold together on the page of a grouped product
+CREATE TABLE grouped_products (
+    product_id INT REFERENCES products(id) ON DELETE CASCADE,
+    child_id INT REFERENCES products(id) ON DELETE CASCADE,
+    position INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (product_id, child_id)
+);
+
+-- Upsells are offered on the page of the product, cross-sells in the cart
+CREATE TABLE linked_products (
+    product_id INT REFERENCES products(id) ON DELETE CASCADE,
+    linked_id INT REFERENCES products(id) ON DELETE CASCADE,
+    link_type link_type NOT NULL,
+    position INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (product_id, linked_id, link_type)
+);
+
+-- A snapshot of the product as the edit form shows it, taken at each save
+CREATE TABLE product_revisions (
+    id SERIAL PRIMARY KEY,
+    product_id INT NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    actor_id INTEGER, -- NULL when the product was not saved from the backend
+    actor VARCHAR(255) NOT NULL DEFAULT 'system', -- username at the time of the save
+    snapshot JSONB NOT NULL, -- fields, categories, tags, linked products and images in order
+    date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX product_revisions_product_id_idx ON product_revisions (product_id);
+
+-- Customer reviews, the approved ones make the rating of the product
+CREATE TABLE reviews (
+    id SERIAL PRIMARY KEY,
+    product_id INT NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    author VARCHAR(255) NOT NULL,
+    rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    content TEXT NOT NULL DEFAULT '',
+    status review_status NOT NULL DEFAULT 'pending',
+    verified BOOLEAN NOT NULL DEFAULT FALSE, -- the author bought the product
+    date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (product_id, user_id)
+);
+
+CREATE INDEX reviews_product_id_idx ON reviews (product_id, status);
+
 CREATE TABLE dimentions (
     product_id INT REFERENCES products(id) ON DELETE CASCADE,
     length INTEGER,
@@ -179,6 +377,7 @@ CREATE TABLE customer_orders (
 CREATE TABLE orders (
     id SERIAL PRIMARY KEY,
     order_key VARCHAR(255) NOT NULL,
+    customer_id INTEGER NOT NULL DEFAULT 0, -- 0 for guest orders
     date_created TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     date_modified TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     customer_ip_address VARCHAR(255) NOT NULL,
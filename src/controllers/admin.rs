@@ -26,7 +26,7 @@ pub async fn sidebar(
 }
 
 pub async fn dashboard(
-    auth::RequireAuth { role }: auth::RequireAuth,
+    auth::RequireAuth { role, .. }: auth::RequireAuth,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
     println!("Role: {:?}", role);
@@ -1,21 +1,208 @@
 //
-// Last Modification: 2024-08-09 22:39:55
+// Last Modification: 2026-10-18 11:31:08
 //
 
+use crate::models::audit;
 use crate::models::users;
 use crate::models::tokens;
+use crate::models::login_attempts;
+use crate::models::settings;
+use crate::models::two_factor;
+use crate::notifications;
 use anyhow::Result;
 
 use axum::{
-    extract::{Extension, FromRequestParts, Query},
-    http::{request::Parts, HeaderMap, HeaderValue, StatusCode},
-    response::Html,
+    extract::{ConnectInfo, Extension, Form, FromRequestParts, Query, Request, State},
+    http::{header::{LOCATION, SET_COOKIE}, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
 };
 
-use tera::{Tera, Context};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use tera::{Tera, Context, Function, Value};
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr};
 
+tokio::task_local! {
+    static CURRENT_ROLE: users::UserRoles;
+}
+
+// Credentials checked, waiting for the second factor
+const PENDING_LOGIN_KEY: &str = "pending_login";
+const PENDING_LOGIN_MINUTES: i64 = 5;
+const MAX_USER_AGENT_LENGTH: usize = 512;
+// Secret shown during enrolment, stored once the first code is confirmed
+const ENROLMENT_SECRET_KEY: &str = "totp_enrolment_secret";
+
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    user_id: i32,
+    username: String,
+    role: users::UserRoles,
+    expires: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorForm {
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordForm {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    token: String,
+    password: String,
+    confirm_password: String,
+}
+
+// Forwarded headers are only trusted from a reverse proxy on the same host,
+// otherwise any client could pick the address used for throttling
+pub fn client_ip(headers: &HeaderMap, peer: &SocketAddr) -> String {
+    if peer.ip().is_loopback() {
+        let forwarded = headers
+            .get("X-Real-IP")
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| {
+                headers
+                    .get("X-Forwarded-For")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.split(',').next_back())
+            })
+            .and_then(|value| value.trim().parse::<IpAddr>().ok());
+
+        if let Some(ip) = forwarded {
+            return ip.to_string();
+        }
+    }
+
+    peer.ip().to_string()
+}
+
+// Shown in the session list to tell the devices apart
+pub fn user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_USER_AGENT_LENGTH)
+        .collect()
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    let cookie_str = headers
+        .get(axum::http::header::COOKIE)
+        .and_then(|value| value.to_str().ok())?;
+
+    cookie_str
+        .split(';')
+        .filter_map(|cookie| cookie.split_once('='))
+        .find(|(key, _)| key.trim() == "token")
+        .map(|(_, value)| value.trim().to_string())
+}
+
+pub fn token_cookie(token: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!("token={}; Path=/; HttpOnly; SameSite=Lax", token)).unwrap()
+}
+
+// Creates the login token, staff goes to the backend and customers to their account
+pub async fn sign_in(
+    pool: sqlx::Pool<sqlx::Postgres>,
+    user_id: &i32,
+    role: &users::UserRoles,
+    request_headers: &HeaderMap,
+    client_ip: &str) -> Result<HeaderMap, anyhow::Error> {
+
+    let tokens_manager = tokens::Tokens::new(pool);
+    let token = tokens_manager.add(user_id, &user_agent(request_headers), client_ip).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, token_cookie(&token));
+    headers.insert(LOCATION, HeaderValue::from_static(if role.is_staff() { "/admin" } else { "/my-account" }));
+
+    Ok(headers)
+}
+
+// Users who enabled it, and admins when the store requires it
+pub async fn requires_two_factor(pool: sqlx::Pool<sqlx::Postgres>, user_id: &i32, role: &users::UserRoles) -> Result<bool, anyhow::Error> {
+    let two_factor_manager = two_factor::TwoFactor::new(pool.clone());
+    if two_factor_manager.is_enabled(user_id).await? {
+        return Ok(true);
+    }
+
+    if *role == users::UserRoles::Admin {
+        let settings_manager = settings::Settings::new(pool);
+        return Ok(settings_manager.get().await?.require_admin_2fa);
+    }
+
+    Ok(false)
+}
+
+pub async fn begin_two_factor(session: &Session, user_id: &i32, username: &str, role: &users::UserRoles) -> Result<(), anyhow::Error> {
+    let pending = PendingLogin {
+        user_id: *user_id,
+        username: username.to_string(),
+        role: role.clone(),
+        expires: Utc::now().naive_utc() + Duration::minutes(PENDING_LOGIN_MINUTES),
+    };
+
+    session.insert(PENDING_LOGIN_KEY, &pending).await?;
+    Ok(())
+}
+
+async fn pending_login(session: &Session) -> Option<PendingLogin> {
+    match session.get::<PendingLogin>(PENDING_LOGIN_KEY).await {
+        Ok(Some(pending)) if pending.expires > Utc::now().naive_utc() => Some(pending),
+        Ok(Some(_)) => {
+            if let Err(e) = session.remove::<PendingLogin>(PENDING_LOGIN_KEY).await {
+                eprintln!("Error: {}", e);
+            }
+            None
+        },
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            None
+        },
+    }
+}
+
+// The same secret is kept until the enrolment is confirmed, so reloading
+// the page does not invalidate an already scanned QR code
+pub async fn enrolment_secret(session: &Session) -> Result<String, anyhow::Error> {
+    if let Some(secret) = session.get::<String>(ENROLMENT_SECRET_KEY).await? {
+        return Ok(secret);
+    }
+
+    let secret = two_factor::generate_secret();
+    session.insert(ENROLMENT_SECRET_KEY, &secret).await?;
+    Ok(secret)
+}
+
+pub async fn clear_enrolment_secret(session: &Session) -> Result<(), anyhow::Error> {
+    session.remove::<String>(ENROLMENT_SECRET_KEY).await?;
+    Ok(())
+}
+
+pub fn insert_enrolment(data: &mut Context, account: &str, secret: &str) -> Result<(), anyhow::Error> {
+    let uri = two_factor::otpauth_uri(account, secret);
+    data.insert("qr_code", &two_factor::qr_svg(&uri)?);
+    data.insert("otpauth_uri", &uri);
+    data.insert("secret", secret);
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct RequireAuth {
+    pub session_id: i32, // the id of the token, to mark the current device
+    pub user_id: i32,
     pub role: users::UserRoles,
 }
 
@@ -33,38 +220,26 @@ where
         //     .await
         //     .expect("Missing session");
 
+        // Already checked by an outer layer of the same request
+        if let Some(auth) = parts.extensions.get::<Self>() {
+            return Ok(auth.clone());
+        }
+
         let Extension(pool) = Extension::<sqlx::Pool<sqlx::Postgres>>::from_request_parts(parts, state)
             .await
             .expect("Missing PgPool");
 
-        println!("parts {:?}", parts);
-        // println!("headers {:?}", parts.headers);
-
-        let mut cookies = HashMap::new();
-
-        if let Some(cookie_str) = parts.headers
-            .get(axum::http::header::COOKIE)
-            .and_then(|value| value.to_str().ok()) {
-
-            println!("Cookie {:?}", cookie_str);
-
-            for cookie in cookie_str.split("; ") {
-                let mut parts = cookie.split('=');
-                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
-                    cookies.insert(key.trim().to_string(), value.trim().to_string());
-                }
-            }
-        }
-
-        if let Some(token) = cookies.get("token") {
-            // println!("token: {}", token);
-
+        if let Some(token) = cookie_token(&parts.headers) {
             let token_manager = tokens::Tokens::new(pool);
             match token_manager.is_valid(&token).await {
-                Ok(role) => {
-                    return Ok(Self {
+                Ok((session_id, user_id, role)) => {
+                    let auth = Self {
+                        session_id,
+                        user_id,
                         role,
-                    });
+                    };
+                    parts.extensions.insert(auth.clone());
+                    return Ok(auth);
                 },
                 Err(err) => println!("error: {:?}", err),
             };
@@ -82,19 +257,358 @@ where
     }
 }
 
+pub fn forbidden(tera: &Tera, message: &str) -> Response {
+    let mut data = Context::new();
+    data.insert("title", "Forbidden");
+    data.insert("message", message);
+
+    let rendered = match tera.render("403.html", &data) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            "Forbidden".to_string()
+        },
+    };
+
+    (StatusCode::FORBIDDEN, Html(rendered)).into_response()
+}
+
+// Route layer for the admin router, e.g.:
+// .route_layer(from_fn_with_state(users::Capability::ManageOrders, auth::require_capability))
+pub async fn require_capability(
+    State(capability): State<users::Capability>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    auth: RequireAuth,
+    Extension(tera): Extension<Tera>,
+    request: Request,
+    next: Next,
+) -> Response {
+
+    if !auth.role.can(capability) {
+        eprintln!("User {} ({:?}) lacks {:?} for {}", auth.user_id, auth.role, capability, request.uri());
+        return forbidden(&tera, "Your account does not have permission to access this page.");
+    }
+
+    // Changes made while handling the request are recorded in the audit log under this user
+    let actor = audit::Actor {
+        user_id: auth.user_id,
+        ip_address: client_ip(request.headers(), &peer),
+    };
+
+    audit::ACTOR.scope(actor, CURRENT_ROLE.scope(auth.role, next.run(request))).await
+}
+
+// Tera function used to hide what the current user can not access:
+// {% if can(capability="manage_orders") %}...{% endif %}
+pub fn make_can() -> impl Function {
+    Box::new(move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let capability = match args.get("capability").and_then(|value| value.as_str()) {
+            Some(name) => users::Capability::from_str(name)
+                .map_err(|_| tera::Error::msg(format!("Unknown capability: {}", name)))?,
+            None => return Err(tera::Error::msg("The capability argument is required")),
+        };
+
+        let allowed = CURRENT_ROLE
+            .try_with(|role| role.can(capability))
+            .unwrap_or(false);
+
+        Ok(Value::Bool(allowed))
+    })
+}
 
 pub async fn login(
     Query(parameters): Query<HashMap<String, String>>,
+    request_headers: HeaderMap,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> (HeaderMap, Html<String>) {
 
     let mut headers = HeaderMap::new();
-    if parameters.contains_key("action") && parameters.get("action").unwrap() == "logout" {
+    if parameters.get("action").is_some_and(|action| action == "logout") {
+        // The cookie alone is not enough, the token must stop working too
+        if let Some(token) = cookie_token(&request_headers) {
+            let tokens_manager = tokens::Tokens::new(pool);
+            if let Err(e) = tokens_manager.delete(&token).await {
+                eprintln!("Error: {}", e);
+            }
+        }
+
         headers
-            .insert(axum::http::header::SET_COOKIE, HeaderValue::from_str("token=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT")
+            .insert(axum::http::header::SET_COOKIE, HeaderValue::from_str("token=; Path=/; HttpOnly; SameSite=Lax; Expires=Thu, 01 Jan 1970 00:00:00 GMT")
             .unwrap());
     }
 
     let data = Context::new();
     let rendered = tera.render("login.html", &data).unwrap();
     return (headers, Html(rendered));
+}
+
+pub async fn forgot_password_form(
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let data = Context::new();
+    let rendered = tera.render("forgot_password.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn forgot_password(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<ForgotPasswordForm>,
+) -> Html<String> {
+
+    let users_manager = users::Users::new(pool.clone());
+
+    // The same answer is given whether the email exists or not
+    match users_manager.cardentials(payload.email.trim()).await {
+        Ok(user) => {
+            let tokens_manager = tokens::Tokens::new(pool.clone());
+            match tokens_manager.add_reset(&user.user_id).await {
+                Ok(token) => {
+                    let link = match settings::Settings::new(pool.clone()).get().await
+                        .and_then(|store_settings| store_settings.link(&format!("/reset-password?token={}", token))) {
+                        Ok(link) => link,
+                        Err(e) => {
+                            eprintln!("Error sending password reset email: {}", e);
+                            return render_forgot_password(&tera);
+                        },
+                    };
+
+                    let body = format!("Someone requested a password reset for your account.\n\n\
+                        To choose a new password, open the following link within the next hour:\n\
+                        {}\n\n\
+                        If you did not request it, you can ignore this email.", link);
+
+                    let mailer = notifications::SMTP::new(pool);
+                    if let Err(e) = mailer.send(payload.email.trim(), "Password reset", &body).await {
+                        eprintln!("Error sending password reset email: {}", e);
+                    }
+                },
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        },
+        Err(e) => println!("Password reset for {}: {}", payload.email, e),
+    }
+
+    render_forgot_password(&tera)
+}
+
+fn render_forgot_password(tera: &Tera) -> Html<String> {
+    let mut data = Context::new();
+    data.insert("alert", "If the email is registered, a link to reset the password has been sent.");
+
+    let rendered = tera.render("forgot_password.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn reset_password_form(
+    Query(parameters): Query<HashMap<String, String>>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let token = parameters.get("token").cloned().unwrap_or_default();
+
+    let tokens_manager = tokens::Tokens::new(pool);
+
+    let mut data = Context::new();
+    match tokens_manager.is_valid_reset(&token).await {
+        Ok(_) => data.insert("token", &token),
+        Err(e) => data.insert("alert", &e.to_string()),
+    };
+
+    let rendered = tera.render("reset_password.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn reset_password(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<ResetPasswordForm>,
+) -> Html<String> {
+
+    let mut data = Context::new();
+
+    if payload.password.chars().count() < 8 || payload.password != payload.confirm_password {
+        data.insert("token", &payload.token);
+        data.insert("alert", if payload.password.chars().count() < 8 {
+            "The password must have at least 8 characters"
+        } else {
+            "The passwords do not match"
+        });
+
+        let rendered = tera.render("reset_password.html", &data).unwrap();
+        return Html(rendered);
+    }
+
+    let tokens_manager = tokens::Tokens::new(pool.clone());
+    let user_id = match tokens_manager.use_reset(&payload.token).await {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            data.insert("alert", &e.to_string());
+            let rendered = tera.render("reset_password.html", &data).unwrap();
+            return Html(rendered);
+        },
+    };
+
+    let users_manager = users::Users::new(pool);
+    if let Err(e) = users_manager.update_password(&user_id, &payload.password).await {
+        eprintln!("Error: {}", e);
+        return Html("An error happened while updating the password".to_string());
+    }
+
+    // Sign out every device that was logged in with the old password
+    if let Err(e) = tokens_manager.delete_all(&user_id).await {
+        eprintln!("Error: {}", e);
+    }
+
+    data.insert("alert", "Your password has been reset, you can now log in.");
+    let rendered = tera.render("login.html", &data).unwrap();
+    Html(rendered)
+}
+
+// Enabled users are asked for a code, admins forced by the store setting
+// without an authenticator enrol first
+async fn two_factor_context(
+    session: &Session,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    pending: &PendingLogin) -> Result<Context, anyhow::Error> {
+
+    let two_factor_manager = two_factor::TwoFactor::new(pool);
+
+    let mut data = Context::new();
+    if two_factor_manager.is_enabled(&pending.user_id).await? {
+        data.insert("mode", "verify");
+    } else {
+        let secret = enrolment_secret(session).await?;
+        insert_enrolment(&mut data, &pending.username, &secret)?;
+        data.insert("mode", "setup");
+    }
+
+    Ok(data)
+}
+
+pub async fn two_factor_form(
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Result<Html<String>, Redirect> {
+
+    let pending = pending_login(&session).await.ok_or(Redirect::to("/login"))?;
+
+    let data = match two_factor_context(&session, pool, &pending).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while preparing two-factor authentication".to_string()));
+        },
+    };
+
+    let rendered = tera.render("two_factor.html", &data).unwrap();
+    Ok(Html(rendered))
+}
+
+pub async fn two_factor(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<TwoFactorForm>,
+) -> Response {
+
+    let pending = match pending_login(&session).await {
+        Some(pending) => pending,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let client_ip = client_ip(&request_headers, &peer);
+    let attempts_manager = login_attempts::LoginAttempts::new(pool.clone());
+
+    let mut data = match two_factor_context(&session, pool.clone(), &pending).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while preparing two-factor authentication".to_string()).into_response();
+        },
+    };
+
+    // Codes are throttled like passwords, six digits do not take long to guess.
+    // The attempt is counted as a failure until the code is found right.
+    match attempts_manager.attempt(&pending.username, &client_ip).await {
+        Ok(Some(_)) => {
+            data.insert("alert", "Too many failed attempts. Please try again later.");
+            let rendered = tera.render("two_factor.html", &data).unwrap();
+            return (StatusCode::TOO_MANY_REQUESTS, Html(rendered)).into_response();
+        },
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check login attempts.").into_response();
+        },
+    }
+
+    let two_factor_manager = two_factor::TwoFactor::new(pool.clone());
+
+    let verified = match data.get("secret").and_then(|secret| secret.as_str()) {
+        // Forced enrolment, the first valid code activates the authenticator
+        Some(secret) => {
+            match two_factor::verify_code(secret, &payload.code) {
+                Some(step) => two_factor_manager.enable(&pending.user_id, secret, step).await.map(Some),
+                None => Ok(None),
+            }
+        },
+        None => two_factor_manager
+            .verify(&pending.user_id, &payload.code)
+            .await
+            .map(|valid| if valid { Some(Vec::new()) } else { None }),
+    };
+
+    let recovery_codes = match verified {
+        Ok(Some(codes)) => codes,
+        Ok(None) => {
+            if let Err(e) = attempts_manager.failure(&pending.username, &client_ip).await {
+                eprintln!("Error: {}", e);
+            }
+
+            data.insert("alert", "Invalid authentication code");
+            let rendered = tera.render("two_factor.html", &data).unwrap();
+            return Html(rendered).into_response();
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while verifying the code".to_string()).into_response();
+        },
+    };
+
+    if let Err(e) = attempts_manager.success(&pending.username, &client_ip).await {
+        eprintln!("Error: {}", e);
+    }
+
+    if let Err(e) = session.remove::<PendingLogin>(PENDING_LOGIN_KEY).await {
+        eprintln!("Error: {}", e);
+    }
+    if let Err(e) = clear_enrolment_secret(&session).await {
+        eprintln!("Error: {}", e);
+    }
+
+    let mut headers = match sign_in(pool, &pending.user_id, &pending.role, &request_headers, &client_ip).await {
+        Ok(headers) => headers,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token.").into_response();
+        },
+    };
+
+    if recovery_codes.is_empty() {
+        return (StatusCode::FOUND, headers).into_response();
+    }
+
+    // Shown once, the user continues to the page sign_in would redirect to
+    let location = headers.remove(LOCATION);
+
+    let mut data = Context::new();
+    data.insert("mode", "recovery_codes");
+    data.insert("recovery_codes", &recovery_codes);
+    data.insert("continue", location.as_ref().and_then(|value| value.to_str().ok()).unwrap_or("/"));
+
+    let rendered = tera.render("two_factor.html", &data).unwrap();
+    (StatusCode::OK, headers, Html(rendered)).into_response()
 }
\ No newline at end of file
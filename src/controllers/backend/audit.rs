@@ -0,0 +1,56 @@
+//
+// Description: Audit log of back-office changes
+// Last Modification: 2026-10-18 19:31:12
+//
+
+use crate::models::audit;
+
+use axum::{
+    extract::{Extension, Query},
+    response::Html,
+};
+
+use tera::{
+    Tera,
+    Context
+};
+
+pub async fn list(
+    Query(parameters): Query<audit::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Html<String> {
+
+    let audit_manager = audit::Audit::new(pool);
+
+    let page = match audit_manager.get_page(&parameters).await {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the audit log".to_string());
+        },
+    };
+
+    let actions = match audit_manager.actions().await {
+        Ok(actions) => actions,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the audit log".to_string());
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "audit");
+    data.insert("title", "Audit Log");
+    data.insert("entries", &page.entries);
+    data.insert("actions", &actions);
+    data.insert("filters", &parameters);
+    data.insert("query", &parameters.filters_query());
+    data.insert("current_page", &page.current_page);
+    data.insert("total_entries", &page.total_count);
+    data.insert("per_page", &page.per_page);
+    data.insert("total_pages", &page.total_pages);
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
\ No newline at end of file
@@ -1,10 +1,17 @@
 //
 // Description: Backend Modules
-// Last Modification: 2024-07-24 17:38:57
+// Last Modification: 2026-10-18 11:02:13
 //
 
+pub mod audit;
 pub mod categories;
 pub mod media;
 pub mod orders;
 pub mod products;
-pub mod users;
\ No newline at end of file
+pub mod product_csv;
+pub mod reviews;
+pub mod revisions;
+pub mod settings;
+pub mod tags;
+pub mod users;
+pub mod variations;
\ No newline at end of file
@@ -1,6 +1,6 @@
 //
 // Description: List all orders
-// Last Modification: 2024-08-30 19:36:22
+// Last Modification: 2026-10-18 10:00:58
 //
 
 use crate::models::orders;
@@ -8,10 +8,12 @@ use crate::types;
 use crate::utils;
 
 use axum::{
-    extract::{Extension, Path, Query},
+    extract::{Extension, Path, Query, RawForm},
     response::Html,
 };
 
+use strum::IntoEnumIterator;
+
 use tera::{
     Tera,
     Context
@@ -24,12 +26,101 @@ pub async fn new(
     Html("Add new order unimplemented".to_string())
 }
 
+fn render_order(tera: &Tera, order: &orders::OrderDetails, alert: Option<&str>) -> Html<String> {
+    let statuses: Vec<(&str, &str)> = orders::OrderStatus::iter()
+        .map(|status| (status.as_str(), status.label()))
+        .collect();
+
+    let mut data = Context::new();
+    data.insert("partial", "order");
+    data.insert("title", &format!("Order #{}", order.id));
+    data.insert("order", order);
+    data.insert("status", order.order.status.as_str());
+    data.insert("statuses", &statuses);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
 pub async fn edit(
     Path(id):Path<i32>,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
-    Html("Edit order unimplemented".to_string())
+    let orders_manager = orders::Orders::new(pool);
+
+    match orders_manager.get(id).await {
+        Ok(order) => render_order(&tera, &order, None),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("An error happened while fetching the order".to_string())
+        },
+    }
+}
+
+// status=processing&quantity_0=2&quantity_1=0, a quantity per line in order
+fn parse_order_form(form: &[u8], lines: usize) -> Result<(orders::OrderStatus, Vec<i32>), String> {
+    let mut status = None;
+    let mut quantities: Vec<Option<i32>> = vec![None; lines];
+
+    for (key, value) in url::form_urlencoded::parse(form) {
+        if key == "status" {
+            status = Some(orders::OrderStatus::parse(&value)
+                .ok_or(format!("Unknown order status: {}", value))?);
+            continue;
+        }
+
+        let Some(index) = key.strip_prefix("quantity_") else {
+            continue;
+        };
+        let quantity = index.parse::<usize>().ok()
+            .and_then(|index| quantities.get_mut(index))
+            .ok_or(format!("Unknown order line: {}", index))?;
+        *quantity = Some(value.trim().parse::<i32>()
+            .ok()
+            .filter(|quantity| *quantity >= 0)
+            .ok_or(format!("Invalid quantity: {}", value))?);
+    }
+
+    let status = status.ok_or("The order status is required".to_string())?;
+    let quantities = quantities.into_iter()
+        .collect::<Option<Vec<i32>>>()
+        .ok_or("A quantity is required for every line".to_string())?;
+
+    Ok((status, quantities))
+}
+
+pub async fn update(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let orders_manager = orders::Orders::new(pool);
+
+    let order = match orders_manager.get(id).await {
+        Ok(order) => order,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the order".to_string());
+        },
+    };
+
+    let (status, quantities) = match parse_order_form(&form, order.order.line_items.len()) {
+        Ok(parsed) => parsed,
+        Err(alert) => return render_order(&tera, &order, Some(&alert)),
+    };
+
+    match orders_manager.update(id, status, &quantities).await {
+        Ok(order) => render_order(&tera, &order, Some("Order saved")),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            render_order(&tera, &order, Some(&format!("The order could not be saved: {}", e)))
+        },
+    }
 }
 
 /*
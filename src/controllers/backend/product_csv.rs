@@ -0,0 +1,98 @@
+//
+// Description: CSV import and export of the products
+// Last Modification: 2026-10-18 10:27:47
+//
+
+use crate::models::product_csv;
+
+use axum::{
+    extract::{Extension, Multipart},
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+
+use tera::{
+    Tera,
+    Context
+};
+
+fn render(tera: &Tera, report: Option<&product_csv::ImportReport>, alert: Option<&str>) -> Html<String> {
+    let mut data = Context::new();
+    data.insert("partial", "product_import");
+    data.insert("title", "Import Products");
+    data.insert("columns", &product_csv::COLUMNS);
+    if let Some(report) = report {
+        data.insert("report", report);
+    }
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn export(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Response {
+
+    match product_csv::export(&pool).await {
+        Ok(csv) => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"products.csv\""),
+            ],
+            csv,
+        ).into_response(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("An error happened while exporting the products".to_string()).into_response()
+        },
+    }
+}
+
+pub async fn import_form(
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    render(&tera, None, None)
+}
+
+pub async fn import(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    mut multipart: Multipart) -> Html<String> {
+
+    let mut text = None;
+    let mut dry_run = false;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name().unwrap_or_default() {
+            "file" => {
+                text = match field.bytes().await {
+                    Ok(bytes) => match String::from_utf8(bytes.to_vec()) {
+                        Ok(text) => Some(text),
+                        Err(_) => return render(&tera, None, Some("The file is not UTF-8 text")),
+                    },
+                    Err(e) => {
+                        eprintln!("Error reading the CSV file: {}", e);
+                        return render(&tera, None, Some("An error occurred while reading the file"));
+                    },
+                };
+            },
+            "dry_run" => dry_run = true,
+            _ => {},
+        }
+    }
+
+    let text = match text {
+        Some(text) if !text.trim().is_empty() => text,
+        _ => return render(&tera, None, Some("Choose a CSV file to import")),
+    };
+
+    match product_csv::ProductCsv::new(pool, dry_run).import(&text).await {
+        Ok(report) => render(&tera, Some(&report), None),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            render(&tera, None, Some(&format!("The file can not be imported: {}", e)))
+        },
+    }
+}
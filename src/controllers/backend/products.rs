@@ -1,10 +1,11 @@
 //
-// Last Modification: 2024-08-30 19:35:58
+// Last Modification: 2026-10-18 10:56:58
 //
 
 use crate::models;
 use crate::models::products;
 use crate::models::categories;
+use crate::models::tags;
 
 use anyhow;
 use slug::slugify;
@@ -19,11 +20,11 @@ use std::{
 
 use strum::IntoEnumIterator;
 
-use chrono::Local;
+use chrono::{Local, NaiveDateTime};
 
 use axum::{
-    extract::{Extension, Query, Path, Multipart},
-    response::Html,
+    extract::{Extension, Query, Path, Multipart, RawForm},
+    response::{Html, Redirect},
 };
 
 use tera::{
@@ -55,6 +56,17 @@ fn generate_unique_filename(dir: &std::path::Path, filename: &str) -> PathBuf {
     path
 }
 
+// "2024-08-20T09:30" from a datetime-local input, empty for no date
+fn parse_datetime(value: &str) -> Result<Option<NaiveDateTime>, chrono::ParseError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .map(Some)
+}
+
 fn upload_image(data: &axum::body::Bytes, filename: &str) -> Result<PathBuf, anyhow::Error> {
     // Implementation to upload images
 
@@ -125,14 +137,24 @@ pub async fn handle(
         price: 0.00,
         sale_price: 0.00,
         on_sale: false,
+        date_on_sale_from: None,
+        date_on_sale_to: None,
         stock_status: products::StockStatus::OutOfStock,
         stock_quantity: 0,
         weight: 0,
         permalink: "".to_string(),
         status: products::Status::Draft,
+        product_type: products::ProductType::Simple,
+        catalog_visibility: products::CatalogVisibility::Visible,
+        external_url: "".to_string(),
+        button_text: "".to_string(),
+        grouped_products: vec![],
+        upsells: vec![],
+        cross_sells: vec![],
         primary_category: 0,
         images: vec![],
         categories: vec![],
+        tags: vec![],
     };
 
     let mut delete_media = false;
@@ -231,6 +253,26 @@ pub async fn handle(
                     }
                 }
             },
+            "date_on_sale_from" | "date_on_sale_to" => {
+                let date = match field.text().await {
+                    Ok(value) => match parse_datetime(&value) {
+                        Ok(date) => date,
+                        Err(e) => {
+                            eprintln!("Error parsing the product field \"{}\": {}", field_name, e);
+                            return Html(format!("The sale date {:?} is not valid", value));
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error parsing the product field \"{}\": {}", field_name, e);
+                        return Html(format!("An error occurred while parsing the product field \"{}\"", field_name));
+                    }
+                };
+                if field_name == "date_on_sale_from" {
+                    product.date_on_sale_from = date;
+                } else {
+                    product.date_on_sale_to = date;
+                }
+            },
             "stock_quantity" => {
                 product.stock_quantity = match field.text().await {
                     Ok(value) => value.parse().expect("Failed to parse the string \"stock_quantity\" to u32"),
@@ -257,6 +299,7 @@ pub async fn handle(
                             "draft" => products::Status::Draft,
                             "pending" => products::Status::Pending,
                             "private" => products::Status::Private,
+                            "trash" => products::Status::Trash,
                             _ => products::Status::Draft,
                         }
                     },
@@ -266,6 +309,97 @@ pub async fn handle(
                     }
                 }
             },
+            "type" => {
+                product.product_type = match field.text().await {
+                    Ok(value) => {
+                        products::ProductType::iter()
+                            .find(|product_type| product_type.as_str() == value)
+                            .unwrap_or(products::ProductType::Simple)
+                    },
+                    Err(e) => {
+                        eprintln!("Error parsing product Type: {}", e);
+                        return Html("An error occurred while parsing product Type".to_string());
+                    }
+                }
+            },
+            "catalog_visibility" => {
+                product.catalog_visibility = match field.text().await {
+                    Ok(value) => {
+                        products::CatalogVisibility::iter()
+                            .find(|visibility| visibility.as_str() == value)
+                            .unwrap_or(products::CatalogVisibility::Visible)
+                    },
+                    Err(e) => {
+                        eprintln!("Error parsing product Catalog Visibility: {}", e);
+                        return Html("An error occurred while parsing product Catalog Visibility".to_string());
+                    }
+                }
+            },
+            "external_url" => {
+                product.external_url = match field.text().await {
+                    Ok(value) => value.trim().to_string(),
+                    Err(e) => {
+                        eprintln!("Error parsing product External URL: {}", e);
+                        return Html("An error occurred while parsing product External URL".to_string());
+                    }
+                }
+            },
+            "button_text" => {
+                product.button_text = match field.text().await {
+                    Ok(value) => value.trim().to_string(),
+                    Err(e) => {
+                        eprintln!("Error parsing product Button Text: {}", e);
+                        return Html("An error occurred while parsing product Button Text".to_string());
+                    }
+                }
+            },
+            "tags" => {
+                // "summer, cotton", unknown tags are created
+                product.tags = match field.text().await {
+                    Ok(value) => tags::parse_names(&value),
+                    Err(e) => {
+                        eprintln!("Error parsing product Tags: {}", e);
+                        return Html("An error occurred while parsing product Tags".to_string());
+                    }
+                }
+            },
+            "grouped_products" => {
+                // "12, 14, 15", the products of a grouped product
+                product.grouped_products = match field.text().await {
+                    Ok(value) => match products::parse_ids(&value) {
+                        Ok(ids) => ids,
+                        Err(e) => {
+                            eprintln!("Error parsing product Grouped Products: {}", e);
+                            return Html(format!("The grouped products are not valid: {}", e));
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error parsing product Grouped Products: {}", e);
+                        return Html("An error occurred while parsing product Grouped Products".to_string());
+                    }
+                }
+            },
+            "upsells" | "cross_sells" => {
+                // "12, 14, 15", the linked products in display order
+                let ids = match field.text().await {
+                    Ok(value) => match products::parse_ids(&value) {
+                        Ok(ids) => ids,
+                        Err(e) => {
+                            eprintln!("Error parsing product Linked Products: {}", e);
+                            return Html(format!("The linked products are not valid: {}", e));
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error parsing product Linked Products: {}", e);
+                        return Html("An error occurred while parsing product Linked Products".to_string());
+                    }
+                };
+                if field_name == "upsells" {
+                    product.upsells = ids;
+                } else {
+                    product.cross_sells = ids;
+                }
+            },
             "category_id" => {
                 // Category id
                 let category_id: i32 = match field.text().await {
@@ -455,53 +589,60 @@ pub async fn handle(
         product.categories.push(category_id);
     }
 
+    // The categories are saved with the product by add and update
     product.primary_category = 0;
-    if !product.categories.is_empty() {
-        match products_manager.backend().delete_categories(product.id).await {
-            Ok(()) => {
-                println!("Product categories deleted successfully for product id {}", product.id);
-            },
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                return Html("An error occurred while delete categories from product.".to_string());
-            }
-        };
-        for id in &product.categories {
-            match products_manager.backend().add_category(id, &product.id).await {
-                Ok(()) => {
-                    println!("Category {} added to product successfully", id);
-                },
-                Err(e) => {
-                    eprintln!("Error: {}", e);
-                    return Html("An error occurred while adding category to product.".to_string());
-                }
-            };
-            if primary_category == *id {
-                product.primary_category = primary_category;
-            }
+    if product.categories.contains(&primary_category) {
+        product.primary_category = primary_category;
+    }
+
+    if let (Some(from), Some(to)) = (product.date_on_sale_from, product.date_on_sale_to) {
+        if to <= from {
+            return Html("The sale must end after it starts".to_string());
         }
     }
 
+    // A scheduled sale is stored now and started by products::schedule_sales
     product.price = product.regular_price;
     if sale_price < 0.00 {
         product.sale_price = 0.00;
         product.on_sale = false;
     } else if sale_price < product.regular_price {
         product.sale_price = sale_price;
-        product.on_sale = true;
-        product.price = sale_price;
+        product.on_sale = match products_manager.backend()
+            .sale_is_active(product.date_on_sale_from, product.date_on_sale_to)
+            .await {
+            Ok(active) => active,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Html("An error happened while checking the sale dates".to_string());
+            },
+        };
+        if product.on_sale {
+            product.price = sale_price;
+        }
     }
 
     if product.slug.is_empty() {
         product.slug = slugify(&product.name);
     }
 
-    product.stock_status = if product.stock_quantity == 0 {
+    // External products are not sold here, grouped products take the stock of their products
+    let has_stock = matches!(product.product_type, products::ProductType::Simple | products::ProductType::Variable);
+    product.stock_status = if product.stock_quantity == 0 && has_stock {
         products::StockStatus::OutOfStock
     } else {
         products::StockStatus::InStock
     };
 
+    if product.product_type == products::ProductType::External {
+        let is_web_address = url::Url::parse(&product.external_url)
+            .map(|url| url.scheme() == "http" || url.scheme() == "https")
+            .unwrap_or(false);
+        if !is_web_address {
+            return Html("The product URL of an external product must be an http or https address.".to_string());
+        }
+    }
+
     product.permalink = format!("/product/{}", product.slug);
 
     println!("Product: {:?}", product);
@@ -512,6 +653,16 @@ pub async fn handle(
         status_names.push(status.as_str().to_string());
     }
 
+    let mut type_names = vec![];
+    for product_type in products::ProductType::iter() {
+        type_names.push(product_type.as_str().to_string());
+    }
+
+    let mut visibility_names = vec![];
+    for visibility in products::CatalogVisibility::iter() {
+        visibility_names.push(visibility.as_str().to_string());
+    }
+
     let categories = match categories_manager.backend().get_tree().await {
         Ok(c) => c,
         Err(e) => {
@@ -534,6 +685,8 @@ pub async fn handle(
                 data.insert("categories", &categories);
                 data.insert("alert", "Product added");
                 data.insert("status", &status_names);
+                data.insert("types", &type_names);
+                data.insert("visibilities", &visibility_names);
                 let rendered = tera.render("backend/admin.html", &data).unwrap();
                 return Html(rendered);
             },
@@ -553,6 +706,8 @@ pub async fn handle(
                 data.insert("categories", &categories);
                 data.insert("alert", "Product updated");
                 data.insert("status", &status_names);
+                data.insert("types", &type_names);
+                data.insert("visibilities", &visibility_names);
                 let rendered = tera.render("backend/admin.html", &data).unwrap();
                 return Html(rendered);
             },
@@ -586,11 +741,26 @@ pub async fn edit(
         
             println!("Categories: {:?}", categories);
 
+            // A product is moved to the trash from the list, only a
+            // product of the trash keeps that status when it is saved
             let mut status_names = vec![];
             for status in products::Status::iter() {
+                if status == products::Status::Trash && product.status != products::Status::Trash {
+                    continue;
+                }
                 status_names.push(status.as_str().to_string());
             }
 
+            let mut type_names = vec![];
+            for product_type in products::ProductType::iter() {
+                type_names.push(product_type.as_str().to_string());
+            }
+
+            let mut visibility_names = vec![];
+            for visibility in products::CatalogVisibility::iter() {
+                visibility_names.push(visibility.as_str().to_string());
+            }
+
             println!("Product: {:?}", product);
             
             let mut data = Context::new();
@@ -599,6 +769,8 @@ pub async fn edit(
             data.insert("product", &product);
             data.insert("categories", &categories);
             data.insert("status", &status_names);
+            data.insert("types", &type_names);
+            data.insert("visibilities", &visibility_names);
             let rendered = tera.render("backend/admin.html", &data).unwrap();
             Html(rendered)
         },
@@ -627,14 +799,24 @@ pub async fn new(
         price: 0.00,
         sale_price: 0.00,
         on_sale: false,
+        date_on_sale_from: None,
+        date_on_sale_to: None,
         stock_status: products::StockStatus::OutOfStock,
         stock_quantity: 0,
         weight: 0,
         permalink: "".to_string(),
         status: products::Status::Draft,
+        product_type: products::ProductType::Simple,
+        catalog_visibility: products::CatalogVisibility::Visible,
+        external_url: "".to_string(),
+        button_text: "".to_string(),
+        grouped_products: vec![],
+        upsells: vec![],
+        cross_sells: vec![],
         primary_category: 0,
         images: vec![],
         categories: vec![],
+        tags: vec![],
     };
 
     let categories_manager = categories::Categories::new(pool);
@@ -651,25 +833,38 @@ pub async fn new(
         status_names.push(status.as_str().to_string());
     }
 
+    let mut type_names = vec![];
+    for product_type in products::ProductType::iter() {
+        type_names.push(product_type.as_str().to_string());
+    }
+
+    let mut visibility_names = vec![];
+    for visibility in products::CatalogVisibility::iter() {
+        visibility_names.push(visibility.as_str().to_string());
+    }
+
     let mut data = Context::new();
     data.insert("partial", "product");
     data.insert("title", "Product");
     data.insert("product", &product);
     data.insert("categories", &categories);
     data.insert("status", &status_names);
+    data.insert("types", &type_names);
+    data.insert("visibilities", &visibility_names);
     let rendered = tera.render("backend/admin.html", &data).unwrap();
     Html(rendered)
 }
 
-pub async fn list(
-    Query(parameters): Query<products::Parameters>,
-    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
-    Extension(tera): Extension<Tera>) -> Html<String> {
+async fn render_list(
+    pool: sqlx::Pool<sqlx::Postgres>,
+    tera: &Tera,
+    parameters: &products::Parameters,
+    alert: Option<&str>) -> Html<String> {
 
-    let products_manager = products::Products::new(pool);
+    let products_manager = products::Products::new(pool.clone());
 
     let page = match products_manager.backend()
-        .get_page(&parameters)
+        .get_page(parameters)
         .await {
         Ok(page) => page,
         Err(e) => {
@@ -678,6 +873,31 @@ pub async fn list(
         },
     };
 
+    let categories_manager = categories::Categories::new(pool);
+    let categories = match categories_manager.backend().get_tree().await {
+        Ok(categories) => categories,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching categories.".to_string());
+        }
+    };
+
+    let trash_count = match products_manager.backend().count_trash().await {
+        Ok(count) => count,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching products".to_string());
+        },
+    };
+
+    let statuses: Vec<String> = products::Status::iter()
+        .filter(|status| *status != products::Status::Trash)
+        .map(|status| status.as_str().to_string())
+        .collect();
+    let stock_statuses: Vec<(String, String)> = products::StockStatus::iter()
+        .map(|status| (status.as_str().to_string(), status.label().to_string()))
+        .collect();
+
     let mut data = Context::new();
     data.insert("partial", "products");
     data.insert("title", "Products");
@@ -686,7 +906,162 @@ pub async fn list(
     data.insert("total_products", &page.total_count);
     data.insert("per_page", &page.per_page);
     data.insert("total_pages", &page.total_pages);
+    data.insert("categories", &categories);
+    data.insert("statuses", &statuses);
+    data.insert("stock_statuses", &stock_statuses);
+    data.insert("trash", &(parameters.status == Some(products::Status::Trash)));
+    data.insert("trash_count", &trash_count);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
 
     let rendered = tera.render("backend/admin.html", &data).unwrap();
     Html(rendered)
-}
\ No newline at end of file
+}
+
+pub async fn list(
+    Query(parameters): Query<products::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    render_list(pool, &tera, &parameters, None).await
+}
+
+pub async fn duplicate(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Result<Redirect, Html<String>> {
+
+    let products_manager = products::Products::new(pool);
+    match products_manager.backend().duplicate(id).await {
+        Ok(copy_id) => Ok(Redirect::to(&format!("/admin/products/{}", copy_id))),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Err(Html("An error happened while duplicating the product".to_string()))
+        },
+    }
+}
+
+// product_id=3&product_id=8&action=price&percentage=-10, the fields of the
+// other actions are ignored
+fn parse_bulk_form(form: &[u8]) -> Result<(Vec<i32>, products::BulkAction), String> {
+    let mut product_ids = vec![];
+    let mut category_ids = vec![];
+    let mut fields = HashMap::new();
+
+    for (key, value) in url::form_urlencoded::parse(form) {
+        match key.as_ref() {
+            "product_id" => product_ids.push(value.parse::<i32>()
+                .map_err(|_| format!("Invalid product: {}", value))?),
+            "category_id" => category_ids.push(value.parse::<i32>()
+                .map_err(|_| format!("Invalid category: {}", value))?),
+            _ => {
+                fields.insert(key.into_owned(), value.trim().to_string());
+            },
+        }
+    }
+
+    if product_ids.is_empty() {
+        return Err("Select the products to change".to_string());
+    }
+
+    let field = |name: &str| fields.get(name).map(|value| value.as_str()).unwrap_or("");
+
+    let action = match field("action") {
+        "status" => products::BulkAction::Status(products::Status::iter()
+            .filter(|status| *status != products::Status::Trash)
+            .find(|status| status.as_str() == field("status"))
+            .ok_or(format!("Unknown product status: {}", field("status")))?),
+        "trash" => products::BulkAction::Trash,
+        "restore" => products::BulkAction::Restore,
+        "add_categories" | "remove_categories" if category_ids.is_empty() => {
+            return Err("Select a category".to_string());
+        },
+        "add_categories" => products::BulkAction::AddCategories(category_ids),
+        "remove_categories" => products::BulkAction::RemoveCategories(category_ids),
+        "stock" => {
+            let quantity = match field("stock_quantity") {
+                "" => None,
+                value => Some(value.parse::<i32>()
+                    .map_err(|_| format!("Invalid stock quantity: {}", value))?),
+            };
+            let status = match field("stock_status") {
+                "" => None,
+                value => Some(products::StockStatus::iter()
+                    .find(|status| status.as_str() == value)
+                    .ok_or(format!("Unknown stock status: {}", value))?),
+            };
+            if quantity.is_none() && status.is_none() {
+                return Err("Enter a stock quantity or a stock status".to_string());
+            }
+            products::BulkAction::Stock(quantity, status)
+        },
+        "price" => products::BulkAction::Price(field("percentage").parse::<f32>()
+            .ok()
+            .filter(|percentage| percentage.is_finite() && *percentage != 0.0)
+            .ok_or(format!("Invalid price change: {}", field("percentage")))?),
+        "" => return Err("Select an action".to_string()),
+        action => return Err(format!("Unknown action: {}", action)),
+    };
+
+    Ok((product_ids, action))
+}
+
+// The list is shown again with the page of the form, e.g. /admin/products/bulk?page=2
+pub async fn bulk_edit(
+    Query(parameters): Query<products::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let (product_ids, action) = match parse_bulk_form(&form) {
+        Ok(parsed) => parsed,
+        Err(alert) => return render_list(pool, &tera, &parameters, Some(&alert)).await,
+    };
+
+    let products_manager = products::Products::new(pool.clone());
+    let alert = match products_manager.backend().bulk_edit(&product_ids, &action).await {
+        Ok(changed) => format!("{} of {} products changed", changed, product_ids.len()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            format!("No product was changed: {}", e)
+        },
+    };
+
+    render_list(pool, &tera, &parameters, Some(&alert)).await
+}
+
+// product_id=3&product_id=8&delete_media=1, from the list of the trash
+pub async fn delete(
+    Query(parameters): Query<products::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let mut product_ids = vec![];
+    let mut delete_media = false;
+    for (key, value) in url::form_urlencoded::parse(&form) {
+        match key.as_ref() {
+            "product_id" => match value.parse::<i32>() {
+                Ok(product_id) => product_ids.push(product_id),
+                Err(_) => return render_list(pool, &tera, &parameters, Some(&format!("Invalid product: {}", value))).await,
+            },
+            "delete_media" => delete_media = true,
+            _ => {},
+        }
+    }
+
+    if product_ids.is_empty() {
+        return render_list(pool, &tera, &parameters, Some("Select the products to delete")).await;
+    }
+
+    let products_manager = products::Products::new(pool.clone());
+    let alert = match products_manager.backend().delete(&product_ids, delete_media).await {
+        Ok(deleted) => format!("{} products deleted for good", deleted),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            format!("No product was deleted: {}", e)
+        },
+    };
+
+    render_list(pool, &tera, &parameters, Some(&alert)).await
+}
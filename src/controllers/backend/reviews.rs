@@ -0,0 +1,83 @@
+//
+// Description: Moderation of the customer reviews
+// Last Modification: 2026-10-18 10:17:38
+//
+
+use crate::models::reviews;
+
+use axum::{
+    extract::{Extension, Form, Path, Query},
+    response::{Html, Redirect},
+};
+
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+
+use tera::{
+    Tera,
+    Context
+};
+
+#[derive(Deserialize)]
+pub struct StatusForm {
+    status: String,
+    filter: Option<String>, // the status listed when the form was sent
+}
+
+pub async fn list(
+    Query(parameters): Query<reviews::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Html<String> {
+
+    let reviews_manager = reviews::Reviews::new(pool);
+
+    let page = match reviews_manager.get_page(&parameters).await {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching reviews".to_string());
+        },
+    };
+
+    let statuses: Vec<(&str, &str)> = reviews::ReviewStatus::iter()
+        .map(|status| (status.as_str(), status.label()))
+        .collect();
+
+    let mut data = Context::new();
+    data.insert("partial", "reviews");
+    data.insert("title", "Reviews");
+    data.insert("reviews", &page.reviews);
+    data.insert("statuses", &statuses);
+    data.insert("status", &parameters.status.clone().unwrap_or_default());
+    data.insert("current_page", &page.current_page);
+    data.insert("total_reviews", &page.total_count);
+    data.insert("per_page", &page.per_page);
+    data.insert("total_pages", &page.total_pages);
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn update_status(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Form(payload): Form<StatusForm>) -> Result<Redirect, Html<String>> {
+
+    let status = match reviews::ReviewStatus::parse(&payload.status) {
+        Some(status) => status,
+        None => return Err(Html(format!("Unknown review status: {}", payload.status))),
+    };
+
+    let reviews_manager = reviews::Reviews::new(pool);
+    if let Err(e) = reviews_manager.set_status(id, status).await {
+        eprintln!("Error: {}", e);
+        return Err(Html("An error happened while moderating the review".to_string()));
+    }
+
+    let filter = payload.filter.unwrap_or_default();
+    Ok(Redirect::to(&match reviews::ReviewStatus::parse(&filter) {
+        Some(filter) => format!("/admin/reviews?status={}", filter.as_str()),
+        None => "/admin/reviews".to_string(),
+    }))
+}
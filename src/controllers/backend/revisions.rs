@@ -0,0 +1,99 @@
+//
+// Description: Revisions of the products, their differences and rollback
+// Last Modification: 2026-10-18 11:02:13
+//
+
+use crate::models::products;
+use crate::models::revisions;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    response::Html,
+};
+
+use tera::{
+    Tera,
+    Context
+};
+
+async fn render(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tera: &Tera,
+    product_id: i32,
+    parameters: &revisions::Parameters,
+    alert: Option<&str>) -> Html<String> {
+
+    let products_manager = products::Products::new(pool.clone());
+    let product = match products_manager.backend().get(product_id).await {
+        Ok(product) => product,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the product.".to_string());
+        }
+    };
+
+    let revisions = match revisions::Revisions::new(pool.clone()).get_all(product_id).await {
+        Ok(revisions) => revisions,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the product revisions.".to_string());
+        }
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "revisions");
+    data.insert("title", "Product Revisions");
+    data.insert("product", &product);
+
+    // Both revisions are of this product, the others are not compared
+    if let (Some(from), Some(to)) = (parameters.from, parameters.to) {
+        let from = revisions.iter().find(|revision| revision.id == from);
+        let to = revisions.iter().find(|revision| revision.id == to);
+        match (from, to) {
+            (Some(from), Some(to)) => {
+                data.insert("changes", &revisions::diff(&from.snapshot, &to.snapshot));
+                data.insert("from", &from.id);
+                data.insert("to", &to.id);
+            },
+            _ => data.insert("alert", "The revisions to compare do not exist"),
+        }
+    }
+
+    data.insert("revisions", &revisions);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn list(
+    Path(id):Path<i32>,
+    Query(parameters): Query<revisions::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    render(&pool, &tera, id, &parameters, None).await
+}
+
+pub async fn rollback(
+    Path((id, revision_id)):Path<(i32, i32)>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let products_manager = products::Products::new(pool.clone());
+    let alert = match products_manager.backend().rollback(id, revision_id).await {
+        Ok(()) => format!("The product was rolled back to the revision #{}", revision_id),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            format!("The product was not rolled back: {}", e)
+        },
+    };
+
+    let parameters = revisions::Parameters {
+        from: None,
+        to: None,
+    };
+    render(&pool, &tera, id, &parameters, Some(&alert)).await
+}
@@ -0,0 +1,89 @@
+//
+// Last Modification: 2026-10-18 10:56:58
+//
+
+use crate::models::settings;
+
+use axum::{
+    extract::{Extension, Form},
+    response::Html,
+};
+
+use serde::Deserialize;
+
+use tera::{
+    Tera,
+    Context
+};
+
+#[derive(Deserialize)]
+pub struct SettingsForm {
+    // Unchecked boxes are not sent
+    require_admin_2fa: Option<String>,
+    site_url: String,
+    trash_days: String,
+}
+
+fn render(tera: &Tera, store_settings: &settings::StoreSettings, alert: Option<&str>) -> Html<String> {
+    let mut data = Context::new();
+    data.insert("partial", "settings");
+    data.insert("title", "Settings");
+    data.insert("settings", store_settings);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn edit(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let settings_manager = settings::Settings::new(pool);
+
+    match settings_manager.get().await {
+        Ok(store_settings) => render(&tera, &store_settings, None),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("An error happened while fetching the settings".to_string())
+        },
+    }
+}
+
+pub async fn update(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<SettingsForm>) -> Html<String> {
+
+    let trash_days = payload.trash_days.trim().parse::<i32>().ok().filter(|days| *days >= 0);
+
+    let store_settings = settings::StoreSettings {
+        require_admin_2fa: payload.require_admin_2fa.is_some(),
+        site_url: payload.site_url.trim().trim_end_matches('/').to_string(),
+        trash_days: trash_days.unwrap_or(0),
+    };
+
+    if trash_days.is_none() {
+        return render(&tera, &store_settings, Some("The days in the trash must be a number, 0 or more"));
+    }
+
+    if !store_settings.site_url.is_empty() {
+        let is_web_address = url::Url::parse(&store_settings.site_url)
+            .map(|url| url.scheme() == "http" || url.scheme() == "https")
+            .unwrap_or(false);
+        if !is_web_address {
+            return render(&tera, &store_settings, Some("The site address must start with http:// or https://"));
+        }
+    }
+
+    let settings_manager = settings::Settings::new(pool);
+
+    if let Err(e) = settings_manager.update(&store_settings).await {
+        eprintln!("Error: {}", e);
+        return Html("An error happened while saving the settings".to_string());
+    }
+
+    render(&tera, &store_settings, Some("Settings saved"))
+}
\ No newline at end of file
@@ -0,0 +1,168 @@
+//
+// Description: Manage product tags.
+// Last Modification: 2026-10-18 08:55:51
+//
+
+use crate::models::tags;
+
+use axum::{
+    extract::{Extension, Form, Path, Query},
+    response::{Html, Redirect},
+};
+
+use serde::Deserialize;
+
+use tera::{
+    Tera,
+    Context
+};
+
+#[derive(Deserialize)]
+pub struct TagForm {
+    name: String,
+    slug: String,
+    description: String,
+}
+
+async fn render_list(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tera: &Tera,
+    parameters: &tags::Parameters,
+    alert: Option<&str>) -> Html<String> {
+
+    let tags_manager = tags::Tags::new(pool.clone());
+
+    let page = match tags_manager.get_page(parameters).await {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching tags".to_string());
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "tags");
+    data.insert("title", "Tags");
+    data.insert("tags", &page.tags);
+    data.insert("current_page", &page.current_page);
+    data.insert("total_tags", &page.total_count);
+    data.insert("per_page", &page.per_page);
+    data.insert("total_pages", &page.total_pages);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+fn render_tag(tera: &Tera, tag: &tags::Tag, alert: Option<&str>) -> Html<String> {
+    let mut data = Context::new();
+    data.insert("partial", "tag");
+    data.insert("title", "Tag");
+    data.insert("tag", tag);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn list(
+    Query(parameters): Query<tags::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    render_list(&pool, &tera, &parameters, None).await
+}
+
+pub async fn add(
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<TagForm>) -> Html<String> {
+
+    let parameters = tags::Parameters { page: None, per_page: None };
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return render_list(&pool, &tera, &parameters, Some("The tag name is required")).await;
+    }
+
+    let tags_manager = tags::Tags::new(pool.clone());
+    match tags_manager.add(name, payload.slug.trim(), payload.description.trim()).await {
+        Ok(_) => render_list(&pool, &tera, &parameters, Some("Tag added")).await,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            render_list(&pool, &tera, &parameters, Some("The tag could not be added, the slug may be in use")).await
+        },
+    }
+}
+
+pub async fn edit(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let tags_manager = tags::Tags::new(pool);
+
+    match tags_manager.get(id).await {
+        Ok(tag) => render_tag(&tera, &tag, None),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("An error happened while fetching tag".to_string())
+        },
+    }
+}
+
+pub async fn update(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<TagForm>) -> Html<String> {
+
+    let tags_manager = tags::Tags::new(pool);
+
+    let mut tag = match tags_manager.get(id).await {
+        Ok(tag) => tag,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching tag".to_string());
+        },
+    };
+
+    tag.name = payload.name.trim().to_string();
+    tag.slug = payload.slug.trim().to_string();
+    tag.description = payload.description.trim().to_string();
+
+    if tag.name.is_empty() {
+        return render_tag(&tera, &tag, Some("The tag name is required"));
+    }
+
+    if let Err(e) = tags_manager.update(&tag).await {
+        eprintln!("Error: {}", e);
+        return render_tag(&tera, &tag, Some("The tag could not be saved, the slug may be in use"));
+    }
+
+    match tags_manager.get(id).await {
+        Ok(tag) => render_tag(&tera, &tag, Some("Tag saved")),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("An error happened while fetching tag".to_string())
+        },
+    }
+}
+
+pub async fn delete(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Result<Redirect, Html<String>> {
+
+    let tags_manager = tags::Tags::new(pool);
+
+    if let Err(e) = tags_manager.delete(id).await {
+        eprintln!("Error: {}", e);
+        return Err(Html("An error happened while deleting the tag".to_string()));
+    }
+
+    Ok(Redirect::to("/admin/tags"))
+}
@@ -2,11 +2,14 @@
 // Last Mofification: 2024-08-09 19:21:51
 //
 
+use crate::models::audit;
 use crate::models::users;
+use crate::models::login_attempts;
+use crate::models::tokens;
 
 use axum::{
     extract::{Extension, Path, Query},
-    response::Html,
+    response::{Html, Redirect},
 };
 
 use tera::{
@@ -27,7 +30,128 @@ pub async fn edit(
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
-    Html("edit user unimplemented".to_string())
+    let users_manager = users::Users::new(pool.clone());
+
+    let user = match users_manager.get(id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the user".to_string());
+        },
+    };
+
+    let attempts_manager = login_attempts::LoginAttempts::new(pool.clone());
+
+    let lock = match attempts_manager.lock(&user.email).await {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the login status".to_string());
+        },
+    };
+
+    let attempts = match attempts_manager.recent(&user.email, 10).await {
+        Ok(attempts) => attempts,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the login attempts".to_string());
+        },
+    };
+
+    let tokens_manager = tokens::Tokens::new(pool);
+
+    let sessions = match tokens_manager.sessions(&id).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the sessions".to_string());
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "user");
+    data.insert("title", "User");
+    data.insert("user", &user);
+    data.insert("lock", &lock);
+    data.insert("attempts", &attempts);
+    data.insert("sessions", &sessions);
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn unlock(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Result<Redirect, Html<String>> {
+
+    let users_manager = users::Users::new(pool.clone());
+
+    let user = match users_manager.get(id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Err(Html("An error happened while fetching the user".to_string()));
+        },
+    };
+
+    let attempts_manager = login_attempts::LoginAttempts::new(pool.clone());
+    if let Err(e) = attempts_manager.unlock(&user.email).await {
+        eprintln!("Error: {}", e);
+        return Err(Html("An error happened while unlocking the user".to_string()));
+    }
+
+    println!("User {} unlocked", user.email);
+
+    let audit_manager = audit::Audit::new(pool);
+    if let Err(e) = audit_manager.record("user.unlock", "user", id, None, None).await {
+        eprintln!("Error: {}", e);
+    }
+
+    Ok(Redirect::to(&format!("/admin/users/{}", id)))
+}
+
+pub async fn revoke_session(
+    Path((id, session_id)):Path<(i32, i32)>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Result<Redirect, Html<String>> {
+
+    let tokens_manager = tokens::Tokens::new(pool.clone());
+    match tokens_manager.revoke(&id, &session_id).await {
+        Ok(true) => {
+            let audit_manager = audit::Audit::new(pool);
+            if let Err(e) = audit_manager
+                .record("user.session_revoke", "user", id, Some(serde_json::json!({ "session_id": session_id })), None)
+                .await {
+                eprintln!("Error: {}", e);
+            }
+        },
+        Ok(false) => {},
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Err(Html("An error happened while revoking the session".to_string()));
+        },
+    }
+
+    Ok(Redirect::to(&format!("/admin/users/{}", id)))
+}
+
+pub async fn revoke_sessions(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Result<Redirect, Html<String>> {
+
+    let tokens_manager = tokens::Tokens::new(pool.clone());
+    if let Err(e) = tokens_manager.delete_all(&id).await {
+        eprintln!("Error: {}", e);
+        return Err(Html("An error happened while revoking the sessions".to_string()));
+    }
+
+    println!("All sessions of user {} revoked", id);
+
+    let audit_manager = audit::Audit::new(pool);
+    if let Err(e) = audit_manager.record("user.sessions_revoke", "user", id, None, None).await {
+        eprintln!("Error: {}", e);
+    }
+
+    Ok(Redirect::to(&format!("/admin/users/{}", id)))
 }
 
 pub async fn list(
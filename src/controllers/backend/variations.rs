@@ -0,0 +1,222 @@
+//
+// Description: Attributes and variations of variable products
+// Last Modification: 2026-10-18 20:48:27
+//
+
+use crate::models::products;
+use crate::models::variations;
+
+use axum::{
+    extract::{Extension, Path, RawForm},
+    response::Html,
+};
+
+use tera::{
+    Tera,
+    Context
+};
+
+async fn render(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tera: &Tera,
+    product_id: i32,
+    alert: Option<&str>) -> Html<String> {
+
+    let products_manager = products::Products::new(pool.clone());
+    let product = match products_manager.backend().get(product_id).await {
+        Ok(product) => product,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the product.".to_string());
+        }
+    };
+
+    let variations_manager = variations::Variations::new(pool.clone());
+    let attributes = match variations_manager.attributes(product_id).await {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the product attributes.".to_string());
+        }
+    };
+    let variations = match variations_manager.get_all(product_id).await {
+        Ok(variations) => variations,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the product variations.".to_string());
+        }
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "variations");
+    data.insert("title", "Product Variations");
+    data.insert("product", &product);
+    data.insert("attributes", &attributes);
+    data.insert("variations", &variations);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    let rendered = tera.render("backend/admin.html", &data).unwrap();
+    Html(rendered)
+}
+
+// attribute_name=Size&attribute_options=S+|+M&attribute_name=Colour&...
+fn parse_attributes(raw_form_data: &[u8]) -> Result<Vec<variations::Attribute>, String> {
+    let mut attributes: Vec<variations::Attribute> = vec![];
+    let mut name = "".to_string();
+
+    for (key, value) in url::form_urlencoded::parse(raw_form_data) {
+        match key.as_ref() {
+            "attribute_name" => name = value.trim().to_string(),
+            "attribute_options" => {
+                let options = variations::Attribute::parse_options(&value);
+                if name.is_empty() {
+                    continue;
+                }
+                if options.is_empty() {
+                    return Err(format!("The attribute {} has no options", name));
+                }
+                if attributes.iter().any(|attribute| attribute.name == name) {
+                    return Err(format!("The attribute {} is repeated", name));
+                }
+                attributes.push(variations::Attribute {
+                    name: name.to_string(),
+                    options,
+                });
+            },
+            "csrf_token" => {}, // checked by the csrf middleware
+            _ => eprintln!("Attributes unknown field {}", key),
+        }
+    }
+
+    Ok(attributes)
+}
+
+// Every variation row starts with variation_id, the chosen options are
+// sent as attribute:<name> fields
+fn parse_variations(raw_form_data: &[u8]) -> Result<(Vec<variations::Variation>, Vec<i32>), String> {
+    let mut rows: Vec<(variations::Variation, Option<f32>)> = vec![];
+    let mut removed: Vec<i32> = vec![];
+
+    let number_error = |field: &str, value: &str| format!("Invalid {}: {:?}", field, value);
+
+    for (key, value) in url::form_urlencoded::parse(raw_form_data) {
+        let value = value.trim();
+
+        if key == "variation_id" {
+            let mut variation = variations::Variation::new();
+            variation.id = value.parse().map_err(|_| number_error("variation", value))?;
+            rows.push((variation, None));
+            continue;
+        }
+
+        if matches!(key.as_ref(), "csrf_token" | "generate" | "save") {
+            continue;
+        }
+
+        let (variation, sale_price) = match rows.last_mut() {
+            Some(row) => row,
+            None => continue,
+        };
+
+        match key.as_ref() {
+            "sku" => variation.sku = value.to_string(),
+            "regular_price" => {
+                variation.regular_price = value.parse().map_err(|_| number_error("regular price", value))?;
+            },
+            "sale_price" => {
+                if !value.is_empty() {
+                    *sale_price = Some(value.parse().map_err(|_| number_error("sale price", value))?);
+                }
+            },
+            "stock_quantity" => {
+                variation.stock_quantity = value.parse().map_err(|_| number_error("quantity", value))?;
+            },
+            "weight" => variation.weight = value.parse().map_err(|_| number_error("weight", value))?,
+            "media_id" => variation.media_id = value.parse().unwrap_or(0),
+            "remove" => removed.push(variation.id),
+            _ => match key.strip_prefix("attribute:") {
+                Some(name) => {
+                    if !value.is_empty() {
+                        variation.attributes.insert(name.to_string(), value.to_string());
+                    }
+                },
+                None => eprintln!("Variations unknown field {}", key),
+            },
+        }
+    }
+
+    let mut variations: Vec<variations::Variation> = vec![];
+    for (mut variation, sale_price) in rows {
+        if variation.id == 0 && variation.attributes.is_empty() {
+            continue; // the empty row to add a variation
+        }
+        let is_kept = |v: &variations::Variation| !removed.contains(&v.id);
+        if is_kept(&variation) && variations.iter().any(|v| is_kept(v) && v.attributes == variation.attributes) {
+            return Err("Two variations have the same options".to_string());
+        }
+        let regular_price = variation.regular_price;
+        variation.set_prices(regular_price, sale_price);
+        variations.push(variation);
+    }
+
+    Ok((variations, removed))
+}
+
+pub async fn edit(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    render(&pool, &tera, id, None).await
+}
+
+pub async fn update_attributes(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let attributes = match parse_attributes(&form) {
+        Ok(attributes) => attributes,
+        Err(alert) => return render(&pool, &tera, id, Some(&alert)).await,
+    };
+
+    let variations_manager = variations::Variations::new(pool.clone());
+    if let Err(e) = variations_manager.save_attributes(id, &attributes).await {
+        eprintln!("Error: {}", e);
+        return Html("An error occurred while saving the product attributes.".to_string());
+    }
+
+    render(&pool, &tera, id, Some("Attributes saved")).await
+}
+
+pub async fn update(
+    Path(id):Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let generate = url::form_urlencoded::parse(&form).any(|(key, _)| key == "generate");
+
+    let (variations, removed) = match parse_variations(&form) {
+        Ok(parsed) => parsed,
+        Err(alert) => return render(&pool, &tera, id, Some(&alert)).await,
+    };
+
+    let variations_manager = variations::Variations::new(pool.clone());
+    if let Err(e) = variations_manager.save(id, &variations, &removed).await {
+        eprintln!("Error: {}", e);
+        return render(&pool, &tera, id, Some(&format!("The variations could not be saved: {}", e))).await;
+    }
+
+    if !generate {
+        return render(&pool, &tera, id, Some("Variations saved")).await;
+    }
+
+    match variations_manager.generate(id).await {
+        Ok(added) => render(&pool, &tera, id, Some(&format!("{} variations added", added))).await,
+        Err(e) => render(&pool, &tera, id, Some(&format!("The variations could not be generated: {}", e))).await,
+    }
+}
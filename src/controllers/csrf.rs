@@ -0,0 +1,139 @@
+//
+// Last Modification: 2026-10-18 14:05:12
+//
+
+// Synchronizer token pattern: one token per session, sent back by every
+// state-changing request in a "csrf_token" form field or X-CSRF-Token header.
+
+use crate::controllers::auth;
+use crate::utils;
+use std::collections::HashMap;
+
+use axum::{
+    body::{self, Body},
+    extract::{Extension, FromRequest, Multipart, Request},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use tera::{
+    Tera,
+    Function,
+    Value,
+};
+
+use tower_sessions::Session;
+use uuid::Uuid;
+
+const SESSION_KEY: &str = "csrf_token";
+const FIELD_NAME: &str = "csrf_token";
+const HEADER_NAME: &str = "x-csrf-token";
+
+// Same as the axum default limit applied later by the Form and Multipart extractors
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+tokio::task_local! {
+    static CSRF_TOKEN: String;
+}
+
+// Tera function used in the templates: {{ csrf_token() }}
+pub fn make_csrf_token() -> impl Function {
+    Box::new(move |_args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let token = CSRF_TOKEN
+            .try_with(|token| token.clone())
+            .unwrap_or_default();
+
+        Ok(Value::String(token))
+    })
+}
+
+async fn session_token(session: &Session) -> Result<String, anyhow::Error> {
+    if let Some(token) = session.get::<String>(SESSION_KEY).await? {
+        return Ok(token);
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+    session.insert(SESSION_KEY, &token).await?;
+
+    Ok(token)
+}
+
+// Look for the token in the header first, then in the form fields
+async fn submitted_token(request: Request) -> (Request, Option<String>) {
+
+    if let Some(token) = request.headers()
+        .get(HEADER_NAME)
+        .and_then(|value| value.to_str().ok()) {
+        let token = token.to_string();
+        return (request, Some(token));
+    }
+
+    let content_type = request.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let (parts, body) = request.into_parts();
+    let bytes = match body::to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(bytes) => bytes,
+        Err(_) => return (Request::from_parts(parts, Body::empty()), None),
+    };
+
+    let mut token = None;
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        token = url::form_urlencoded::parse(&bytes)
+            .find(|(key, _)| key == FIELD_NAME)
+            .map(|(_, value)| value.into_owned());
+    } else if content_type.starts_with("multipart/form-data") {
+        let copy = Request::from_parts(parts.clone(), Body::from(bytes.clone()));
+        if let Ok(mut multipart) = Multipart::from_request(copy, &()).await {
+            while let Ok(Some(field)) = multipart.next_field().await {
+                if field.name() == Some(FIELD_NAME) {
+                    token = field.text().await.ok();
+                    break;
+                }
+            }
+        }
+    }
+
+    // The body was consumed, give the handler a new one with the same bytes
+    (Request::from_parts(parts, Body::from(bytes)), token)
+}
+
+pub async fn protect(
+    session: Session,
+    Extension(tera): Extension<Tera>,
+    request: Request,
+    next: Next,
+) -> Response {
+
+    let token = match session_token(&session).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        },
+    };
+
+    let request = if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        request
+    } else {
+        let (request, submitted) = submitted_token(request).await;
+        let is_valid = submitted
+            .map(|submitted| utils::constant_time_eq(submitted.as_bytes(), token.as_bytes()))
+            .unwrap_or(false);
+
+        if !is_valid {
+            eprintln!("CSRF token mismatch: {} {}", request.method(), request.uri());
+            return auth::forbidden(&tera,
+                "The form has expired or was not sent from this site. Please go back, reload the page and try again.");
+        }
+
+        request
+    };
+
+    CSRF_TOKEN.scope(token, next.run(request)).await
+}
\ No newline at end of file
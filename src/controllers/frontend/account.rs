@@ -0,0 +1,847 @@
+//
+// Description: Customer registration and account area
+// Last Modification: 2026-10-18 11:28:42
+//
+
+use crate::controllers::auth;
+use crate::models::orders;
+use crate::models::settings;
+use crate::models::tokens;
+use crate::models::two_factor;
+use crate::models::users;
+use crate::types;
+use crate::notifications;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Form, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{Html, Redirect},
+};
+
+use std::net::SocketAddr;
+
+use tera::{
+    Tera,
+    Context
+};
+
+use serde::Deserialize;
+use tower_sessions::Session;
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterForm {
+    username: String,
+    email: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    password: String,
+    confirm_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileForm {
+    first_name: String,
+    last_name: String,
+    email: String,
+    phone: String,
+    current_password: Option<String>,
+    new_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressesForm {
+    billing_first_name: String,
+    billing_last_name: String,
+    billing_address: String,
+    billing_postcode: String,
+    billing_city: String,
+    billing_country: String,
+    billing_email: String,
+    billing_phone: String,
+    tax_id_number: Option<String>,
+    shipping_first_name: String,
+    shipping_last_name: String,
+    shipping_address: String,
+    shipping_postcode: String,
+    shipping_city: String,
+    shipping_country: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnableTwoFactorForm {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorForm {
+    password: String,
+}
+
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.contains('@'),
+        None => false,
+    }
+}
+
+// The guest orders of an address are only added to an account once the
+// customer opens the link mailed to it
+async fn send_email_verification(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    email: &str) -> Result<(), anyhow::Error> {
+
+    let token = tokens::Tokens::new(pool.clone()).add_email_verification(&user_id, email).await?;
+    let link = settings::Settings::new(pool.clone()).get().await?
+        .link(&format!("/my-account/verify-email?token={}", token))?;
+
+    let body = format!("Please confirm your email address by opening the following link within the next two days:\n\
+        {}\n\n\
+        The orders placed as a guest with this address will then appear in your account.\n\
+        If you did not create an account, you can ignore this email.", link);
+
+    notifications::SMTP::new(pool.clone()).send(email, "Confirm your email address", &body).await
+}
+
+// The address of the account only changes once the link mailed to the new
+// address is opened, a stolen session can not take the account over
+async fn send_email_change(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    email: &str) -> Result<(), anyhow::Error> {
+
+    let token = tokens::Tokens::new(pool.clone()).add_email_verification(&user_id, email).await?;
+    let link = settings::Settings::new(pool.clone()).get().await?
+        .link(&format!("/my-account/verify-email?token={}", token))?;
+
+    let body = format!("Please confirm the new email address of your account by opening the following link within the next two days:\n\
+        {}\n\n\
+        Until then your account keeps its current address.\n\
+        If you did not ask for this change, you can ignore this email.", link);
+
+    notifications::SMTP::new(pool.clone()).send(email, "Confirm your new email address", &body).await
+}
+
+fn render_account(tera: &Tera, data: &Context) -> Html<String> {
+    let rendered = tera.render("frontend/account.html", data).unwrap();
+    Html(rendered)
+}
+
+pub async fn register_form(
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let data = Context::new();
+    let rendered = tera.render("register.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn register(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<RegisterForm>,
+) -> (StatusCode, HeaderMap, Html<String>) {
+
+    let new_user = users::NewUser {
+        username: payload.username.trim().to_string(),
+        email: payload.email.trim().to_string(),
+        password: payload.password,
+        first_name: payload.first_name.unwrap_or_default().trim().to_string(),
+        last_name: payload.last_name.unwrap_or_default().trim().to_string(),
+        role: users::UserRoles::Customer,
+    };
+
+    let mut data = Context::new();
+    data.insert("user", &new_user);
+
+    let alert = if new_user.username.is_empty() {
+        "The username is required"
+    } else if !is_valid_email(&new_user.email) {
+        "The email address is not valid"
+    } else if new_user.password.chars().count() < 8 {
+        "The password must have at least 8 characters"
+    } else if new_user.password != payload.confirm_password {
+        "The passwords do not match"
+    } else {
+        ""
+    };
+
+    if !alert.is_empty() {
+        data.insert("alert", alert);
+        let rendered = tera.render("register.html", &data).unwrap();
+        return (StatusCode::OK, HeaderMap::new(), Html(rendered));
+    }
+
+    let users_manager = users::Users::new(pool.clone());
+
+    match users_manager.is_registered(&new_user.username, &new_user.email).await {
+        Ok(false) => (),
+        Ok(true) => {
+            data.insert("alert", "An account with this username or email already exists");
+            let rendered = tera.render("register.html", &data).unwrap();
+            return (StatusCode::OK, HeaderMap::new(), Html(rendered));
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to create the account.".to_string().into());
+        },
+    };
+
+    let user_id = match users_manager.add(&new_user).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to create the account.".to_string().into());
+        }
+    };
+
+    if let Err(e) = send_email_verification(&pool, user_id, &new_user.email).await {
+        eprintln!("Error sending the email confirmation: {}", e);
+    }
+
+    let client_ip = auth::client_ip(&request_headers, &peer);
+    match auth::sign_in(pool, &user_id, &users::UserRoles::Customer, &request_headers, &client_ip).await {
+        Ok(headers) => (StatusCode::FOUND, headers, Html("".to_string())),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to generate token.".to_string().into())
+        },
+    }
+}
+
+pub async fn dashboard(
+    auth: Option<auth::RequireAuth>,
+    Query(parameters): Query<std::collections::HashMap<String, String>>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let users_manager = users::Users::new(pool.clone());
+    let user = match users_manager.get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the account".to_string()));
+        },
+    };
+
+    let orders_manager = orders::Orders::new(pool);
+    let page = match orders_manager.get_page(&orders::Parameters {
+        page: Some(1),
+        per_page: Some(5),
+        order: Some(types::Order::Desc),
+        order_by: None,
+        after: None,
+        before: None,
+        modified_after: None,
+        modified_before: None,
+        product: None,
+        status: None,
+        customer: Some(auth.user_id as u32),
+    }).await {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching orders".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_dashboard");
+    data.insert("title", "My Account");
+    data.insert("user", &user);
+    data.insert("confirmation_sent", &parameters.contains_key("confirmation"));
+    data.insert("orders", &page.orders);
+    data.insert("total_orders", &page.total_count);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn verify_email(
+    Query(parameters): Query<std::collections::HashMap<String, String>>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let token = parameters.get("token").cloned().unwrap_or_default();
+
+    let mut data = Context::new();
+
+    let (user_id, email) = match tokens::Tokens::new(pool.clone()).use_email_verification(&token).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            data.insert("alert", &e.to_string());
+            let rendered = tera.render("login.html", &data).unwrap();
+            return Html(rendered);
+        },
+    };
+
+    // A link to another address than the one of the account confirms an
+    // email change, only the last link sent to the account is kept
+    let users_manager = users::Users::new(pool.clone());
+    let user = match users_manager.get(user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the account".to_string());
+        },
+    };
+    if !user.email.eq_ignore_ascii_case(&email) {
+        if let Err(e) = users_manager.update_email(user_id, &email).await {
+            eprintln!("Error: {}", e);
+            data.insert("alert", "The email address could not be changed, it may already be in use");
+            let rendered = tera.render("login.html", &data).unwrap();
+            return Html(rendered);
+        }
+    }
+
+    let orders_manager = orders::Orders::new(pool);
+    let count = match orders_manager.link_guest_orders(user_id, &email).await {
+        Ok(count) => count,
+        Err(e) => {
+            eprintln!("Error linking guest orders: {}", e);
+            return Html("An error happened while adding the guest orders".to_string());
+        },
+    };
+    println!("Linked {} guest orders to user id {}", count, user_id);
+
+    data.insert("alert", &format!("Your email address is confirmed, {} guest order{} added to your account.",
+        count, if count == 1 { " was" } else { "s were" }));
+    let rendered = tera.render("login.html", &data).unwrap();
+    Html(rendered)
+}
+
+pub async fn resend_email_verification(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+) -> Result<Redirect, Html<String>> {
+
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Ok(Redirect::to("/login")),
+    };
+
+    let user = match users::Users::new(pool.clone()).get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Err(Html("An error happened while fetching the account".to_string()));
+        },
+    };
+
+    if let Err(e) = send_email_verification(&pool, auth.user_id, &user.email).await {
+        eprintln!("Error sending the email confirmation: {}", e);
+        return Err(Html("The confirmation email could not be sent".to_string()));
+    }
+
+    Ok(Redirect::to("/my-account?confirmation=sent"))
+}
+
+pub async fn orders(
+    auth: Option<auth::RequireAuth>,
+    Query(mut parameters): Query<orders::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    parameters.customer = Some(auth.user_id as u32);
+    if parameters.order.is_none() {
+        parameters.order = Some(types::Order::Desc);
+    }
+
+    let orders_manager = orders::Orders::new(pool);
+    let page = match orders_manager.get_page(&parameters).await {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching orders".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_orders");
+    data.insert("title", "My Orders");
+    data.insert("orders", &page.orders);
+    data.insert("current_page", &page.current_page);
+    data.insert("total_orders", &page.total_count);
+    data.insert("per_page", &page.per_page);
+    data.insert("total_pages", &page.total_pages);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn order(
+    auth: Option<auth::RequireAuth>,
+    Path(id): Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let orders_manager = orders::Orders::new(pool);
+    let details = match orders_manager.get(id).await {
+        Ok(details) if details.order.customer_id == auth.user_id => details,
+        Ok(_) => return Ok(Html("Order not found".to_string())),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("Order not found".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "order_details");
+    data.insert("title", "Order Details");
+    data.insert("number", &details.id);
+    data.insert("date", &details.date_created);
+    data.insert("status", details.order.status.as_str());
+    data.insert("order", &details.order);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn profile(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let users_manager = users::Users::new(pool);
+    let user = match users_manager.get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the account".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_profile");
+    data.insert("title", "Account Details");
+    data.insert("user", &user);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn update_profile(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<ProfileForm>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let users_manager = users::Users::new(pool.clone());
+
+    let user = match users_manager.get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the account".to_string()));
+        },
+    };
+
+    let new_email = payload.email.trim().to_string();
+    let email_changed = !new_email.eq_ignore_ascii_case(&user.email);
+
+    // The email is changed once the new address is confirmed
+    let profile = users::Profile {
+        first_name: payload.first_name.trim().to_string(),
+        last_name: payload.last_name.trim().to_string(),
+        email: user.email.clone(),
+        phone: payload.phone.trim().to_string(),
+    };
+
+    let new_password = payload.new_password.unwrap_or_default();
+
+    // The current password is required to set a new password or email
+    let password_confirmed = if new_password.is_empty() && !email_changed {
+        false
+    } else {
+        match users_manager.cardentials(&user.email).await {
+            Ok(cardentials) => cardentials.verify(&payload.current_password.unwrap_or_default()).await,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                false
+            },
+        }
+    };
+
+    let email_taken = email_changed && users_manager.cardentials(&new_email).await.is_ok();
+
+    let invalid = if !is_valid_email(&new_email) {
+        Some("The email address is not valid")
+    } else if !new_password.is_empty() && new_password.chars().count() < 8 {
+        Some("The new password must have at least 8 characters")
+    } else if (email_changed || !new_password.is_empty()) && !password_confirmed {
+        Some("The current password is incorrect")
+    } else if email_taken {
+        Some("The email address is already in use")
+    } else {
+        None
+    };
+
+    let alert = if let Some(invalid) = invalid {
+        invalid.to_string()
+    } else if let Err(e) = users_manager.update(auth.user_id, &profile).await {
+        eprintln!("Error: {}", e);
+        "The account details could not be updated".to_string()
+    } else {
+        let mut alert = if new_password.is_empty() {
+            "Account details updated".to_string()
+        } else if let Err(e) = users_manager.update_password(&auth.user_id, &new_password).await {
+            eprintln!("Error: {}", e);
+            "The password could not be updated".to_string()
+        } else {
+            // Other devices logged in with the old password are signed out
+            if let Err(e) = tokens::Tokens::new(pool.clone()).revoke_others(&auth.user_id, &auth.session_id).await {
+                eprintln!("Error: {}", e);
+            }
+            "Account details and password updated".to_string()
+        };
+
+        if email_changed {
+            alert = match send_email_change(&pool, auth.user_id, &new_email).await {
+                Ok(()) => format!("{}, a link to confirm the new email address was sent to {}", alert, new_email),
+                Err(e) => {
+                    eprintln!("Error sending the email confirmation: {}", e);
+                    format!("{}, the confirmation of the new email address could not be sent", alert)
+                },
+            };
+        }
+
+        alert
+    };
+
+    let user = match users_manager.get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the account".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_profile");
+    data.insert("title", "Account Details");
+    data.insert("user", &user);
+    data.insert("alert", &alert);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn addresses(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let users_manager = users::Users::new(pool);
+    let addresses = match users_manager.get_addresses(auth.user_id).await {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the addresses".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_addresses");
+    data.insert("title", "Addresses");
+    data.insert("billing", &addresses.billing);
+    data.insert("shipping", &addresses.shipping);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn update_addresses(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<AddressesForm>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let addresses = users::Addresses {
+        billing: orders::Billing {
+            first_name: payload.billing_first_name,
+            last_name: payload.billing_last_name,
+            address: payload.billing_address,
+            city: payload.billing_city,
+            postcode: payload.billing_postcode,
+            country_code: payload.billing_country,
+            email: payload.billing_email,
+            phone: payload.billing_phone,
+            tax_id_number: payload.tax_id_number.unwrap_or_default(),
+        },
+        shipping: orders::Shipping {
+            first_name: payload.shipping_first_name,
+            last_name: payload.shipping_last_name,
+            address: payload.shipping_address,
+            city: payload.shipping_city,
+            postcode: payload.shipping_postcode,
+            country_code: payload.shipping_country,
+        },
+    };
+
+    let users_manager = users::Users::new(pool);
+    let alert = match users_manager.update_addresses(auth.user_id, &addresses).await {
+        Ok(()) => "Addresses updated",
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            "The addresses could not be updated"
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_addresses");
+    data.insert("title", "Addresses");
+    data.insert("billing", &addresses.billing);
+    data.insert("shipping", &addresses.shipping);
+    data.insert("alert", alert);
+
+    Ok(render_account(&tera, &data))
+}
+
+
+// Status and recovery codes when enabled, the enrolment form otherwise
+async fn two_factor_page(
+    session: &Session,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    user_id: i32) -> Result<Context, anyhow::Error> {
+
+    let users_manager = users::Users::new(pool.clone());
+    let user = users_manager.get(user_id).await?;
+
+    let two_factor_manager = two_factor::TwoFactor::new(pool);
+    let enabled = two_factor_manager.is_enabled(&user_id).await?;
+
+    let mut data = Context::new();
+    data.insert("partial", "account_two_factor");
+    data.insert("title", "Two-Factor Authentication");
+    data.insert("enabled", &enabled);
+
+    if enabled {
+        data.insert("recovery_codes_left", &two_factor_manager.recovery_codes_left(&user_id).await?);
+    } else {
+        let secret = auth::enrolment_secret(session).await?;
+        auth::insert_enrolment(&mut data, &user.email, &secret)?;
+    }
+
+    Ok(data)
+}
+
+pub async fn two_factor(
+    auth: Option<auth::RequireAuth>,
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    match two_factor_page(&session, pool, auth.user_id).await {
+        Ok(data) => Ok(render_account(&tera, &data)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Ok(Html("An error happened while fetching the two-factor settings".to_string()))
+        },
+    }
+}
+
+pub async fn enable_two_factor(
+    auth: Option<auth::RequireAuth>,
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<EnableTwoFactorForm>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let mut data = match two_factor_page(&session, pool.clone(), auth.user_id).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the two-factor settings".to_string()));
+        },
+    };
+
+    // Only the secret shown on the enrolment form can be confirmed
+    let secret = match data.get("secret").and_then(|secret| secret.as_str()) {
+        Some(secret) => secret.to_string(),
+        None => return Ok(render_account(&tera, &data)),
+    };
+
+    let step = match two_factor::verify_code(&secret, &payload.code) {
+        Some(step) => step,
+        None => {
+            data.insert("alert", "Invalid authentication code");
+            return Ok(render_account(&tera, &data));
+        },
+    };
+
+    let two_factor_manager = two_factor::TwoFactor::new(pool);
+    let recovery_codes = match two_factor_manager.enable(&auth.user_id, &secret, step).await {
+        Ok(codes) => codes,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while enabling two-factor authentication".to_string()));
+        },
+    };
+
+    if let Err(e) = auth::clear_enrolment_secret(&session).await {
+        eprintln!("Error: {}", e);
+    }
+
+    let mut data = Context::new();
+    data.insert("partial", "account_two_factor");
+    data.insert("title", "Two-Factor Authentication");
+    data.insert("enabled", &true);
+    data.insert("recovery_codes", &recovery_codes);
+
+    Ok(render_account(&tera, &data))
+}
+
+pub async fn disable_two_factor(
+    auth: Option<auth::RequireAuth>,
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    Form(payload): Form<DisableTwoFactorForm>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let users_manager = users::Users::new(pool.clone());
+
+    // The password is asked again, a stolen session alone is not enough
+    let password_confirmed = match users_manager.get(auth.user_id).await {
+        Ok(user) => match users_manager.cardentials(&user.email).await {
+            Ok(cardentials) => cardentials.verify(&payload.password).await,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                false
+            },
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            false
+        },
+    };
+
+    let alert = if !password_confirmed {
+        "The password is incorrect"
+    } else if let Err(e) = two_factor::TwoFactor::new(pool.clone()).disable(&auth.user_id).await {
+        eprintln!("Error: {}", e);
+        "Two-factor authentication could not be disabled"
+    } else {
+        "Two-factor authentication disabled"
+    };
+
+    let mut data = match two_factor_page(&session, pool, auth.user_id).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Ok(Html("An error happened while fetching the two-factor settings".to_string()));
+        },
+    };
+    data.insert("alert", alert);
+
+    Ok(render_account(&tera, &data))
+}
+
+async fn render_sessions(
+    tera: &Tera,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    auth: &auth::RequireAuth,
+    alert: Option<&str>) -> Html<String> {
+
+    let tokens_manager = tokens::Tokens::new(pool);
+    let sessions = match tokens_manager.sessions(&auth.user_id).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching the sessions".to_string());
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("partial", "account_sessions");
+    data.insert("title", "Sessions");
+    data.insert("sessions", &sessions);
+    data.insert("current_session", &auth.session_id);
+    if let Some(alert) = alert {
+        data.insert("alert", alert);
+    }
+
+    render_account(tera, &data)
+}
+
+pub async fn sessions(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    Ok(render_sessions(&tera, pool, &auth, None).await)
+}
+
+pub async fn revoke_session(
+    auth: Option<auth::RequireAuth>,
+    Path(id): Path<i32>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    // Revoking the current device is a logout
+    if id == auth.session_id {
+        return Err(Redirect::to("/login?action=logout"));
+    }
+
+    let tokens_manager = tokens::Tokens::new(pool.clone());
+    let alert = match tokens_manager.revoke(&auth.user_id, &id).await {
+        Ok(true) => "The session has been signed out",
+        Ok(false) => "Session not found",
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            "The session could not be signed out"
+        },
+    };
+
+    Ok(render_sessions(&tera, pool, &auth, Some(alert)).await)
+}
+
+pub async fn revoke_other_sessions(
+    auth: Option<auth::RequireAuth>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+) -> Result<Html<String>, Redirect> {
+
+    let auth = auth.ok_or(Redirect::to("/login"))?;
+
+    let tokens_manager = tokens::Tokens::new(pool.clone());
+    let alert = match tokens_manager.revoke_others(&auth.user_id, &auth.session_id).await {
+        Ok(_) => "All other sessions have been signed out",
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            "The sessions could not be signed out"
+        },
+    };
+
+    Ok(render_sessions(&tera, pool, &auth, Some(alert)).await)
+}
\ No newline at end of file
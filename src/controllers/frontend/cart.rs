@@ -1,5 +1,5 @@
 //
-// Last Modified: 2024-08-30 19:33:23
+// Last Modified: 2026-10-18 20:36:10
 //
 
 use crate::models::cart;
@@ -7,7 +7,7 @@ use crate::models::cart;
 use std::collections::HashMap;
 
 use axum::{
-    extract::{Extension, Form, RawForm},
+    extract::{Extension, RawForm},
     response::Html,
 };
 
@@ -26,7 +26,7 @@ pub async fn update_cart(
 
     let raw_form_data = String::from_utf8(form.to_vec()).unwrap();
 
-    let mut current_cart: HashMap<i32, i32> = match session.get("cart").await.unwrap() {
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
         Some(cart) => cart,
         None => HashMap::new()
     };
@@ -57,15 +57,69 @@ pub async fn add_to_cart(
     session: Session,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>,
-    Form(payload): Form<cart::ProductToCart>) -> Html<String> {
+    RawForm(form): RawForm) -> Html<String> {
+
+    let payload = match cart::ProductToCart::parse(&form) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while adding the product to the cart".to_string());
+        },
+    };
+
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
+        Some(cart) => cart,
+        None => HashMap::new()
+    };
+
+    let mut cart = cart::Cart::new(pool, &mut current_cart);
+    if let Err(e) = cart.add(&payload).await {
+        eprintln!("Error: {}", e);
+        return Html("An error happened while adding the product to the cart".to_string());
+    }
+
+    match cart.get().await {
+        Ok(products) => {
+            session.insert("cart", current_cart).await.unwrap();
+
+            let mut data = Context::new();
+            data.insert("partial", "cart");
+            data.insert("title", "Cart");
+            data.insert("cart", &products);
+            let rendered = tera.render("frontend/shopping.html", &data).unwrap();
+            Html(rendered)
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Html("Happen an error when get the cart".to_string())
+        },
+    }
+}
 
-    let mut current_cart: HashMap<i32, i32> = match session.get("cart").await.unwrap() {
+pub async fn add_group_to_cart(
+    session: Session,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>,
+    RawForm(form): RawForm) -> Html<String> {
+
+    let payload = match cart::GroupToCart::parse(&form) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while adding the products to the cart".to_string());
+        },
+    };
+
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
         Some(cart) => cart,
         None => HashMap::new()
     };
 
     let mut cart = cart::Cart::new(pool, &mut current_cart);
-    cart.add(&payload);
+    if let Err(e) = cart.add_group(&payload).await {
+        eprintln!("Error: {}", e);
+        return Html("An error happened while adding the products to the cart".to_string());
+    }
 
     match cart.get().await {
         Ok(products) => {
@@ -90,7 +144,7 @@ pub async fn show(
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
-    let mut current_cart: HashMap<i32, i32> = match session.get("cart").await.unwrap() {
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
         Some(cart) => cart,
         None => HashMap::new()
     };
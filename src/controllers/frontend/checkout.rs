@@ -4,10 +4,12 @@
 // https://woocommerce.com/document/managing-orders/order-statuses/
 //
 
+use crate::controllers::auth;
 use crate::models::cart;
 use crate::models::cart::Product;
 use crate::models::shipping;
 use crate::models::orders;
+use crate::models::users;
 use crate::utils;
 use crate::notifications;
 use std::collections::HashMap;
@@ -157,6 +159,7 @@ fn checkout_data(
 }
 
 pub async fn place_order(
+    auth: Option<auth::RequireAuth>,
     headers: HeaderMap,
     session: Session,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
@@ -165,7 +168,7 @@ pub async fn place_order(
 
     println!("Order Billing: {:?}", payload);
 
-    let mut current_cart: HashMap<i32, i32> = match session.get("cart").await.unwrap() {
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
         Some(cart) => cart,
         None => HashMap::new()
     };
@@ -246,6 +249,10 @@ pub async fn place_order(
 
                 let mut order = orders::Order {
                     order_key: "order_58d2d042d1d".to_string(),
+                    customer_id: match &auth {
+                        Some(auth) => auth.user_id,
+                        None => 0,
+                    },
                     customer_ip_address: client_ip.to_string(),
                     customer_user_agent: user_agent.to_string(),
                     billing: orders::Billing {
@@ -297,8 +304,9 @@ pub async fn place_order(
 
                         let line_item = orders::LineItem {
                             product_id: product.id,
-                            sku: product.sku,
-                            name: product.name,
+                            variation_id: product.variation_id,
+                            sku: product.sku.to_string(),
+                            name: product.full_name(),
                             price: product.price,
                             quantity: product.quantity,
                             subtotal: product.regular_price * product.quantity as f32, // Line subtotal (before discounts)
@@ -357,6 +365,7 @@ pub async fn place_order(
                         data.insert("partial", "order_details");
                         data.insert("title", "Order Details");
                         data.insert("number", &order_id);
+                        data.insert("date", &chrono::Local::now().format("%b %d, %Y").to_string());
                         data.insert("order", &order);
                         let rendered = tera.render("frontend/shopping.html", &data).unwrap();
                         Html(rendered)
@@ -377,11 +386,33 @@ pub async fn place_order(
 }
 
 pub async fn show(
+    auth: Option<auth::RequireAuth>,
     session: Session,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
-    let mut current_cart: HashMap<i32, i32> = match session.get("cart").await.unwrap() {
+    // Prefill the checkout with the addresses saved in the customer account
+    let addresses = match &auth {
+        Some(auth) => {
+            let users_manager = users::Users::new(pool.clone());
+            match users_manager.get_addresses(auth.user_id).await {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    users::Addresses {
+                        billing: orders::Billing::new(),
+                        shipping: orders::Shipping::new(),
+                    }
+                },
+            }
+        },
+        None => users::Addresses {
+            billing: orders::Billing::new(),
+            shipping: orders::Shipping::new(),
+        },
+    };
+
+    let mut current_cart: HashMap<String, i32> = match session.get("cart").await.unwrap() {
         Some(cart) => cart,
         None => HashMap::new()
     };
@@ -393,22 +424,22 @@ pub async fn show(
 
             let data = checkout_data(
                 RawOrder {
-                    billing_first_name: "".to_string(),
-                    billing_last_name: "".to_string(),
-                    email: "".to_string(),
-                    phone: "".to_string(),
-                    billing_address: "".to_string(),
-                    billing_postcode: "".to_string(),
-                    billing_city: "".to_string(),
-                    billing_country: "".to_string(),
-                    tax_id_number: None,
+                    billing_first_name: addresses.billing.first_name,
+                    billing_last_name: addresses.billing.last_name,
+                    email: addresses.billing.email,
+                    phone: addresses.billing.phone,
+                    billing_address: addresses.billing.address,
+                    billing_postcode: addresses.billing.postcode,
+                    billing_city: addresses.billing.city,
+                    billing_country: addresses.billing.country_code,
+                    tax_id_number: Some(addresses.billing.tax_id_number),
                     ship_to_different_address: None,
-                    shipping_first_name: "".to_string(),
-                    shipping_last_name: "".to_string(),
-                    shipping_address: "".to_string(),
-                    shipping_postcode: "".to_string(),
-                    shipping_city: "".to_string(),
-                    shipping_country: "".to_string(),
+                    shipping_first_name: addresses.shipping.first_name,
+                    shipping_last_name: addresses.shipping.last_name,
+                    shipping_address: addresses.shipping.address,
+                    shipping_postcode: addresses.shipping.postcode,
+                    shipping_city: addresses.shipping.city,
+                    shipping_country: addresses.shipping.country_code,
                     terms: None,
                     payment_method: "".to_string(),
                     order_comments: None,
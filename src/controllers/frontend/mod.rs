@@ -1,8 +1,11 @@
 //
-// Last Modification: 2024-07-24 20:05:55
+// Last Modification: 2026-10-18 10:17:38
 //
 
+pub mod account;
 pub mod cart;
 pub mod checkout;
 pub mod products;
+pub mod reviews;
+pub mod search;
 pub mod shortcodes;
\ No newline at end of file
@@ -1,9 +1,13 @@
 //
-// Last Modification: 2024-08-30 19:28:33
+// Last Modification: 2026-10-18 10:17:38
 //
 
 use anyhow;
+use crate::controllers::auth;
 use crate::models::products;
+use crate::models::reviews;
+use crate::models::tags;
+use crate::models::variations;
 use crate::types;
 
 use axum::{
@@ -20,12 +24,18 @@ use tera::{
     Context
 };
 
+// Shown below the description of a product
+const MAX_UPSELLS: i32 = 8;
+const RELATED_PRODUCTS: i32 = 4;
+
 pub async fn product(
+    auth: Option<auth::RequireAuth>,
     Path(slug):Path<String>,
+    Query(parameters): Query<std::collections::HashMap<String, String>>,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>) -> Html<String> {
 
-    let products_manager = products::Products::new(pool);
+    let products_manager = products::Products::new(pool.clone());
 
     match products_manager
         .frontend()
@@ -36,6 +46,102 @@ pub async fn product(
         
             let mut data = Context::new();
             data.insert("product", &product);
+
+            let tags = match tags::Tags::new(pool.clone()).product_tags(product.id).await {
+                Ok(tags) => tags,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Html("An error occurred while fetching product tags.".to_string());
+                },
+            };
+            data.insert("tags", &tags);
+
+            if product.product_type == products::ProductType::Variable {
+                let variations_manager = variations::Variations::new(pool.clone());
+                let attributes = match variations_manager.attributes(product.id).await {
+                    Ok(attributes) => attributes,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return Html("An error occurred while fetching product attributes.".to_string());
+                    },
+                };
+                let variations = match variations_manager.get_all(product.id).await {
+                    Ok(variations) => variations,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return Html("An error occurred while fetching product variations.".to_string());
+                    },
+                };
+                data.insert("attributes", &attributes);
+                data.insert("variations", &variations);
+            }
+
+            if product.product_type == products::ProductType::Grouped {
+                let children = match products_manager.frontend().grouped_children(product.id).await {
+                    Ok(children) => children,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return Html("An error occurred while fetching grouped products.".to_string());
+                    },
+                };
+                data.insert("children", &children);
+            }
+
+            let upsells = match products_manager.frontend()
+                .linked_products(&[product.id], products::LinkType::Upsell, MAX_UPSELLS)
+                .await {
+                Ok(upsells) => upsells,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Html("An error occurred while fetching upsells.".to_string());
+                },
+            };
+            data.insert("upsells", &upsells);
+
+            let related = match products_manager.frontend().related(product.id, RELATED_PRODUCTS).await {
+                Ok(related) => related,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Html("An error occurred while fetching related products.".to_string());
+                },
+            };
+            data.insert("related", &related);
+
+            let reviews_manager = reviews::Reviews::new(pool.clone());
+            let reviews = match reviews_manager.approved(product.id).await {
+                Ok(reviews) => reviews,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Html("An error occurred while fetching reviews.".to_string());
+                },
+            };
+            data.insert("reviews", &reviews);
+
+            // Customers review a product once, the visitors are asked to log in
+            let can_review = match auth.as_ref() {
+                Some(auth) => match reviews_manager.has_reviewed(product.id, auth.user_id).await {
+                    Ok(reviewed) => !reviewed,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return Html("An error occurred while fetching reviews.".to_string());
+                    },
+                },
+                None => false,
+            };
+            data.insert("logged_in", &auth.is_some());
+            data.insert("can_review", &can_review);
+            data.insert("max_review_length", &reviews::MAX_CONTENT_LENGTH);
+
+            // See reviews::add
+            if let Some(review) = parameters.get("review") {
+                data.insert("review_notice", match review.as_str() {
+                    "submitted" => "Thank you, your review is awaiting approval.",
+                    "duplicate" => "You have already reviewed this product.",
+                    "rating" => "Please select a rating from 1 to 5 stars.",
+                    "length" => "Your review is too long.",
+                    _ => "Your review could not be saved, please try again.",
+                });
+            }
         
             let rendered = tera.render("frontend/product.html", &data).unwrap();
             Html(rendered)
@@ -65,15 +171,19 @@ pub async fn get_products_data(
         },
     };
     
-    let categories = match products_manager.frontend()
-        .categories()
+    let facets = match products_manager.frontend()
+        .facets(parameters, category_slug)
         .await {
-        Ok(categories) => categories,
+        Ok(facets) => facets,
         Err(e) => {
-            return Err(anyhow::anyhow!("An error occurred while fetching categories: {}", e));
+            return Err(anyhow::anyhow!("An error occurred while fetching the filters: {}", e));
         }
     };
 
+    let encode = |value: &str| -> String {
+        url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>()
+    };
+
     let mut query_parts = vec![];
 
     if parameters.min_price.is_some() {
@@ -84,6 +194,21 @@ pub async fn get_products_data(
         query_parts.push(format!("max_price={}",
             page.max_price));
     }
+    if let Some(tag) = parameters.tag {
+        query_parts.push(format!("tag={}", tag));
+    }
+    if let Some(tags) = parameters.tags.as_ref() {
+        query_parts.push(format!("tags={}", encode(tags)));
+    }
+    if let Some(attributes) = parameters.attributes.as_ref() {
+        query_parts.push(format!("attributes={}", encode(attributes)));
+    }
+    if let Some(stock_status) = parameters.stock_status.as_ref() {
+        query_parts.push(format!("stock_status={}", stock_status.as_str()));
+    }
+    if let Some(rating) = parameters.rating {
+        query_parts.push(format!("rating={}", rating));
+    }
     if parameters.on_sale.is_some() {
         query_parts.push(format!("on_sale={}",
             parameters.on_sale.unwrap_or(false)));
@@ -107,7 +232,15 @@ pub async fn get_products_data(
         "".to_string()
     }(&parameters.order_by);
 
-    data.insert("categories", &categories);
+    // The category links keep the other filters, a category page replaces
+    // the category of the query string
+    data.insert("category_query", &query_parts.join("&"));
+    if let Some(category) = parameters.category {
+        query_parts.push(format!("category={}", category));
+    }
+
+    data.insert("categories", &facets.categories);
+    data.insert("facets", &facets);
 
     // range price
     data.insert("default_min_price", &page.min_price);
@@ -158,6 +291,36 @@ pub async fn product_category(
     Html(rendered)
 }
 
+pub async fn product_tag(
+    Path(slug):Path<String>,
+    Query(mut parameters): Query<products::Parameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let tag = match tags::Tags::new(pool.clone()).get_by_slug(&slug).await {
+        Ok(Some(tag)) => tag,
+        Ok(None) => return Html("Tag not found".to_string()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error occurred while fetching the tag.".to_string());
+        },
+    };
+
+    parameters.tag = Some(tag.id as u32);
+
+    let mut data = Context::new();
+
+    let result = get_products_data(&pool, &mut data, &parameters, None).await;
+    if result.is_err() {
+        return Html(result.err().unwrap().to_string());
+    }
+
+    data.insert("tag", &tag);
+    data.insert("path", &format!("/product-tag/{}", slug));
+
+    let rendered = tera.render("frontend/products.html", &data).unwrap();
+    Html(rendered)
+}
 
 pub async fn list(
     Query(parameters): Query<products::Parameters>,
@@ -0,0 +1,88 @@
+//
+// Description: Customer reviews of the products
+// Last Modification: 2026-10-18 10:17:38
+//
+
+use crate::controllers::auth;
+use crate::models::products;
+use crate::models::reviews;
+use crate::models::users;
+
+use axum::{
+    extract::{Extension, Form, Path},
+    response::Redirect,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewForm {
+    rating: String,
+    content: String,
+}
+
+// The outcome is shown on the product page, see products::product
+pub async fn add(
+    auth: Option<auth::RequireAuth>,
+    Path(slug):Path<String>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Form(payload): Form<ReviewForm>) -> Redirect {
+
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Redirect::to("/login"),
+    };
+
+    let back = |review: &str| Redirect::to(&format!("/product/{}?review={}#reviews", slug, review));
+
+    let product = match products::Products::new(pool.clone()).frontend().get_one_by_slug(&slug).await {
+        Ok(product) => product,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Redirect::to("/");
+        },
+    };
+
+    let rating = match payload.rating.trim().parse::<i32>() {
+        Ok(rating) if (1..=5).contains(&rating) => rating,
+        _ => return back("rating"),
+    };
+
+    let content = payload.content.trim();
+    if content.chars().count() > reviews::MAX_CONTENT_LENGTH {
+        return back("length");
+    }
+
+    let reviews_manager = reviews::Reviews::new(pool.clone());
+    match reviews_manager.has_reviewed(product.id, auth.user_id).await {
+        Ok(false) => {},
+        Ok(true) => return back("duplicate"),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return back("error");
+        },
+    }
+
+    let user = match users::Users::new(pool).get(auth.user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return back("error");
+        },
+    };
+
+    // "Jane D.", the username when the account has no name
+    let author = match (user.first_name.trim(), user.last_name.trim().chars().next()) {
+        ("", _) => user.username.clone(),
+        (first_name, Some(initial)) => format!("{} {}.", first_name, initial),
+        (first_name, None) => first_name.to_string(),
+    };
+
+    match reviews_manager.add(product.id, auth.user_id, &author, rating, content).await {
+        Ok(_) => back("submitted"),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            back("error")
+        },
+    }
+}
@@ -0,0 +1,93 @@
+//
+// Description: Product search
+// Last Modification: 2026-10-18 08:58:00
+//
+
+use crate::models::frontend;
+use crate::models::products;
+
+use axum::{
+    extract::{Extension, Query},
+    response::Html,
+    Json,
+};
+
+use serde::Deserialize;
+
+use tera::{
+    Tera,
+    Context
+};
+
+const RESULTS_PER_PAGE: i32 = 12;
+const SUGGESTIONS_LIMIT: i32 = 8;
+
+// Shorter queries match too many products to be useful as suggestions
+const SUGGESTIONS_MIN_LENGTH: usize = 2;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParameters {
+    q: Option<String>,
+    page: Option<u32>,
+}
+
+pub async fn search(
+    Query(parameters): Query<SearchParameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>) -> Html<String> {
+
+    let text = parameters.q.unwrap_or_default().trim().to_string();
+
+    let mut data = Context::new();
+    data.insert("q", &text);
+
+    if let Some(query) = products::search_query(&text) {
+        let products_manager = products::Products::new(pool);
+        let page = match products_manager.frontend()
+            .search(&query, parameters.page.unwrap_or(1) as i32, RESULTS_PER_PAGE)
+            .await {
+            Ok(page) => page,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Html("An error happened while searching products".to_string());
+            },
+        };
+
+        let mut query_string = url::form_urlencoded::Serializer::new(String::new());
+        query_string.append_pair("q", &text);
+
+        data.insert("results", &page.results);
+        data.insert("total_results", &page.total_count);
+        data.insert("current_page", &page.current_page);
+        data.insert("total_pages", &page.total_pages);
+        data.insert("query", &query_string.finish());
+    }
+
+    let rendered = tera.render("frontend/search.html", &data).unwrap();
+    Html(rendered)
+}
+
+// /search/suggestions?q=cott -> [{"id": 3, "name": "Cotton Shirt", ...}]
+pub async fn suggestions(
+    Query(parameters): Query<SearchParameters>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>) -> Json<Vec<frontend::Suggestion>> {
+
+    let text = parameters.q.unwrap_or_default();
+    if text.trim().chars().count() < SUGGESTIONS_MIN_LENGTH {
+        return Json(vec![]);
+    }
+
+    let query = match products::search_query(&text) {
+        Some(query) => query,
+        None => return Json(vec![]),
+    };
+
+    let products_manager = products::Products::new(pool);
+    match products_manager.frontend().suggestions(&query, SUGGESTIONS_LIMIT).await {
+        Ok(suggestions) => Json(suggestions),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Json(vec![])
+        },
+    }
+}
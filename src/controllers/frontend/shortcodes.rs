@@ -1,5 +1,5 @@
 //
-// Last Modification: 2024-08-14 19:44:01
+// Last Modification: 2026-10-18 11:41:47
 //
 
 // https://woocommerce.com/document/woocommerce-shortcodes/products/
@@ -10,6 +10,7 @@ use std::collections::HashMap;
 
 use axum::{
     extract::{Extension, Query},
+    http::StatusCode,
     response::Html,
 };
 
@@ -44,12 +45,13 @@ pub struct ProductsParams {
     skus: Option<String>,
     on_sale: Option<bool>,
     order: Option<String>,
-    order_by: Option<String>,
+    #[serde(alias = "orderby")] // the attribute of the tera shortcode
+    order_by: Option<String>, // date (default), title, price, popularity or rating
 }
 
 //
 // Example
-// <div data-swap="outer" data-shortcode="/shortcode/products?ids=1,3,5&limit=4&skus=6654343,7548765&on_sale=true&order=desc"></div>
+// <div data-swap="outer" data-shortcode="/shortcode/products?ids=1,3,5&limit=4&skus=6654343,7548765&on_sale=true&order=desc&order_by=popularity"></div>
 //
 pub async fn products(
     Query(parameters): Query<ProductsParams>,
@@ -58,28 +60,30 @@ pub async fn products(
 
     println!("Parameters: {:?}", parameters);
 
-    let ids = match parameters.ids {
-        Some(ids) => {
-            ids.split(',')
-               .map(|s| s.trim().parse::<i32>().unwrap())
-               .collect::<Vec<i32>>()
-        },
-        None => Vec::new(),
-    };
+    let mut filters = products::Filters::new();
+    filters.status = Some(products::Status::Publish);
+    filters.listed = true;
+    filters.on_sale = parameters.on_sale;
 
-    let skus = match parameters.skus {
-        Some(skus) => {
-            skus.split(',')
-               .map(|s| s.trim().to_string())
-               .collect::<Vec<String>>()
-        },
-        None => Vec::new(),
-    };
+    if let Some(ids) = parameters.ids.as_ref() {
+        filters.include = match products::parse_ids(ids) {
+            Ok(ids) => ids,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Html("Invalid product ids".to_string());
+            },
+        };
+    }
 
-    let on_sale = match parameters.on_sale {
-        Some(os) => os,
-        None => false,
-    };
+    if let Some(skus) = parameters.skus.as_ref() {
+        filters.skus = match products::parse_skus(skus) {
+            Ok(skus) => skus,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Html("Invalid product skus".to_string());
+            },
+        };
+    }
 
     let per_page = match parameters.limit {
         Some(l) => l,
@@ -99,16 +103,19 @@ pub async fn products(
     };
 
 
-    let order_by = match parameters.order_by {
-        Some(ob) => ob,
-        None => "name".to_string(),
-    };
+    let order_by = products::products_order_by(&parameters.order_by);
 
     let products_manager = products::Products::new(pool);
-    let products = products_manager
+    let products = match products_manager
         .frontend()
-        .get_by_parameters(&ids, &skus, 1, per_page, order)
-        .await.expect("unable to get the products");
+        .get_by_parameters(&filters, 1, per_page, order_by, order)
+        .await {
+        Ok(products) => products,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching products".to_string());
+        },
+    };
 
 
     let mut data = Context::new();
@@ -118,10 +125,116 @@ pub async fn products(
 }
 
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BestSellersParams {
+    limit: Option<i32>,
+}
+
+//
+// Example
+// <div data-swap="outer" data-shortcode="/shortcode/best-sellers?limit=4"></div>
+//
+pub async fn best_sellers(
+    Query(parameters): Query<BestSellersParams>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>)  -> Html<String> {
+
+    let mut filters = products::Filters::new();
+    filters.status = Some(products::Status::Publish);
+    filters.listed = true;
+
+    let limit = parameters.limit.unwrap_or(4).clamp(1, 20);
+
+    let products_manager = products::Products::new(pool);
+    let products = match products_manager
+        .frontend()
+        .get_by_parameters(&filters, 1, limit, "total_sales", types::Order::Desc)
+        .await {
+        Ok(products) => products,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return Html("An error happened while fetching products".to_string());
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("products", &products);
+    data.insert("title", "Best sellers");
+    let rendered = tera.render("shortcodes/linked_products.html", &data).unwrap();
+    Html(rendered)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkedProductsParams {
+    ids: String, // the products, e.g. of the cart
+    #[serde(rename = "type")]
+    link_type: Option<String>, // related (default), upsells or cross_sells
+    limit: Option<i32>,
+}
+
+//
+// Example
+// <div data-swap="outer" data-shortcode="/shortcode/linked-products?ids=3,8&type=cross_sells&limit=4"></div>
+//
+pub async fn linked_products(
+    Query(parameters): Query<LinkedProductsParams>,
+    Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
+    Extension(tera): Extension<Tera>)  -> (StatusCode, Html<String>) {
+
+    let ids = match products::parse_ids(&parameters.ids) {
+        Ok(ids) if !ids.is_empty() => ids,
+        Ok(_) => return (StatusCode::OK, Html("".to_string())),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::BAD_REQUEST, Html("Invalid product ids".to_string()));
+        },
+    };
+
+    let limit = parameters.limit.unwrap_or(4).clamp(1, 20);
+
+    let products_manager = products::Products::new(pool);
+    let frontend = products_manager.frontend();
+
+    let (products, title) = match parameters.link_type.as_deref().unwrap_or("related") {
+        "related" => (frontend.related(ids[0], limit).await, "Related products"),
+        "upsells" => (frontend.linked_products(&ids, products::LinkType::Upsell, limit).await, "You may also like…"),
+        "cross_sells" => (frontend.linked_products(&ids, products::LinkType::CrossSell, limit).await, "You may be interested in…"),
+        _ => return (StatusCode::BAD_REQUEST, Html("Unknown linked products type".to_string())),
+    };
+
+    let products = match products {
+        Ok(products) => products,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html("An error happened while fetching products".to_string()));
+        },
+    };
+
+    let mut data = Context::new();
+    data.insert("products", &products);
+    data.insert("title", title);
+    let rendered = tera.render("shortcodes/linked_products.html", &data).unwrap();
+    (StatusCode::OK, Html(rendered))
+}
+
 // tera.register_function("shortcode", make_shortcode());
 // {{ shortcode(display="products", limit="4", columns="4", orderby="popularity", class="quick-sale", on_sale="true") | safe }}
 
 fn shortcode_products(args: &HashMap<String, Value>) -> String {
+    shortcode_element(args, "/shortcode/products", &["limit", "ids", "skus", "on_sale", "order", "orderby"])
+}
+
+// {{ shortcode(display="best_sellers", limit="4") | safe }}
+fn shortcode_best_sellers(args: &HashMap<String, Value>) -> String {
+    shortcode_element(args, "/shortcode/best-sellers", &["limit"])
+}
+
+// {{ shortcode(display="linked_products", ids="3,8", type="cross_sells", limit="4") | safe }}
+fn shortcode_linked_products(args: &HashMap<String, Value>) -> String {
+    shortcode_element(args, "/shortcode/linked-products", &["ids", "type", "limit"])
+}
+
+fn shortcode_element(args: &HashMap<String, Value>, url: &str, names: &[&str]) -> String {
     let mut attributes = vec![];
 
     let swap = match args.get("swap") {
@@ -129,7 +242,7 @@ fn shortcode_products(args: &HashMap<String, Value>) -> String {
         None => "outer".to_string(),
     };
 
-    for attribute in ["limit", "ids", "skus", "on_sale", "order", "orderby"] {
+    for attribute in names.iter().copied() {
         if let Some(v) = args.get(attribute) {
             let v = to_value(v).unwrap();
             attributes.push(format!("{}={}", attribute, from_value::<String>(v).unwrap()));
@@ -137,9 +250,9 @@ fn shortcode_products(args: &HashMap<String, Value>) -> String {
     }
 
     format!("<div data-swap=\"{}\" data-shortcode=\"{}\"></div>", swap, if attributes.is_empty() {
-        "/shortcode/products".to_string()
+        url.to_string()
     } else {
-        format!("/shortcode/products?{}", attributes.join("&"))
+        format!("{}?{}", url, attributes.join("&"))
     })
 }
 
@@ -154,6 +267,8 @@ pub fn make_shortcode() -> impl Function {
 
         match display_name.as_ref() {
             "products" => Ok(shortcode_products(args).into()),
+            "best_sellers" => Ok(shortcode_best_sellers(args).into()),
+            "linked_products" => Ok(shortcode_linked_products(args).into()),
             _ => Err(tera::Error::msg(format!("Unknown shortcode display name: {}", display_name))),
         }
     })
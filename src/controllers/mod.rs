@@ -4,6 +4,7 @@
 
 pub mod admin;
 pub mod auth;
+pub mod csrf;
 pub mod backend;
 pub mod frontend;
 pub mod storefront;
\ No newline at end of file
@@ -23,15 +23,20 @@ mod utils;
 mod notifications;
 
 use time::Duration;
+use std::io::{
+    self,
+    Write,
+};
 use std::path::{
     Path,
     PathBuf
 };
+use std::net::SocketAddr;
 
 use axum::{
-    extract::{Extension, Form, Request},
+    extract::{ConnectInfo, Extension, Form, Request},
     http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode},
-    middleware::from_extractor,
+    middleware::{from_extractor, from_fn, from_fn_with_state},
     response::Html,
     routing::{get, post},
     Router,
@@ -47,10 +52,13 @@ use tower_http::{
 
 use tower_sessions::{
     Expiry,
+    Session,
     SessionManagerLayer,
 };
 use tower_sessions_sqlx_store::PostgresStore;
 
+use models::users::Capability;
+
 use tera::{
     Tera,
     Context
@@ -75,7 +83,10 @@ use serde::{
 const APP_NAME: &str = "store";
 const DEFAULT_CONFIG_FILE: &str = "config/store.ini";
 
-use clap::Parser;
+use clap::{
+    Parser,
+    Subcommand,
+};
 
 #[derive(Parser)]
 #[command(name = APP_NAME)]
@@ -88,6 +99,49 @@ struct Cli {
 
     #[arg(short = 'p', long = "port", default_value_t = 8080)]
     port: u16,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Set a new password for the user with the given email
+    ResetPassword {
+        #[arg(short = 'u', long = "user")]
+        email: String,
+    },
+    /// Change the role of the user with the given email (admin, shop_manager, editor, support, customer)
+    SetRole {
+        #[arg(short = 'u', long = "user")]
+        email: String,
+        #[arg(short = 'r', long = "role")]
+        role: models::users::UserRoles,
+    },
+    /// Create or update the products of a CSV file by SKU
+    ImportProducts {
+        #[arg(short = 'f', long = "file")]
+        file: PathBuf,
+        /// Report the changes without saving them
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Write the products to a CSV file
+    ExportProducts {
+        #[arg(short = 'f', long = "file")]
+        file: PathBuf,
+    },
+    /// Import the categories.json, products.json, customers.json and orders.json of a WooCommerce REST API export
+    ImportWoocommerce {
+        #[arg(short = 'd', long = "dir")]
+        dir: PathBuf,
+        /// Report the changes without saving them
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+        /// The weight unit of the shop (kg, g, lbs, oz)
+        #[arg(long = "weight-unit", default_value = "kg")]
+        weight_unit: String,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -106,48 +160,104 @@ struct DatabaseConf {
 }
 
 async fn autentication(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    session: Session,
     Extension(pool): Extension<sqlx::Pool<sqlx::Postgres>>,
     Extension(tera): Extension<Tera>,
     Form(payload): Form<LoginForm>,
 ) -> (StatusCode, HeaderMap, Html<String>) {
 
-    let user = models::users::Users::new(pool.clone());
+    let users_manager = models::users::Users::new(pool.clone());
+    let attempts_manager = models::login_attempts::LoginAttempts::new(pool.clone());
+
+    let client_ip = controllers::auth::client_ip(&request_headers, &peer);
 
     let mut data = Context::new();
 
-    match user.cardentials(&payload.user).await {
+    // The attempt is counted as a failure until the password is found right
+    match attempts_manager.attempt(&payload.user, &client_ip).await {
+        Ok(Some(locked_until)) => {
+            eprintln!("Blocked login for {:?} from {} until {}", payload.user, client_ip, locked_until);
+
+            let wait = (locked_until - chrono::Utc::now().naive_utc()).num_seconds().max(1);
+            data.insert("alert", &format!("Too many failed login attempts. Please try again in {}.",
+                match wait {
+                    1 => "1 second".to_string(),
+                    2..=59 => format!("{} seconds", wait),
+                    _ => format!("{} minutes", (wait + 59) / 60),
+                }));
+
+            let rendered = tera.render("login.html", &data).unwrap();
+            return (StatusCode::TOO_MANY_REQUESTS, HeaderMap::new(), Html(rendered));
+        },
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to check login attempts.".to_string().into());
+        },
+    }
+
+    match users_manager.cardentials(&payload.user).await {
         Ok(user) => {
-            if payload.password == user.password {
+            if user.verify(&payload.password).await {
+                if user.is_legacy() {
+                    // Upgrade the plain text password to a hash
+                    match users_manager.update_password(&user.user_id, &payload.password).await {
+                        Ok(()) => println!("Password upgraded for user id {}", user.user_id),
+                        Err(e) => eprintln!("Error upgrading password: {}", e),
+                    }
+                }
+
+                // The password is right, the code still has to be checked before the token is created
+                match controllers::auth::requires_two_factor(pool.clone(), &user.user_id, &user.role).await {
+                    Ok(true) => {
+                        // The code is an attempt of its own
+                        if let Err(e) = attempts_manager.release(&payload.user, &client_ip).await {
+                            eprintln!("Error: {}", e);
+                        }
+
+                        if let Err(e) = controllers::auth::begin_two_factor(&session, &user.user_id, &payload.user, &user.role).await {
+                            eprintln!("Error: {}", e);
+                            return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to start two-factor authentication.".to_string().into());
+                        }
+
+                        let mut headers = HeaderMap::new();
+                        headers.insert(LOCATION, HeaderValue::from_static("/login/two-factor"));
+                        return (StatusCode::FOUND, headers, Html("".to_string()));
+                    },
+                    Ok(false) => {},
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to check two-factor authentication.".to_string().into());
+                    },
+                }
+
+                if let Err(e) = attempts_manager.success(&payload.user, &client_ip).await {
+                    eprintln!("Error: {}", e);
+                }
+
                 // Create session here
-                let tokens_manager = models::tokens::Tokens::new(pool.clone());
-                let token = match tokens_manager.add(&user.user_id).await {
-                    Ok(token) => token,
+                return match controllers::auth::sign_in(pool.clone(), &user.user_id, &user.role, &request_headers, &client_ip).await {
+                    Ok(headers) => (StatusCode::FOUND, headers, Html("".to_string())),
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to generate token.".to_string().into());
-                    }
+                        (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "Failed to generate token.".to_string().into())
+                    },
                 };
-                println!("TOKEN: {}", token);
-                let mut headers = HeaderMap::new();
-                headers.insert(axum::http::header::SET_COOKIE, format!("token={}; Path=/; HttpOnly", token).parse().unwrap());
-
-                if user.role.is_admin() {
-                    // data.insert("partial", "dashboard");
-                    // let rendered = tera.render("admin/admin.html", &data).unwrap();
-                    // return (StatusCode::OK, headers, Html(rendered));
-                    headers.insert(LOCATION, HeaderValue::from_str("/admin").unwrap());
-                    return (StatusCode::FOUND, headers, Html("".to_string()));
-                } else {
-                    return (StatusCode::OK, headers, Html("<h1>User Login Successful</h1>".to_string()));
-                }
             }
         }
+        // Unknown users count as failures too, the answer and its timing are the same
         Err(e) => {
             eprintln!("Error: {}", e);
-            return (StatusCode::NOT_FOUND, HeaderMap::new(), "User not found!".to_string().into());
-        }
+            models::users::verify_unknown_user(&payload.password).await;
+        },
     };
 
+    if let Err(e) = attempts_manager.failure(&payload.user, &client_ip).await {
+        eprintln!("Error: {}", e);
+    }
+
     data.insert("alert", "Invalid username or password");
 
     let rendered = tera.render("login.html", &data).unwrap();
@@ -155,6 +265,96 @@ async fn autentication(
     (StatusCode::OK, HeaderMap::new(), Html(rendered))
 }
 
+fn prompt_password(prompt: &str) -> String {
+    loop {
+        let mut input = String::new();
+        print!("{}: ", prompt);
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut input).unwrap();
+        let input = input.trim_end_matches(&['\r', '\n'][..]).to_string();
+        if input.chars().count() >= 8 {
+            return input;
+        }
+        println!("The password must have at least 8 characters. Please try again.");
+    }
+}
+
+async fn reset_password(pool: &Pool<Postgres>, email: &str) -> Result<(), anyhow::Error> {
+    let password = prompt_password("New password");
+    if password != prompt_password("Repeat the new password") {
+        return Err(anyhow::anyhow!("the passwords do not match"));
+    }
+
+    let users_manager = models::users::Users::new(pool.clone());
+    let user_id = users_manager.reset_password(email, &password).await?;
+
+    let tokens_manager = models::tokens::Tokens::new(pool.clone());
+    tokens_manager.delete_all(&user_id).await
+}
+
+async fn import_products(pool: &Pool<Postgres>, file: &Path, dry_run: bool) -> Result<(), anyhow::Error> {
+    let text = std::fs::read_to_string(file)?;
+    let report = models::product_csv::ProductCsv::new(pool.clone(), dry_run).import(&text).await?;
+
+    for warning in report.warnings.iter() {
+        println!("{}", warning);
+    }
+    for row in report.rows.iter() {
+        println!("Line {}: {} {} {}", row.line, row.action, row.sku, row.name);
+        for message in row.messages.iter() {
+            println!("    {}", message);
+        }
+    }
+    println!("{}{} created, {} updated, {} failed",
+        if dry_run { "Dry run, nothing saved: " } else { "" },
+        report.created, report.updated, report.failed);
+
+    Ok(())
+}
+
+async fn export_products(pool: &Pool<Postgres>, file: &Path) -> Result<(), anyhow::Error> {
+    let csv = models::product_csv::export(pool).await?;
+    std::fs::write(file, csv)?;
+    println!("Products written to {}", file.display());
+    Ok(())
+}
+
+async fn import_woocommerce(pool: &Pool<Postgres>, dir: &Path, dry_run: bool, weight_unit: &str) -> Result<(), anyhow::Error> {
+    let grams_per_unit = models::woocommerce::grams_per_unit(weight_unit)
+        .ok_or(anyhow::anyhow!("the weight unit {:?} is not kg, g, lbs or oz", weight_unit))?;
+    let report = models::woocommerce::WooCommerce::new(pool.clone(), dry_run, grams_per_unit).import(dir).await?;
+
+    for file in models::woocommerce::FILES.iter() {
+        if !report.resources.iter().any(|resource| resource.file == *file) {
+            println!("{}: not found, skipped", file);
+        }
+    }
+    for resource in report.resources.iter() {
+        println!("{}: {} imported, {} already in the shop, {} failed",
+            resource.file, resource.imported, resource.existing, resource.errors.len());
+        for (woo_id, id) in resource.renumbered.iter() {
+            println!("    {} is now {}, the id was taken", woo_id, id);
+        }
+        for error in resource.errors.iter() {
+            println!("    Error: {}", error);
+        }
+        for warning in resource.warnings.iter() {
+            println!("    {}", warning);
+        }
+        for (field, count) in resource.unmapped.iter() {
+            println!("    Unmapped field {} in {} records", field, count);
+        }
+    }
+    if report.resources.iter().any(|resource| resource.file == models::woocommerce::FILES[2] && resource.imported > 0) {
+        println!("The imported customers have no password, they set one with a password reset");
+    }
+    if report.dry_run {
+        println!("Dry run, nothing saved");
+    }
+
+    Ok(())
+}
+
 async fn is_database_empty(pool: &Pool<Postgres>) -> Result<bool, Error> {
     let row: (i64,) = sqlx::query_as(
         r#"
@@ -219,6 +419,46 @@ async fn main() {
         Err(e) => panic!("Error checking database schema: {}", e),
     };
 
+    if let Some(command) = args.command {
+        match command {
+            Commands::ResetPassword { email } => {
+                match reset_password(&pool, &email).await {
+                    Ok(()) => println!("Password updated for user {}", email),
+                    Err(e) => eprintln!("Error resetting password: {}", e),
+                }
+            },
+            Commands::SetRole { email, role } => {
+                let users_manager = models::users::Users::new(pool.clone());
+                match users_manager.set_role(&email, &role).await {
+                    Ok(()) => println!("User {} is now {:?}", email, role),
+                    Err(e) => eprintln!("Error changing role: {}", e),
+                }
+            },
+            Commands::ImportProducts { file, dry_run } => {
+                if let Err(e) = import_products(&pool, &file, dry_run).await {
+                    eprintln!("Error importing products: {}", e);
+                }
+            },
+            Commands::ExportProducts { file } => {
+                if let Err(e) = export_products(&pool, &file).await {
+                    eprintln!("Error exporting products: {}", e);
+                }
+            },
+            Commands::ImportWoocommerce { dir, dry_run, weight_unit } => {
+                if let Err(e) = import_woocommerce(&pool, &dir, dry_run, &weight_unit).await {
+                    eprintln!("Error importing the WooCommerce export: {}", e);
+                }
+            },
+        }
+        return;
+    }
+
+    // Starts and ends the scheduled sales
+    tokio::spawn(models::products::schedule_sales(pool.clone()));
+
+    // Empties the trash of the products older than the trash_days of the settings
+    tokio::spawn(models::products::schedule_trash_purge(pool.clone()));
+
     // https://github.com/maxcountryman/tower-sessions
     // => \dt *.*
     let session_store = PostgresStore::new(pool.clone())
@@ -241,32 +481,76 @@ async fn main() {
     };
     tera.register_filter("round_and_format", utils::round_and_format_filter);
     tera.register_function("shortcode", controllers::frontend::shortcodes::make_shortcode());
+    tera.register_function("csrf_token", controllers::csrf::make_csrf_token());
+    tera.register_function("can", controllers::auth::make_can());
 
     let admin_router = Router::new()
-        .route("/media", get(controllers::backend::media::library))
-        // .route("/products/:id/media/update", post(admin::products::media::update))
-        // .route("/products/:id/media/add", post(admin::products::media::add))
-        // .route("/products/:id/media", post(admin::products::media::select))
-        // backend orders
-        .route("/orders/new", get(controllers::backend::orders::new))
-        .route("/orders/:id", get(controllers::backend::orders::edit))
-        .route("/orders", get(controllers::backend::orders::list))
-        // backend categories
-        .route("/categories/new", get(controllers::backend::categories::new))
-        .route("/categories/:id", get(controllers::backend::categories::edit))
-        .route("/categories", get(controllers::backend::categories::list))
-        // backend products
-        .route("/products/:id", get(controllers::backend::products::edit)
-            .post(controllers::backend::products::handle))
-        .route("/products/new", get(controllers::backend::products::new))
-        .route("/products", get(controllers::backend::products::list))
-        // backend users
-        .route("/users/new", get(controllers::backend::users::new))
-        .route("/users/:id", get(controllers::backend::users::edit))
-        .route("/users", get(controllers::backend::users::list))
+        .merge(Router::new()
+            .route("/media", get(controllers::backend::media::library))
+            // .route("/products/:id/media/update", post(admin::products::media::update))
+            // .route("/products/:id/media/add", post(admin::products::media::add))
+            // .route("/products/:id/media", post(admin::products::media::select))
+            // backend categories
+            .route("/categories/new", get(controllers::backend::categories::new))
+            .route("/categories/:id", get(controllers::backend::categories::edit))
+            .route("/categories", get(controllers::backend::categories::list))
+            // backend tags
+            .route("/tags/:id/delete", post(controllers::backend::tags::delete))
+            .route("/tags/:id", get(controllers::backend::tags::edit)
+                .post(controllers::backend::tags::update))
+            .route("/tags", get(controllers::backend::tags::list)
+                .post(controllers::backend::tags::add))
+            // backend products
+            .route("/products/:id", get(controllers::backend::products::edit)
+                .post(controllers::backend::products::handle))
+            .route("/products/:id/duplicate", post(controllers::backend::products::duplicate))
+            .route("/products/:id/attributes", post(controllers::backend::variations::update_attributes))
+            .route("/products/:id/variations", get(controllers::backend::variations::edit)
+                .post(controllers::backend::variations::update))
+            .route("/products/:id/revisions", get(controllers::backend::revisions::list))
+            .route("/products/:id/revisions/:revision/rollback", post(controllers::backend::revisions::rollback))
+            .route("/products/new", get(controllers::backend::products::new))
+            .route("/products/bulk", post(controllers::backend::products::bulk_edit))
+            .route("/products/delete", post(controllers::backend::products::delete))
+            .route("/products/export", get(controllers::backend::product_csv::export))
+            .route("/products/import", get(controllers::backend::product_csv::import_form)
+                .post(controllers::backend::product_csv::import))
+            .route("/products", get(controllers::backend::products::list))
+            .route_layer(from_fn_with_state(Capability::ManageProducts, controllers::auth::require_capability)))
+        .merge(Router::new()
+            // backend orders
+            .route("/orders/new", get(controllers::backend::orders::new))
+            .route("/orders/:id", get(controllers::backend::orders::edit)
+                .post(controllers::backend::orders::update))
+            .route("/orders", get(controllers::backend::orders::list))
+            .route_layer(from_fn_with_state(Capability::ManageOrders, controllers::auth::require_capability)))
+        .merge(Router::new()
+            // backend reviews
+            .route("/reviews/:id/status", post(controllers::backend::reviews::update_status))
+            .route("/reviews", get(controllers::backend::reviews::list))
+            .route_layer(from_fn_with_state(Capability::ModerateReviews, controllers::auth::require_capability)))
+        .merge(Router::new()
+            // backend users
+            .route("/users/new", get(controllers::backend::users::new))
+            .route("/users/:id/unlock", post(controllers::backend::users::unlock))
+            .route("/users/:id/sessions/revoke", post(controllers::backend::users::revoke_sessions))
+            .route("/users/:id/sessions/:session_id/revoke", post(controllers::backend::users::revoke_session))
+            .route("/users/:id", get(controllers::backend::users::edit))
+            .route("/users", get(controllers::backend::users::list))
+            .route_layer(from_fn_with_state(Capability::ManageUsers, controllers::auth::require_capability)))
+        .merge(Router::new()
+            // backend settings
+            .route("/settings", get(controllers::backend::settings::edit)
+                .post(controllers::backend::settings::update))
+            .route_layer(from_fn_with_state(Capability::ManageSettings, controllers::auth::require_capability)))
+        .merge(Router::new()
+            // backend audit log
+            .route("/audit", get(controllers::backend::audit::list))
+            .route_layer(from_fn_with_state(Capability::ViewAuditLog, controllers::auth::require_capability)))
         // admin
         .route("/sidebar", get(controllers::admin::sidebar))
-        .route("/", get(controllers::admin::dashboard));
+        .route("/", get(controllers::admin::dashboard))
+        .route_layer(from_fn_with_state(Capability::AccessBackend, controllers::auth::require_capability));
 
     let app = Router::new()
         .nest("/admin", admin_router)
@@ -275,15 +559,46 @@ async fn main() {
         // .route("/test", get(|| async { "Hello, World!" }))
         .route("/login", get(controllers::auth::login)
             .post(autentication))
+        .route("/login/two-factor", get(controllers::auth::two_factor_form)
+            .post(controllers::auth::two_factor))
+        .route("/forgot-password", get(controllers::auth::forgot_password_form)
+            .post(controllers::auth::forgot_password))
+        .route("/reset-password", get(controllers::auth::reset_password_form)
+            .post(controllers::auth::reset_password))
+        .route("/register", get(controllers::frontend::account::register_form)
+            .post(controllers::frontend::account::register))
+        .route("/my-account/orders/:id", get(controllers::frontend::account::order))
+        .route("/my-account/orders", get(controllers::frontend::account::orders))
+        .route("/my-account/addresses", get(controllers::frontend::account::addresses)
+            .post(controllers::frontend::account::update_addresses))
+        .route("/my-account/profile", get(controllers::frontend::account::profile)
+            .post(controllers::frontend::account::update_profile))
+        .route("/my-account/two-factor", get(controllers::frontend::account::two_factor)
+            .post(controllers::frontend::account::enable_two_factor))
+        .route("/my-account/sessions/revoke-others", post(controllers::frontend::account::revoke_other_sessions))
+        .route("/my-account/sessions/:id/revoke", post(controllers::frontend::account::revoke_session))
+        .route("/my-account/sessions", get(controllers::frontend::account::sessions))
+        .route("/my-account/two-factor/disable", post(controllers::frontend::account::disable_two_factor))
+        .route("/my-account/verify-email", get(controllers::frontend::account::verify_email)
+            .post(controllers::frontend::account::resend_email_verification))
+        .route("/my-account", get(controllers::frontend::account::dashboard))
         .route("/checkout", get(controllers::frontend::checkout::show)
             .post(controllers::frontend::checkout::place_order))
         .route("/cart/update", post(controllers::frontend::cart::update_cart))
         .route("/cart/add", post(controllers::frontend::cart::add_to_cart))
+        .route("/cart/add-group", post(controllers::frontend::cart::add_group_to_cart))
         .route("/cart", get(controllers::frontend::cart::show))
         .route("/products", get(controllers::frontend::products::list))
         .route("/product-category/:slug", get(controllers::frontend::products::product_category))
+        .route("/product-tag/:slug", get(controllers::frontend::products::product_tag))
         .route("/product/:slug", get(controllers::frontend::products::product))
+        .route("/product/:slug/reviews", post(controllers::frontend::reviews::add))
+        .route("/search/suggestions", get(controllers::frontend::search::suggestions))
+        .route("/search", get(controllers::frontend::search::search))
         .route("/shortcode/products", get(controllers::frontend::shortcodes::products))
+        .route("/shortcode/best-sellers", get(controllers::frontend::shortcodes::best_sellers))
+        .route("/shortcode/linked-products", get(controllers::frontend::shortcodes::linked_products))
+        .layer(from_fn(controllers::csrf::protect))
         .layer(Extension(pool))
         .layer(Extension(tera))
         // .nest_service("/assets", ServeDir::new("static/assets"))
@@ -296,7 +611,7 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(&format!("0.0.0.0:{}", args.port))
         .await
         .unwrap();
-    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
+    axum::serve(listener, ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app))
         // .with_graceful_shutdown(async {})
         .await
         .unwrap();
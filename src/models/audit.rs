@@ -0,0 +1,272 @@
+//
+// Description: Audit log of back-office changes
+// Last Modification: 2026-10-18 10:00:58
+//
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use serde_json::{Map, Value as JsonValue};
+use sqlx::{
+    postgres::PgRow,
+    Postgres,
+    QueryBuilder,
+    Row,
+};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+
+tokio::task_local! {
+    // Set by the admin router for the duration of the request, changes made
+    // outside of it (e.g. from the command line) are recorded without an actor
+    pub static ACTOR: Actor;
+}
+
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub user_id: i32,
+    pub ip_address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub actor: String,
+    pub ip_address: String,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: i32,
+    pub before: JsonValue,
+    pub after: JsonValue,
+    pub date_created: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntryPage {
+    pub entries: Vec<Entry>,
+    pub total_count: i32,
+    pub current_page: i32,
+    pub per_page: i32,
+    pub total_pages: i32,
+}
+
+// Filters come from a form, empty fields are sent as empty strings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Parameters {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub actor: Option<String>, // user id
+    pub action: Option<String>,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub after: Option<String>, // date, YYYY-MM-DD
+    pub before: Option<String>, // date, YYYY-MM-DD
+}
+
+impl Parameters {
+    // The filters as a query string, used by the pagination links
+    pub fn filters_query(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        for (key, value) in [
+            ("actor", &self.actor),
+            ("action", &self.action),
+            ("entity_type", &self.entity_type),
+            ("entity_id", &self.entity_id),
+            ("after", &self.after),
+            ("before", &self.before),
+        ] {
+            if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+                query.append_pair(key, value);
+            }
+        }
+        query.finish()
+    }
+}
+
+fn parse_id(id: &Option<String>) -> Option<i32> {
+    id.as_ref().and_then(|id| id.trim().parse::<i32>().ok())
+}
+
+// Keeps only the top level fields that changed, so an update of the stock
+// does not store the whole product description twice
+fn diff(before: Option<JsonValue>, after: Option<JsonValue>) -> (JsonValue, JsonValue) {
+    match (before, after) {
+        (Some(JsonValue::Object(before)), Some(JsonValue::Object(after))) => {
+            let mut old = Map::new();
+            let mut new = Map::new();
+
+            for (key, value) in &after {
+                if before.get(key) != Some(value) {
+                    old.insert(key.clone(), before.get(key).cloned().unwrap_or(JsonValue::Null));
+                    new.insert(key.clone(), value.clone());
+                }
+            }
+            for (key, value) in &before {
+                if !after.contains_key(key) {
+                    old.insert(key.clone(), value.clone());
+                    new.insert(key.clone(), JsonValue::Null);
+                }
+            }
+
+            (JsonValue::Object(old), JsonValue::Object(new))
+        },
+        (before, after) => (
+            before.unwrap_or(JsonValue::Null),
+            after.unwrap_or(JsonValue::Null),
+        ),
+    }
+}
+
+fn parse_date(date: &Option<String>) -> Option<NaiveDateTime> {
+    date.as_ref()
+        .and_then(|date| chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+}
+
+fn push_where<'args>(parameters: &'args Parameters, query: &mut QueryBuilder<'args, Postgres>) {
+    query.push(" WHERE TRUE");
+
+    if let Some(actor) = parse_id(&parameters.actor) {
+        query.push(" AND actor_id = ").push_bind(actor);
+    }
+    if let Some(action) = parameters.action.as_ref().filter(|action| !action.is_empty()) {
+        query.push(" AND action = ").push_bind(action);
+    }
+    if let Some(entity_type) = parameters.entity_type.as_ref().filter(|entity_type| !entity_type.is_empty()) {
+        query.push(" AND entity_type = ").push_bind(entity_type);
+    }
+    if let Some(entity_id) = parse_id(&parameters.entity_id) {
+        query.push(" AND entity_id = ").push_bind(entity_id);
+    }
+    if let Some(after) = parse_date(&parameters.after) {
+        query.push(" AND date_created >= ").push_bind(after);
+    }
+    // The whole day is included
+    if let Some(before) = parse_date(&parameters.before) {
+        query.push(" AND date_created < ").push_bind(before + chrono::Duration::days(1));
+    }
+}
+
+// Writes the entry with the given executor, a change made in a transaction
+// is recorded with it and rolled back with it
+pub async fn record_with<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    action: &str,
+    entity_type: &str,
+    entity_id: i32,
+    before: Option<JsonValue>,
+    after: Option<JsonValue>) -> Result<(), anyhow::Error> {
+
+    let (before, after) = diff(before, after);
+
+    // Nothing changed, e.g. a product form saved as it was
+    if before == JsonValue::Object(Map::new()) && after == JsonValue::Object(Map::new()) {
+        return Ok(());
+    }
+
+    let actor = ACTOR.try_with(|actor| actor.clone()).ok();
+
+    // The username is copied, the entry stays readable if the user is removed
+    sqlx::query(r#"
+        INSERT INTO audit_log (actor_id, actor, ip_address, action, entity_type, entity_id, before, after)
+        VALUES ($1, COALESCE((SELECT username FROM users WHERE id = $1), 'system'), $2, $3, $4, $5, $6, $7);
+    "#)
+        .bind(actor.as_ref().map(|actor| actor.user_id))
+        .bind(actor.as_ref().map(|actor| actor.ip_address.as_str()).unwrap_or_default())
+        .bind(action)
+        .bind(entity_type)
+        .bind(entity_id)
+        .bind(before)
+        .bind(after)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub struct Audit {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl Audit {
+
+    // e.g. record("product.update", "product", id, Some(json!(before)), Some(json!(after)))
+    pub async fn record(&self,
+        action: &str,
+        entity_type: &str,
+        entity_id: i32,
+        before: Option<JsonValue>,
+        after: Option<JsonValue>) -> Result<(), anyhow::Error> {
+
+        record_with(&self.pool, action, entity_type, entity_id, before, after).await
+    }
+
+    pub async fn actions(&self) -> Result<Vec<String>, anyhow::Error> {
+        let rows = sqlx::query(r#"
+            SELECT DISTINCT action FROM audit_log ORDER BY action;
+        "#)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| row.get::<String, _>("action")).collect())
+    }
+
+    pub async fn get_page(&self, parameters: &Parameters) -> Result<EntryPage, anyhow::Error> {
+        let per_page = parameters.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, 100) as i32;
+
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM audit_log");
+        push_where(parameters, &mut query);
+
+        let total: i64 = query
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        let total_pages = ((total as f32 / per_page as f32).ceil() as i32).max(1);
+        let page = (parameters.page.unwrap_or(1) as i32).clamp(1, total_pages);
+
+        let mut query = QueryBuilder::new(r#"
+            SELECT id, actor_id, actor, ip_address, action, entity_type, entity_id, before, after, date_created
+            FROM audit_log"#);
+        push_where(parameters, &mut query);
+        query.push(" ORDER BY id DESC LIMIT ")
+            .push_bind(per_page)
+            .push(" OFFSET ")
+            .push_bind((page - 1) * per_page);
+
+        let entries = query
+            .build()
+            .map(|row: PgRow| Entry {
+                id: row.get::<i32, _>("id"),
+                actor_id: row.get::<Option<i32>, _>("actor_id"),
+                actor: row.get::<String, _>("actor"),
+                ip_address: row.get::<String, _>("ip_address"),
+                action: row.get::<String, _>("action"),
+                entity_type: row.get::<String, _>("entity_type"),
+                entity_id: row.get::<i32, _>("entity_id"),
+                before: row.get::<JsonValue, _>("before"),
+                after: row.get::<JsonValue, _>("after"),
+                date_created: row.get::<NaiveDateTime, _>("date_created").format("%Y/%m/%d %H:%M:%S").to_string(),
+            })
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(EntryPage {
+            entries,
+            total_count: total as i32,
+            current_page: page,
+            per_page,
+            total_pages,
+        })
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        Audit {
+            pool,
+        }
+    }
+}
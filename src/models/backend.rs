@@ -1,11 +1,14 @@
 //
-// Last Modification: 2024-08-14 19:19:01
+// Last Modification: 2026-10-18 10:20:51
 //
 
 use crate::models::products::StockStatus;
 use crate::models::products::Status;
+use crate::models::products::ProductType;
+use crate::models::products::CatalogVisibility;
 use crate::models::products::ProductImage;
 
+use chrono::NaiveDateTime;
 use sqlx::types::Json;
 
 use serde::{
@@ -29,6 +32,7 @@ pub struct ProductShort {
     pub stock_quantity: i32,
     pub image_src: String,
     pub image_alt: String,
+    pub total_sales: i32, // see orders::OrderStatus::counts_sales
     pub date_created: String,
     pub status: Status,
 }
@@ -46,13 +50,23 @@ pub struct Product {
     pub regular_price: f32, // product regular price
     pub sale_price: f32, // product sale price
     pub on_sale: bool, // shows if the product is on sale (read-only)
+    pub date_on_sale_from: Option<NaiveDateTime>, // start of the sale, none starts it at once
+    pub date_on_sale_to: Option<NaiveDateTime>, // end of the sale, none keeps it on
     pub stock_status: StockStatus,
     pub stock_quantity: i32,
     pub weight: u32,
     pub status: Status,
+    pub product_type: ProductType,
+    pub catalog_visibility: CatalogVisibility,
+    pub external_url: String, // external products only
+    pub button_text: String, // external products only
+    pub grouped_products: Vec<i32>, // grouped products only, in display order
+    pub upsells: Vec<i32>, // in display order, see LinkType
+    pub cross_sells: Vec<i32>, // in display order, see LinkType
     pub primary_category: i32,
     pub categories: Vec<i32>,
-    pub images: Vec<ProductImage>
+    pub images: Vec<ProductImage>,
+    pub tags: Vec<String>, // tag names
 }
 
 
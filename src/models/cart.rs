@@ -1,14 +1,18 @@
 //
 // Description: Shopping cart management
-// Last Modification: 2024-08-01 16:47:46
+// Last Modification: 2026-10-18 09:19:10
 //
 
+use crate::models::products;
+use crate::models::variations;
+
 use anyhow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use num_traits::ToPrimitive;
 use serde::{Serialize, Deserialize};
 
 use sqlx::{
+    postgres::PgRow,
     types::{Decimal, Json},
     FromRow,
     Row,
@@ -26,8 +30,11 @@ struct Media {
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct Product {
+    pub key: String, // cart item key, see item_key
     pub id: i32,
+    pub variation_id: i32, // 0 when the product has no variations
     pub name: String,
+    pub variation: String, // chosen options, e.g. "M, Red"
     pub sku: String,
     pub price: f32,
     pub regular_price: f32,
@@ -37,15 +44,123 @@ pub struct Product {
     image: Json<Media>,
 }
 
+impl Product {
+    // The name shown on the order, e.g. "T-Shirt - M, Red"
+    pub fn full_name(&self) -> String {
+        if self.variation.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} - {}", self.name, self.variation)
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProductToCart {
     product_id: i32,
+    variation_id: i32,
     product_quantity: i32,
+    attributes: BTreeMap<String, String>, // chosen options when the variation is not known
+}
+
+impl ProductToCart {
+    // product_id=1&variation_id=2&product_quantity=1, or attribute_<name>=<option>
+    // fields instead of variation_id when the page is used without javascript
+    pub fn parse(raw_form_data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut product_to_cart = ProductToCart {
+            product_id: 0,
+            variation_id: 0,
+            product_quantity: 1,
+            attributes: BTreeMap::new(),
+        };
+
+        for (key, value) in url::form_urlencoded::parse(raw_form_data) {
+            match key.as_ref() {
+                "product_id" => product_to_cart.product_id = value.parse()?,
+                "variation_id" => product_to_cart.variation_id = value.parse().unwrap_or(0),
+                "product_quantity" => product_to_cart.product_quantity = value.parse()?,
+                "csrf_token" => {}, // checked by the csrf middleware
+                _ => match key.strip_prefix("attribute_") {
+                    Some(name) if !value.is_empty() => {
+                        product_to_cart.attributes.insert(name.to_string(), value.to_string());
+                    },
+                    _ => eprintln!("Invalid cart field: {:?}", key),
+                },
+            }
+        }
+
+        if product_to_cart.product_id <= 0 || product_to_cart.product_quantity <= 0 {
+            return Err(anyhow::anyhow!("invalid product or quantity"));
+        }
+
+        Ok(product_to_cart)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupToCart {
+    product_id: i32, // the grouped product
+    quantities: Vec<(i32, i32)>, // product id, quantity
+}
+
+impl GroupToCart {
+    // product_id=10&quantity_12=1&quantity_14=0, products left at 0 are not added
+    pub fn parse(raw_form_data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut group_to_cart = GroupToCart {
+            product_id: 0,
+            quantities: vec![],
+        };
+
+        for (key, value) in url::form_urlencoded::parse(raw_form_data) {
+            match key.as_ref() {
+                "product_id" => group_to_cart.product_id = value.parse()?,
+                "csrf_token" => {}, // checked by the csrf middleware
+                _ => match key.strip_prefix("quantity_") {
+                    Some(id) => {
+                        let quantity: i32 = value.parse().unwrap_or(0);
+                        if quantity > 0 {
+                            group_to_cart.quantities.push((id.parse()?, quantity));
+                        }
+                    },
+                    None => eprintln!("Invalid cart field: {:?}", key),
+                },
+            }
+        }
+
+        if group_to_cart.quantities.is_empty() {
+            return Err(anyhow::anyhow!("no product was chosen"));
+        }
+
+        Ok(group_to_cart)
+    }
+}
+
+// The cart holds one line per product, or per variation of a variable product:
+// "12" for product 12, "12-5" for its variation 5
+pub fn item_key(product_id: i32, variation_id: i32) -> String {
+    if variation_id == 0 {
+        product_id.to_string()
+    } else {
+        format!("{}-{}", product_id, variation_id)
+    }
+}
+
+pub fn parse_item_key(key: &str) -> Option<(i32, i32)> {
+    let (product_id, variation_id) = match key.split_once('-') {
+        Some((product_id, variation_id)) => (product_id.parse().ok()?, variation_id.parse().ok()?),
+        None => (key.parse().ok()?, 0),
+    };
+
+    if product_id <= 0 || variation_id < 0 {
+        return None;
+    }
+
+    Some((product_id, variation_id))
 }
 
 pub struct Cart<'a> {
     pool: sqlx::Pool<sqlx::Postgres>,
-    purchases: &'a mut HashMap<i32, i32>,
+    purchases: &'a mut HashMap<String, i32>,
     pub total_weight: u32,
     pub total_order: f32,
 }
@@ -62,37 +177,78 @@ impl<'a> Cart<'a> {
         self.total_order = 0.0;
     }
 
-    pub fn add(&mut self, product_to_cart: &ProductToCart) {
-        if self.purchases.contains_key(&product_to_cart.product_id) {
-            if let Some(value) = self.purchases.get_mut(&product_to_cart.product_id) {
-                *value += product_to_cart.product_quantity;
-            }
+    pub async fn add(&mut self, product_to_cart: &ProductToCart) -> Result<(), anyhow::Error> {
+        let mut variation_id = product_to_cart.variation_id;
+        if variation_id == 0 && !product_to_cart.attributes.is_empty() {
+            variation_id = variations::Variations::new(self.pool.clone())
+                .find(product_to_cart.product_id, &product_to_cart.attributes)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("the chosen options are not available"))?;
+        }
+
+        let key = item_key(product_to_cart.product_id, variation_id);
+        if let Some(value) = self.purchases.get_mut(&key) {
+            *value += product_to_cart.product_quantity;
         } else {
-            self.purchases.insert(product_to_cart.product_id, product_to_cart.product_quantity);
+            self.purchases.insert(key, product_to_cart.product_quantity);
+        }
+
+        Ok(())
+    }
+
+    // Adds the chosen products of a grouped product, only the products of the group are accepted
+    pub async fn add_group(&mut self, group_to_cart: &GroupToCart) -> Result<(), anyhow::Error> {
+        let children: Vec<i32> = sqlx::query(r#"
+            SELECT child_id FROM grouped_products WHERE product_id = $1;
+        "#)
+            .bind(group_to_cart.product_id)
+            .map(|row: PgRow| row.get::<i32, _>("child_id"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        for (product_id, quantity) in &group_to_cart.quantities {
+            if !children.contains(product_id) {
+                return Err(anyhow::anyhow!("the product {} is not part of the group", product_id));
+            }
+
+            let key = item_key(*product_id, 0);
+            if let Some(value) = self.purchases.get_mut(&key) {
+                *value += quantity;
+            } else {
+                self.purchases.insert(key, *quantity);
+            }
         }
+
+        Ok(())
     }
 
     pub fn update(&mut self, raw_form_data: &str) {
 
         let v: Vec<&str> = raw_form_data.split('&').collect();
     
-        let mut current_key = 0;
+        let mut current_key = "".to_string();
         for key_value in v.iter() {
             key_value.split_once('=').map(|(k, v)| {
                 println!("key: {}, value: {}", k, v);
                 match k {
                     "id" => {
-                        current_key = v.parse::<i32>().unwrap();
-                        self.purchases.insert(current_key, 0);
+                        current_key = match parse_item_key(v) {
+                            Some((product_id, variation_id)) => item_key(product_id, variation_id),
+                            None => "".to_string(),
+                        };
+                        if !current_key.is_empty() {
+                            self.purchases.insert(current_key.to_string(), 0);
+                        }
                     },
                     "quantity" => {
                         if let Some(value) = self.purchases.get_mut(&current_key) {
-                            *value += &v.parse::<i32>().unwrap();
+                            *value += v.parse::<i32>().unwrap_or(0);
                         }
                     },
                     "remove" => {
                         self.purchases.remove(&current_key);
                     },
+                    "csrf_token" => {}, // checked by the csrf middleware
                     _ => {
                         eprintln!("Invalid cart key: {:?}", current_key);
                     }
@@ -103,12 +259,43 @@ impl<'a> Cart<'a> {
 
 
     pub async fn get(&mut self) -> Result<Vec<Product>, anyhow::Error> {
-        let product_ids: Vec<i32> = self.purchases.keys().cloned().collect();
+        let mut keys: Vec<String> = vec![];
+        let mut product_ids: Vec<i32> = vec![];
+        let mut variation_ids: Vec<i32> = vec![];
+        for key in self.purchases.keys() {
+            if let Some((product_id, variation_id)) = parse_item_key(key) {
+                keys.push(key.to_string());
+                product_ids.push(product_id);
+                variation_ids.push(variation_id);
+            }
+        }
 
-        let products = sqlx::query(r#"
+        // A variable product is only sold through one of its variations, grouped
+        // products through their own products and external ones on another site
+        let products = sqlx::query(&format!(r#"
             SELECT
-                products.id, products.name, products.sku, products.permalink, products.price, products.regular_price,
-                products.stock_quantity, products.weight, products.stock_status, (
+                items.key, products.id, COALESCE(product_variations.id, 0) AS variation_id,
+                products.name, products.permalink,
+                COALESCE(NULLIF(product_variations.sku, ''), products.sku) AS sku,
+                COALESCE(product_variations.price, {}) AS price,
+                COALESCE(product_variations.regular_price, products.regular_price) AS regular_price,
+                COALESCE(product_variations.stock_quantity, products.stock_quantity) AS stock_quantity,
+                COALESCE(product_variations.weight, products.weight) AS weight,
+                COALESCE((
+                    SELECT STRING_AGG(options.value, ', ' ORDER BY product_attributes.position)
+                    FROM jsonb_each_text(product_variations.attributes) AS options
+                    JOIN product_attributes ON product_attributes.product_id = products.id
+                        AND product_attributes.name = options.key), '') AS variation,
+                COALESCE((
+                SELECT json_build_object(
+                    'id', media.id,
+                    'src', media.src,
+                    'name', media.name,
+                    'alt', media.alt,
+                    'date_created', date_created,
+                    'date_modified', date_modified)
+                FROM media
+                WHERE media.id = product_variations.media_id), (
                 SELECT json_build_object(
                     'id', media.id,
                     'src', media.src,
@@ -118,16 +305,28 @@ impl<'a> Cart<'a> {
                     'date_modified', date_modified)
                 FROM product_media, media
                 WHERE product_media.media_id = media.id AND product_media.product_id = products.id
-                ORDER BY product_media.position DESC LIMIT 1) AS image
-            FROM products
-            WHERE products.id = ANY($1) AND products.status = 'publish';
-        "#)
+                ORDER BY product_media.position DESC LIMIT 1)) AS image
+            FROM UNNEST($1::TEXT[], $2::INT[], $3::INT[]) AS items(key, product_id, variation_id)
+            JOIN products ON products.id = items.product_id
+            LEFT JOIN product_variations ON product_variations.id = items.variation_id
+                AND product_variations.product_id = products.id
+            WHERE products.status = 'publish'
+                AND CASE products.type
+                    WHEN 'simple' THEN product_variations.id IS NULL
+                    WHEN 'variable' THEN product_variations.id IS NOT NULL
+                    ELSE FALSE END;
+        "#, products::PRICE))
+            .bind(keys)
             .bind(product_ids)
+            .bind(variation_ids)
             .map(|row| -> Product {
 
                 let product = Product {
+                    key: row.get::<String, _>("key"),
                     id: row.get::<i32, _>("id"),
+                    variation_id: row.get::<i32, _>("variation_id"),
                     name: row.get::<String, _>("name"),
+                    variation: row.get::<String, _>("variation"),
                     sku: row.get::<String, _>("sku"),
                     price: match row.get::<Decimal, _>("price").to_f32() {
                         Some(f) => f,
@@ -137,14 +336,14 @@ impl<'a> Cart<'a> {
                         Some(f) => f,
                         None => 0.00,
                     },
-                    quantity: match self.purchases.get(&row.get::<i32, _>("id")) {
+                    quantity: match self.purchases.get(&row.get::<String, _>("key")) {
                         Some(q) => {
-                            let id = &row.get::<i32, _>("id");
+                            let key = &row.get::<String, _>("key");
                             let stock_quantity = row.get::<i32, _>("stock_quantity");
                             
                             let mut quantity = *q;
                             if stock_quantity == 0 {
-                                self.purchases.remove(id);
+                                self.purchases.remove(key);
                                 quantity = 0;
                             } else if *q > stock_quantity {
                                 quantity = stock_quantity;
@@ -168,13 +367,16 @@ impl<'a> Cart<'a> {
             .fetch_all(&self.pool)
             .await?;
 
+        // Forget what can no longer be bought, e.g. a removed variation
+        self.purchases.retain(|key, _| products.iter().any(|product| product.key == *key));
+
         Ok(products)
     }
 
 
     pub fn new(
         pool: sqlx::Pool<sqlx::Postgres>,
-        purchases: &'a mut HashMap<i32, i32>) -> Self {
+        purchases: &'a mut HashMap<String, i32>) -> Self {
 
         Cart {
             pool,
@@ -1,8 +1,9 @@
 //
-// Last Modification: 2024-08-14 19:17:46
+// Last Modification: 2026-10-18 11:40:43
 //
 
 use crate::types;
+use crate::models::audit;
 use crate::models::backend;
 use crate::models::media;
 
@@ -114,6 +115,8 @@ impl<'a> Backend<'a> {
 
     pub async fn add(&self, name: &str, parent: i32) -> Result<i32, anyhow::Error> {
         // Implementation to add a new category
+        let mut transaction = self.pool.begin().await?;
+
         let category_id: i32 = sqlx::query(r#"
             INSERT INTO categories (name, slug, parent)
             VALUES ($1, $2, $3) RETURNING id;
@@ -121,10 +124,16 @@ impl<'a> Backend<'a> {
             .bind(&name)
             .bind(slugify(&name))
             .bind(&parent)
-            .fetch_one(self.pool)
+            .fetch_one(&mut *transaction)
             .await?
             .get(0);
 
+        audit::record_with(&mut *transaction, "category.create", "category", category_id, None,
+            Some(serde_json::json!({ "name": name, "slug": slugify(name), "parent": parent })))
+            .await?;
+
+        transaction.commit().await?;
+
         Ok(category_id)
     }
 
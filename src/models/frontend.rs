@@ -1,9 +1,11 @@
 //
-// Last Modification: 2024-08-14 19:18:21
+// Last Modification: 2026-10-18 10:17:38
 //
 
 use crate::models::products::StockStatus;
 use crate::models::products::Media;
+use crate::models::products::ProductType;
+use crate::models::products::Category;
 
 use sqlx::types::Json;
 
@@ -28,6 +30,9 @@ pub struct ProductShort {
     pub stock_quantity: i32,
     pub stock_status: StockStatus,
     pub weight: u32,
+    pub product_type: ProductType,
+    pub external_url: String, // external products only
+    pub button_text: String, // external products only
     pub gallery: Json<Vec<Media>>,
 }
 
@@ -47,6 +52,11 @@ pub struct Product {
     pub stock_quantity: i32,
     pub stock_status: StockStatus,
     pub weight: u32,
+    pub product_type: ProductType,
+    pub external_url: String, // external products only
+    pub button_text: String, // external products only
+    pub average_rating: f32, // of the approved reviews
+    pub rating_count: i32,
     // categories: Vec<Category>,
     pub gallery: Json<Vec<Media>>,
 }
@@ -74,4 +84,53 @@ impl ProductPage {
             max_price: 0.00,
         }
     }
-}
\ No newline at end of file
+}
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub product: ProductShort,
+    pub name_highlight: String, // html, the matched words are in <mark>
+    pub snippet: String, // html, the matched words are in <mark>
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub total_count: i32,
+    pub current_page: i32,
+    pub per_page: i32,
+    pub total_pages: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub id: i32,
+    pub name: String,
+    pub permalink: String,
+    pub price: f32,
+    pub image: Option<String>,
+}
+
+// One value of a facet, the count is the number of products of the current
+// result set that would remain if the value was picked
+#[derive(Debug, Serialize)]
+pub struct FacetOption {
+    pub value: String, // as sent in the query string
+    pub label: String,
+    pub count: i64,
+    pub selected: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttributeFacet {
+    pub name: String,
+    pub options: Vec<FacetOption>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Facets {
+    pub categories: Vec<Category>, // the whole tree, the counts are for the current result set
+    pub tags: Vec<FacetOption>,
+    pub attributes: Vec<AttributeFacet>,
+    pub stock_statuses: Vec<FacetOption>,
+    pub ratings: Vec<FacetOption>,
+}
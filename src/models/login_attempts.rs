@@ -0,0 +1,295 @@
+//
+// Last Modification: 2026-10-18 11:31:08
+//
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc, Duration};
+use serde::Serialize;
+use sqlx::{
+    postgres::PgRow,
+    Row,
+};
+
+// Failures allowed before the backoff starts
+const USER_FREE_ATTEMPTS: i32 = 3;
+const IP_FREE_ATTEMPTS: i32 = 10; // an address can be shared by many customers
+
+// The backoff doubles with every failure: 1s, 2s, 4s, ... up to 15 minutes
+const MAX_BACKOFF_SECONDS: i64 = 15 * 60;
+
+// After this many failures the account stays locked until an admin unlocks it
+// or the lockout expires
+const LOCKOUT_FAILURES: i32 = 10;
+const LOCKOUT_MINUTES: i64 = 60;
+
+// Failures older than this are forgotten
+const RESET_AFTER_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize)]
+pub struct Lock {
+    pub failures: i32,
+    pub locked_until: Option<String>,
+    pub is_locked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Attempt {
+    pub ip_address: String,
+    pub success: bool,
+    pub date_created: String,
+}
+
+fn user_key(username: &str) -> String {
+    format!("user:{}", username.trim().to_lowercase())
+}
+
+fn ip_key(ip_address: &str) -> String {
+    format!("ip:{}", ip_address)
+}
+
+// The failures once this attempt is counted, they start over after a quiet day
+const FAILURES_SQL: &str = "CASE WHEN login_throttles.last_failure < $3 THEN 1 ELSE login_throttles.failures + 1 END";
+
+// The lock that follows a number of failures, the SQL version of
+// "lockout after LOCKOUT_FAILURES, else 2^(failures - free - 1) seconds"
+fn locked_until_sql(failures: &str, since: &str, free_attempts: &str, lockout: &str) -> String {
+    format!("CASE \
+        WHEN {lockout} AND {failures} >= {lockout_failures} THEN {since} + MAKE_INTERVAL(mins => {minutes}) \
+        WHEN {failures} > {free} THEN {since} + MAKE_INTERVAL(secs => LEAST(POWER(2, LEAST({failures} - {free} - 1, 20)), {max})) \
+        END",
+        failures = failures,
+        since = since,
+        free = free_attempts,
+        lockout = lockout,
+        lockout_failures = LOCKOUT_FAILURES,
+        minutes = LOCKOUT_MINUTES,
+        max = MAX_BACKOFF_SECONDS)
+}
+
+// One statement, the row lock makes parallel attempts wait for each other.
+// Returns false when the key is locked, the attempt is not counted then.
+async fn increment<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    key: &str,
+    free_attempts: i32,
+    lockout: bool,
+) -> Result<bool, anyhow::Error> {
+
+    let now = Utc::now().naive_utc();
+
+    let query = format!(r#"
+        INSERT INTO login_throttles (key, failures, last_failure, locked_until)
+        VALUES ($1, 1, $2, {first_lock})
+        ON CONFLICT (key) DO UPDATE SET
+            failures = {failures},
+            last_failure = EXCLUDED.last_failure,
+            locked_until = {lock}
+        WHERE login_throttles.locked_until IS NULL OR login_throttles.locked_until <= $2
+        RETURNING failures;
+    "#,
+        first_lock = locked_until_sql("1", "$2", "$4", "$5"),
+        failures = FAILURES_SQL,
+        lock = locked_until_sql(&format!("({})", FAILURES_SQL), "$2", "$4", "$5"));
+
+    let row = sqlx::query(&query)
+        .bind(key)
+        .bind(now)
+        .bind(now - Duration::hours(RESET_AFTER_HOURS))
+        .bind(free_attempts)
+        .bind(lockout)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+// Takes back one failure counted by increment, with the lock of one failure less
+async fn decrement<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    key: &str,
+    free_attempts: i32,
+    lockout: bool,
+) -> Result<(), anyhow::Error> {
+
+    let query = format!(r#"
+        UPDATE login_throttles SET
+            failures = failures - 1,
+            locked_until = {lock}
+        WHERE key = $1 AND failures > 0;
+    "#, lock = locked_until_sql("(login_throttles.failures - 1)", "login_throttles.last_failure", "$2", "$3"));
+
+    sqlx::query(&query)
+        .bind(key)
+        .bind(free_attempts)
+        .bind(lockout)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub struct LoginAttempts {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl LoginAttempts {
+
+    // Counts the attempt as a failure before the password or code is checked,
+    // so that parallel guesses can not all pass before one is recorded.
+    // Returns until when the login is blocked, nothing is counted then.
+    pub async fn attempt(&self,
+        username: &str,
+        ip_address: &str,
+    ) -> Result<Option<NaiveDateTime>, anyhow::Error> {
+
+        let mut transaction = self.pool.begin().await?;
+
+        let counted = increment(&mut *transaction, &user_key(username), USER_FREE_ATTEMPTS, true).await?
+            && increment(&mut *transaction, &ip_key(ip_address), IP_FREE_ATTEMPTS, false).await?;
+
+        if counted {
+            transaction.commit().await?;
+            return Ok(None);
+        }
+
+        transaction.rollback().await?;
+
+        let row = sqlx::query(r#"
+            SELECT MAX(locked_until) AS locked_until FROM login_throttles
+            WHERE key IN ($1, $2) AND locked_until > $3;
+        "#)
+            .bind(user_key(username))
+            .bind(ip_key(ip_address))
+            .bind(Utc::now().naive_utc())
+            .fetch_one(&self.pool)
+            .await?;
+
+        // The lock may have just expired, the next attempt goes through
+        Ok(Some(row.get::<Option<NaiveDateTime>, _>("locked_until")
+            .unwrap_or_else(|| Utc::now().naive_utc())))
+    }
+
+    async fn log(&self,
+        username: &str,
+        ip_address: &str,
+        success: bool,
+    ) -> Result<(), anyhow::Error> {
+
+        sqlx::query(r#"
+            INSERT INTO login_attempts (username, ip_address, success) VALUES ($1, $2, $3);
+        "#)
+            .bind(username.trim().to_lowercase())
+            .bind(ip_address)
+            .bind(success)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Takes back the failure counted by attempt, e.g. the password was
+    // right and the code of the two-factor authentication comes next
+    pub async fn release(&self,
+        username: &str,
+        ip_address: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        let mut transaction = self.pool.begin().await?;
+        decrement(&mut *transaction, &user_key(username), USER_FREE_ATTEMPTS, true).await?;
+        decrement(&mut *transaction, &ip_key(ip_address), IP_FREE_ATTEMPTS, false).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    // The failure was counted by attempt, it is only logged
+    pub async fn failure(&self,
+        username: &str,
+        ip_address: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        eprintln!("Failed login for {:?} from {}", username, ip_address);
+
+        self.log(username, ip_address, false).await
+    }
+
+    // Only the username counter is cleared, one valid account must not
+    // reset the failures of an address trying many others
+    pub async fn success(&self,
+        username: &str,
+        ip_address: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        self.log(username, ip_address, true).await?;
+        decrement(&self.pool, &ip_key(ip_address), IP_FREE_ATTEMPTS, false).await?;
+        self.unlock(username).await
+    }
+
+    pub async fn unlock(&self, username: &str) -> Result<(), anyhow::Error> {
+        sqlx::query(r#"
+            DELETE FROM login_throttles WHERE key = $1;
+        "#)
+            .bind(user_key(username))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn lock(&self, username: &str) -> Result<Lock, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT failures, locked_until FROM login_throttles WHERE key = $1;
+        "#)
+            .bind(user_key(username))
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let now = Utc::now().naive_utc();
+
+        Ok(match row {
+            Some(row) => {
+                let locked_until = row.get::<Option<NaiveDateTime>, _>("locked_until");
+                Lock {
+                    failures: row.get::<i32, _>("failures"),
+                    locked_until: locked_until
+                        .map(|date| date.format("%Y/%m/%d at %H:%M:%S UTC").to_string()),
+                    is_locked: locked_until.is_some_and(|date| date > now),
+                }
+            },
+            None => Lock {
+                failures: 0,
+                locked_until: None,
+                is_locked: false,
+            },
+        })
+    }
+
+    pub async fn recent(&self,
+        username: &str,
+        limit: i32,
+    ) -> Result<Vec<Attempt>, anyhow::Error> {
+
+        let attempts = sqlx::query(r#"
+            SELECT ip_address, success, date_created FROM login_attempts
+            WHERE username = $1
+            ORDER BY date_created DESC
+            LIMIT $2;
+        "#)
+            .bind(username.trim().to_lowercase())
+            .bind(limit)
+            .map(|row: PgRow| Attempt {
+                ip_address: row.get::<String, _>("ip_address"),
+                success: row.get::<bool, _>("success"),
+                date_created: row.get::<NaiveDateTime, _>("date_created")
+                    .format("%Y/%m/%d at %H:%M:%S").to_string(),
+            })
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(attempts)
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        LoginAttempts {
+            pool,
+        }
+    }
+}
\ No newline at end of file
@@ -1,16 +1,25 @@
 //
-// Last Modification: 2024-07-24 19:54:12
+// Last Modification: 2026-10-18 11:02:13
 //
 
 pub mod backend;
 pub mod frontend;
 
+pub mod audit;
 pub mod cart;
 pub mod categories;
+pub mod login_attempts;
 pub mod media;
 pub mod orders;
 pub mod products;
+pub mod product_csv;
+pub mod revisions;
+pub mod reviews;
+pub mod settings;
 pub mod shipping;
 pub mod tags;
 pub mod tokens;
-pub mod users;
\ No newline at end of file
+pub mod two_factor;
+pub mod users;
+pub mod variations;
+pub mod woocommerce;
\ No newline at end of file
@@ -5,12 +5,13 @@
 
 use crate::types;
 use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
 use num_traits::ToPrimitive;
 use anyhow;
 
 use sqlx::{
     postgres::PgRow,
-    types::Decimal,
+    types::{Decimal, Json},
     Row,
 };
 
@@ -19,6 +20,12 @@ use serde::{
     Deserialize
 };
 
+use strum::{
+    EnumIter,
+    IntoEnumIterator,
+};
+
+use super::audit;
 use super::products;
 
 #[derive(Debug, Serialize, Deserialize, sqlx::Type)]
@@ -29,7 +36,7 @@ pub enum Currency {
     USD,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
 #[sqlx(type_name = "order_status", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
@@ -44,7 +51,7 @@ pub enum OrderStatus {
 }
 
 impl OrderStatus {
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             OrderStatus::Pending => "pending",
             OrderStatus::Processing => "processing",
@@ -56,6 +63,30 @@ impl OrderStatus {
             OrderStatus::Trash => "trash",
         }
     }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "Pending payment",
+            OrderStatus::Processing => "Processing",
+            OrderStatus::OnHold => "On hold",
+            OrderStatus::Completed => "Completed",
+            OrderStatus::Cancelled => "Cancelled",
+            OrderStatus::Refunded => "Refunded",
+            OrderStatus::Failed => "Failed",
+            OrderStatus::Trash => "Trash",
+        }
+    }
+
+    // From the value of as_str, e.g. a select of the order form
+    pub fn parse(value: &str) -> Option<OrderStatus> {
+        OrderStatus::iter().find(|status| status.as_str() == value)
+    }
+
+    // The items of the paid orders count in the total_sales of the
+    // products, a refund or a cancellation takes them back
+    pub fn counts_sales(&self) -> bool {
+        matches!(self, OrderStatus::Processing | OrderStatus::Completed)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,6 +102,22 @@ pub struct Billing {
     pub tax_id_number: String,
 }
 
+impl Billing {
+    pub fn new() -> Self {
+        Billing {
+            first_name: "".to_string(),
+            last_name: "".to_string(),
+            address: "".to_string(),
+            city: "".to_string(),
+            postcode: "".to_string(),
+            country_code: "".to_string(),
+            email: "".to_string(),
+            phone: "".to_string(),
+            tax_id_number: "".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Shipping {
     pub first_name: String,
@@ -81,10 +128,25 @@ pub struct Shipping {
     pub country_code: String,
 }
 
+impl Shipping {
+    pub fn new() -> Self {
+        Shipping {
+            first_name: "".to_string(),
+            last_name: "".to_string(),
+            address: "".to_string(),
+            city: "".to_string(),
+            postcode: "".to_string(),
+            country_code: "".to_string(),
+        }
+    }
+}
+
 // https://woocommerce.github.io/woocommerce-rest-api-docs/#order-line-items-properties
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LineItem {
     pub product_id: i32,
+    #[serde(default)] // orders placed before variable products
+    pub variation_id: i32,
     pub sku: String,
     pub name: String,
     pub price: f32,
@@ -95,6 +157,19 @@ pub struct LineItem {
     pub total_tax: f32, // after discounts
 }
 
+impl LineItem {
+    // The amounts are kept per unit, so the discount and taxes of the line
+    // follow the new quantity
+    fn set_quantity(&mut self, quantity: i32) {
+        let ratio = quantity as f32 / self.quantity as f32;
+        self.subtotal *= ratio;
+        self.subtotal_tax *= ratio;
+        self.total *= ratio;
+        self.total_tax *= ratio;
+        self.quantity = quantity;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ShippingLine {
     pub total: f32,
@@ -104,6 +179,7 @@ pub struct ShippingLine {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Order {
     pub order_key: String,
+    pub customer_id: i32, // 0 for guests
     pub customer_ip_address: String,
     pub customer_user_agent: String,
     pub billing: Billing,
@@ -126,6 +202,50 @@ pub struct Order {
     pub cart_hash: String,
 }
 
+impl Order {
+    // The same sums as the checkout
+    fn update_totals(&mut self) {
+        self.discount_total = self.line_items.iter().map(|line| line.subtotal - line.total).sum();
+        self.discount_tax = self.line_items.iter().map(|line| line.subtotal_tax - line.total_tax).sum();
+        self.cart_tax = self.line_items.iter().map(|line| line.total_tax).sum();
+        self.total = self.line_items.iter().map(|line| line.total).sum::<f32>() + self.shipping_total;
+        self.total_tax = self.cart_tax + self.shipping_items.iter().map(|line| line.total_tax).sum::<f32>();
+    }
+
+    // The quantities sold by product, none until the order is paid
+    fn sales(&self) -> BTreeMap<i32, i32> {
+        let mut sales = BTreeMap::new();
+        if self.status.counts_sales() {
+            for line in self.line_items.iter() {
+                *sales.entry(line.product_id).or_insert(0) += line.quantity;
+            }
+        }
+        sales
+    }
+
+    // The part of the order recorded in the audit log by update
+    fn audit_snapshot(&self) -> (serde_json::Value, serde_json::Value) {
+        (
+            serde_json::json!({ "status": self.status.as_str() }),
+            serde_json::json!({
+                "line_items": self.line_items,
+                "discount_total": self.discount_total,
+                "discount_tax": self.discount_tax,
+                "cart_tax": self.cart_tax,
+                "total": self.total,
+                "total_tax": self.total_tax,
+            }),
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderDetails {
+    pub id: i32,
+    pub date_created: String,
+    pub order: Order,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OrderShort {
     pub id: i32,
@@ -187,6 +307,94 @@ fn orders_order_by(parameter: &Option<String>) -> &str {
     }
 }
 
+async fn fetch<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    id: i32,
+    for_update: bool,
+) -> Result<OrderDetails, anyhow::Error> {
+
+    // Locks the order until the end of the transaction of an update
+    let lock = if for_update { " FOR UPDATE" } else { "" };
+
+    let row = sqlx::query(&format!(r#"
+        SELECT
+            id, order_key, customer_id, date_created, customer_ip_address, customer_user_agent,
+            customer_note, billing, shipping, line_items, shipping_lines,
+            payment_method, payment_method_title, status, currency,
+            discount_total, discount_tax, shipping_total, shipping_tax, cart_tax,
+            total, total_tax, prices_include_tax, cart_hash
+        FROM orders WHERE id = $1{};
+    "#, lock))
+        .bind(id)
+        .fetch_one(executor)
+        .await?;
+
+    let decimal = |column: &str| -> f32 {
+        row.get::<Decimal, _>(column).to_f32().unwrap_or(0.00)
+    };
+
+    let order = Order {
+        order_key: row.get::<String, _>("order_key"),
+        customer_id: row.get::<i32, _>("customer_id"),
+        customer_ip_address: row.get::<String, _>("customer_ip_address"),
+        customer_user_agent: row.get::<String, _>("customer_user_agent"),
+        billing: row.get::<Json<Billing>, _>("billing").0,
+        shipping: row.get::<Json<Shipping>, _>("shipping").0,
+        line_items: row.get::<Json<Vec<LineItem>>, _>("line_items").0,
+        shipping_items: row.get::<Json<Vec<ShippingLine>>, _>("shipping_lines").0,
+        payment_method: row.get::<String, _>("payment_method"),
+        payment_method_title: row.get::<String, _>("payment_method_title"),
+        currency: row.get::<Currency, _>("currency"),
+        discount_total: decimal("discount_total"),
+        discount_tax: decimal("discount_tax"),
+        shipping_total: decimal("shipping_total"),
+        shipping_tax: decimal("shipping_tax"),
+        cart_tax: decimal("cart_tax"),
+        total: decimal("total"),
+        total_tax: decimal("total_tax"),
+        prices_include_tax: row.get::<bool, _>("prices_include_tax"),
+        customer_note: row.get::<String, _>("customer_note"),
+        status: row.get::<OrderStatus, _>("status"),
+        cart_hash: row.get::<String, _>("cart_hash"),
+    };
+
+    Ok(OrderDetails {
+        id: row.get::<i32, _>("id"),
+        date_created: row.get::<NaiveDateTime, _>("date_created")
+            .format("%b %d, %Y").to_string(),
+        order,
+    })
+}
+
+// Moves the total_sales of the products by the difference between the sales
+// of an order before and after a change, see Order::sales
+async fn update_total_sales<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    before: &BTreeMap<i32, i32>,
+    after: &BTreeMap<i32, i32>,
+) -> Result<(), anyhow::Error> {
+
+    let mut deltas = after.clone();
+    for (product_id, quantity) in before {
+        *deltas.entry(*product_id).or_insert(0) -= quantity;
+    }
+    deltas.retain(|_, quantity| *quantity != 0);
+
+    if deltas.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(r#"
+        UPDATE products SET total_sales = GREATEST(products.total_sales + sales.quantity, 0)
+        FROM UNNEST($1::INT[], $2::INT[]) AS sales(product_id, quantity)
+        WHERE products.id = sales.product_id;
+    "#)
+        .bind(deltas.keys().copied().collect::<Vec<i32>>())
+        .bind(deltas.values().copied().collect::<Vec<i32>>())
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
 pub struct Orders {
     pool: sqlx::Pool<sqlx::Postgres>,
 }
@@ -202,7 +410,7 @@ impl Orders {
 
         let order_id: i32 = sqlx::query(r#"
             INSERT INTO orders (
-                order_key, customer_ip_address, customer_user_agent, customer_note,
+                order_key, customer_id, customer_ip_address, customer_user_agent, customer_note,
                 billing, shipping, line_items, shipping_lines,
                 payment_method, payment_method_title, status, currency,
                 discount_total, discount_tax,
@@ -213,12 +421,13 @@ impl Orders {
                 cart_hash
             )
             VALUES (
-                $1, $2, $3, $4,
-                $5::jsonb, $6::jsonb, $7::jsonb, $8::jsonb,
-                $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21
+                $1, $2, $3, $4, $5,
+                $6::jsonb, $7::jsonb, $8::jsonb, $9::jsonb,
+                $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22
             ) RETURNING id;
         "#)
             .bind(&order.order_key)
+            .bind(order.customer_id)
             .bind(&order.customer_ip_address)
             .bind(&order.customer_user_agent)
             .bind(&order.customer_note)
@@ -246,6 +455,96 @@ impl Orders {
         Ok(order_id)
     }
 
+    // Assign the guest orders placed with this billing email to the customer
+    pub async fn link_guest_orders(&self,
+        customer_id: i32,
+        email: &str,
+    ) -> Result<u64, anyhow::Error> {
+
+        let result = sqlx::query(r#"
+            UPDATE orders SET customer_id = $1
+            WHERE customer_id = 0 AND LOWER(billing->>'email') = LOWER($2);
+        "#)
+            .bind(customer_id)
+            .bind(email)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get(&self, id: i32) -> Result<OrderDetails, anyhow::Error> {
+        fetch(&self.pool, id, false).await
+    }
+
+    // Sets the status and the quantities of the lines, by position, a
+    // quantity of 0 removes the line. The audit entries are written in the
+    // same transaction, a change is never saved without them
+    pub async fn update(&self,
+        id: i32,
+        status: OrderStatus,
+        quantities: &[i32],
+    ) -> Result<OrderDetails, anyhow::Error> {
+
+        let mut transaction = self.pool.begin().await?;
+
+        let mut details = fetch(&mut *transaction, id, true).await?;
+        let (status_before, lines_before) = details.order.audit_snapshot();
+        let sales_before = details.order.sales();
+
+        let order = &mut details.order;
+        if quantities.len() != order.line_items.len() {
+            return Err(anyhow::anyhow!("expected {} quantities, got {}",
+                order.line_items.len(), quantities.len()));
+        }
+        if let Some(quantity) = quantities.iter().find(|quantity| **quantity < 0) {
+            return Err(anyhow::anyhow!("invalid quantity: {}", quantity));
+        }
+        if quantities.iter().all(|quantity| *quantity == 0) {
+            return Err(anyhow::anyhow!("an order needs at least one line"));
+        }
+
+        for (line, quantity) in order.line_items.iter_mut().zip(quantities) {
+            if *quantity > 0 && *quantity != line.quantity {
+                line.set_quantity(*quantity);
+            }
+        }
+        let mut quantities = quantities.iter();
+        order.line_items.retain(|_| quantities.next().is_some_and(|quantity| *quantity > 0));
+        order.update_totals();
+        order.status = status;
+
+        sqlx::query(r#"
+            UPDATE orders
+            SET status = $1, line_items = $2::jsonb,
+                discount_total = $3, discount_tax = $4, cart_tax = $5, total = $6, total_tax = $7,
+                date_modified = CURRENT_TIMESTAMP
+            WHERE id = $8;
+        "#)
+            .bind(&order.status)
+            .bind(serde_json::to_string(&order.line_items)?)
+            .bind(order.discount_total)
+            .bind(order.discount_tax)
+            .bind(order.cart_tax)
+            .bind(order.total)
+            .bind(order.total_tax)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+
+        update_total_sales(&mut *transaction, &sales_before, &order.sales()).await?;
+
+        let (status_after, lines_after) = order.audit_snapshot();
+        audit::record_with(&mut *transaction, "order.status", "order", id,
+            Some(status_before), Some(status_after)).await?;
+        audit::record_with(&mut *transaction, "order.line_items", "order", id,
+            Some(lines_before), Some(lines_after)).await?;
+
+        transaction.commit().await?;
+
+        Ok(details)
+    }
+
     pub async fn get_page(&self,
         parameters: &Parameters,
     ) -> Result<OrderPage, anyhow::Error> {
@@ -255,9 +554,13 @@ impl Orders {
         let order = parameters.order.as_ref().unwrap_or(&types::Order::Asc);
         let order_by = orders_order_by(&parameters.order_by);
 
+        // 0 matches every order, otherwise only the orders of the given customer
+        let customer_id = parameters.customer.unwrap_or(0) as i32;
+
         let total: (i64, ) = sqlx::query_as(r#"
-            SELECT COUNT(*) FROM orders;
+            SELECT COUNT(*) FROM orders WHERE $1 = 0 OR customer_id = $1;
         "#)
+            .bind(customer_id)
             .fetch_one(&self.pool)
             .await?;
 
@@ -292,12 +595,14 @@ impl Orders {
                 (billing->>'first_name') || ' ' || (billing->>'last_name') AS customer_name,
                 orders.total
             FROM orders
+            WHERE $3 = 0 OR orders.customer_id = $3
             ORDER BY 
                 orders.{} {}
             LIMIT $1 OFFSET $2;
         "#, order_by, order.as_str()))
             .bind(per_page)
             .bind(offset)
+            .bind(customer_id)
             .map(|row: PgRow| OrderShort {
                 id: row.get::<i32, _>("id"),
                 date_created: || -> String {
@@ -0,0 +1,735 @@
+//
+// Description: CSV import and export of the product catalog
+// Last Modification: 2026-10-18 11:43:29
+//
+
+use crate::models::backend;
+use crate::models::categories;
+use crate::models::products::{
+    self,
+    ImageOperation,
+    ProductImage,
+    ProductType,
+    Status,
+    StockStatus,
+};
+
+use anyhow;
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::{
+    postgres::PgRow,
+    types::Decimal,
+    Row,
+};
+use strum::IntoEnumIterator;
+
+// The columns of an export, in order. An import matches the header without
+// regard to case, a missing column leaves the field of an updated product as
+// it is. Categories are paths ("Clothing > Shirts") and, like the images,
+// are separated by commas. A path or an address that holds a comma or a
+// quote is quoted the way a field is: Clothing > Shirts, "Hats, Caps".
+pub const COLUMNS: [&str; 9] = [
+    "SKU", "Name", "Regular price", "Sale price", "Stock", "Weight", "Categories", "Images", "Status",
+];
+
+// Reads the records of a RFC 4180 file with the line each one starts on.
+// Fields are quoted when they hold a comma, a quote ("") or a line break.
+pub fn parse(text: &str) -> Result<Vec<(usize, Vec<String>)>, anyhow::Error> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                },
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                // Blank lines are skipped
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear(); // the blank line
+                line += 1;
+                start = line;
+            },
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(anyhow::anyhow!("line {}: a quoted field is not closed", start));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+
+    Ok(records)
+}
+
+fn push_record(output: &mut String, fields: &[String]) {
+    let fields: Vec<String> = fields.iter()
+        .map(|field| if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        })
+        .collect();
+    output.push_str(&fields.join(","));
+    output.push_str("\r\n");
+}
+
+// The list of a Categories or Images field, joined with ", "
+fn join_list(items: &[String]) -> String {
+    items.iter()
+        .map(|item| if item.contains([',', '"']) {
+            format!("\"{}\"", item.replace('"', "\"\""))
+        } else {
+            item.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut quoted = false;
+
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                item.push('"');
+            },
+            '"' if quoted => quoted = false,
+            '"' if item.trim().is_empty() => {
+                item.clear();
+                quoted = true;
+            },
+            ',' if !quoted => items.push(std::mem::take(&mut item)),
+            _ => item.push(c),
+        }
+    }
+    items.push(item);
+
+    items.into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+pub async fn export(pool: &sqlx::Pool<sqlx::Postgres>) -> Result<String, anyhow::Error> {
+    let mut output = String::new();
+    push_record(&mut output, &COLUMNS.map(String::from));
+
+    let rows = sqlx::query(r#"
+        WITH RECURSIVE paths AS (
+            SELECT id, name::TEXT AS path FROM categories WHERE parent = 0
+            UNION ALL
+            SELECT categories.id, paths.path || ' > ' || categories.name
+            FROM categories JOIN paths ON categories.parent = paths.id
+        )
+        SELECT
+            products.sku, products.name, products.regular_price, products.sale_price,
+            products.stock_quantity, products.weight, products.status,
+            COALESCE((SELECT ARRAY_AGG(paths.path ORDER BY paths.path)
+                FROM product_categories JOIN paths ON paths.id = product_categories.category_id
+                WHERE product_categories.product_id = products.id), '{}') AS categories,
+            COALESCE((SELECT ARRAY_AGG(media.src::TEXT ORDER BY product_media.position)
+                FROM product_media JOIN media ON media.id = product_media.media_id
+                WHERE product_media.product_id = products.id), '{}') AS images
+        FROM products
+        WHERE products.status <> 'trash'
+        ORDER BY products.id;
+    "#)
+        .fetch_all(pool)
+        .await?;
+
+    for row in rows {
+        // The form ignores a sale price that is not lower than the regular price
+        let regular_price = row.get::<Decimal, _>("regular_price");
+        let sale_price = row.get::<Option<Decimal>, _>("sale_price").unwrap_or_default();
+        push_record(&mut output, &[
+            row.get::<String, _>("sku"),
+            row.get::<String, _>("name"),
+            regular_price.to_string(),
+            if sale_price.is_zero() || sale_price >= regular_price { "".to_string() } else { sale_price.to_string() },
+            row.get::<Option<i32>, _>("stock_quantity").unwrap_or(0).to_string(),
+            row.get::<i32, _>("weight").to_string(),
+            join_list(&row.get::<Vec<String>, _>("categories")),
+            join_list(&row.get::<Vec<String>, _>("images")),
+            row.get::<Status, _>("status").as_str().to_string(),
+        ]);
+    }
+
+    Ok(output)
+}
+
+// A record of the file, None for the columns missing or left empty
+#[derive(Debug, Default)]
+struct Record {
+    sku: String,
+    name: Option<String>,
+    regular_price: Option<f32>,
+    sale_price: Option<Option<f32>>, // Some(None) when the column is there and empty, the sale ends
+    stock_quantity: Option<i32>,
+    weight: Option<u32>,
+    categories: Option<Vec<Vec<String>>>, // the names from the top category down
+    images: Option<Vec<String>>,
+    status: Option<Status>,
+}
+
+fn parse_record(columns: &HashMap<&str, usize>, fields: &[String]) -> Result<Record, Vec<String>> {
+    let mut errors = vec![];
+    let value = |column: &str| -> Option<&str> {
+        columns.get(column)
+            .and_then(|index| fields.get(*index))
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+
+    let mut record = Record {
+        sku: value("SKU").unwrap_or_default().to_string(),
+        name: value("Name").map(String::from),
+        ..Default::default()
+    };
+    if record.sku.is_empty() {
+        errors.push("the SKU is required".to_string());
+    }
+
+    let mut price = |column: &str| -> Option<f32> {
+        let value = value(column)?;
+        match value.parse::<f32>() {
+            Ok(price) if price >= 0.00 && price.is_finite() => Some(price),
+            _ => {
+                errors.push(format!("{}: {:?} is not a price", column, value));
+                None
+            },
+        }
+    };
+    record.regular_price = price("Regular price");
+    if columns.contains_key("Sale price") {
+        record.sale_price = Some(price("Sale price"));
+    }
+
+    if let Some(stock) = value("Stock") {
+        match stock.parse::<i32>() {
+            Ok(stock) if stock >= 0 => record.stock_quantity = Some(stock),
+            _ => errors.push(format!("Stock: {:?} is not a quantity", stock)),
+        }
+    }
+
+    if let Some(weight) = value("Weight") {
+        match weight.parse::<u32>() {
+            Ok(weight) => record.weight = Some(weight),
+            Err(_) => errors.push(format!("Weight: {:?} is not a weight in grams", weight)),
+        }
+    }
+
+    if let Some(categories) = value("Categories") {
+        let mut paths = vec![];
+        for path in split_list(categories) {
+            let names: Vec<String> = path.split('>').map(|name| name.trim().to_string()).collect();
+            if names.iter().any(|name| name.is_empty()) {
+                errors.push(format!("Categories: {:?} is not a category path", path));
+            }
+            paths.push(names);
+        }
+        record.categories = Some(paths);
+    }
+
+    if let Some(images) = value("Images") {
+        let images = split_list(images);
+        for src in images.iter() {
+            let is_web_address = url::Url::parse(src)
+                .map(|url| url.scheme() == "http" || url.scheme() == "https")
+                .unwrap_or(false);
+            if !is_web_address {
+                errors.push(format!("Images: {:?} is not an http or https address", src));
+            }
+        }
+        record.images = Some(images);
+    }
+
+    if let Some(status) = value("Status") {
+        match Status::iter().filter(|option| *option != Status::Trash).find(|option| option.as_str() == status) {
+            Some(status) => record.status = Some(status),
+            None => errors.push(format!("Status: {:?} is not one of draft, pending, private or publish", status)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(record)
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowReport {
+    pub line: usize,
+    pub sku: String,
+    pub name: String,
+    pub action: &'static str, // create, update or error
+    pub product_id: i32, // 0 for a product created by a dry run
+    pub messages: Vec<String>, // the errors, or the categories and images created
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub warnings: Vec<String>, // about the whole file, e.g. an unknown column
+    pub rows: Vec<RowReport>,
+    pub created: i32,
+    pub updated: i32,
+    pub failed: i32,
+}
+
+pub struct ProductCsv {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    categories: HashMap<(i32, String), i32>, // (parent, lowercase name) => id
+    media: HashMap<String, i32>, // src => id
+    dry_run: bool,
+    next_id: i32, // the negative ids of the rows a dry run would insert
+}
+
+impl ProductCsv {
+
+    // Upserts the products by SKU. A row with errors is skipped and the
+    // others are saved, a dry run reports the same without saving anything.
+    pub async fn import(&mut self, text: &str) -> Result<ImportReport, anyhow::Error> {
+        let mut records = parse(text)?.into_iter();
+
+        let header = match records.next() {
+            Some((_, header)) => header,
+            None => return Err(anyhow::anyhow!("the file is empty")),
+        };
+
+        let mut report = ImportReport {
+            dry_run: self.dry_run,
+            warnings: vec![],
+            rows: vec![],
+            created: 0,
+            updated: 0,
+            failed: 0,
+        };
+
+        let mut columns = HashMap::new();
+        for (index, name) in header.iter().enumerate() {
+            match COLUMNS.iter().find(|column| column.eq_ignore_ascii_case(name.trim())) {
+                Some(column) => { columns.insert(*column, index); },
+                None => report.warnings.push(format!("The column {:?} is ignored", name)),
+            }
+        }
+        if !columns.contains_key("SKU") {
+            return Err(anyhow::anyhow!("the file has no SKU column"));
+        }
+
+        self.load().await?;
+
+        let mut lines: HashMap<String, usize> = HashMap::new();
+        for (line, fields) in records {
+            let mut row = RowReport {
+                line,
+                sku: columns.get("SKU").and_then(|index| fields.get(*index)).map(|sku| sku.trim().to_string()).unwrap_or_default(),
+                name: columns.get("Name").and_then(|index| fields.get(*index)).map(|name| name.trim().to_string()).unwrap_or_default(),
+                action: "error",
+                product_id: 0,
+                messages: vec![],
+            };
+
+            let result = match parse_record(&columns, &fields) {
+                Ok(record) => match lines.get(&record.sku) {
+                    Some(first) => Err(vec![format!("the SKU is already on line {}", first)]),
+                    None => {
+                        lines.insert(record.sku.clone(), line);
+                        self.save(&record, &mut row).await
+                    },
+                },
+                Err(errors) => Err(errors),
+            };
+
+            match result {
+                Ok(()) if row.action == "create" => report.created += 1,
+                Ok(()) => report.updated += 1,
+                Err(errors) => {
+                    row.action = "error";
+                    row.messages = errors;
+                    report.failed += 1;
+                },
+            }
+            report.rows.push(row);
+        }
+
+        Ok(report)
+    }
+
+    async fn load(&mut self) -> Result<(), anyhow::Error> {
+        self.categories = sqlx::query("SELECT id, name, parent FROM categories;")
+            .map(|row: PgRow| ((row.get::<i32, _>("parent"), row.get::<String, _>("name").to_lowercase()), row.get::<i32, _>("id")))
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        self.media = sqlx::query("SELECT id, src FROM media;")
+            .map(|row: PgRow| (row.get::<String, _>("src"), row.get::<i32, _>("id")))
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(())
+    }
+
+    async fn save(&mut self, record: &Record, row: &mut RowReport) -> Result<(), Vec<String>> {
+        let backend = products::Products::new(self.pool.clone());
+        let backend = backend.backend();
+        let failed = |e: anyhow::Error| vec![e.to_string()];
+
+        let ids = backend.find_by_sku(&record.sku).await.map_err(failed)?;
+        let mut product = match ids.as_slice() {
+            [] => {
+                let name = match record.name.as_ref() {
+                    Some(name) => name,
+                    None => return Err(vec!["the name of a new product is required".to_string()]),
+                };
+                row.action = "create";
+                new_product(name, backend.unique_slug(name).await.map_err(failed)?)
+            },
+            [id] => {
+                row.action = "update";
+                row.product_id = *id;
+                backend.get(*id).await.map_err(failed)?
+            },
+            _ => return Err(vec![format!("the SKU belongs to {} products", ids.len())]),
+        };
+
+        product.sku = record.sku.clone();
+        if let Some(name) = record.name.as_ref() {
+            product.name = name.clone();
+        }
+        if let Some(status) = record.status.as_ref() {
+            product.status = status.clone();
+        }
+        if let Some(weight) = record.weight {
+            product.weight = weight;
+        }
+        if let Some(stock_quantity) = record.stock_quantity {
+            product.stock_quantity = stock_quantity;
+        }
+
+        // The same rules as the product form
+        if let Some(regular_price) = record.regular_price {
+            product.regular_price = regular_price;
+        }
+        if let Some(sale_price) = record.sale_price {
+            product.sale_price = sale_price.unwrap_or(0.00);
+        }
+        if product.sale_price > 0.00 && product.sale_price >= product.regular_price {
+            return Err(vec!["the sale price must be lower than the regular price".to_string()]);
+        }
+        product.on_sale = product.sale_price > 0.00 && backend
+            .sale_is_active(product.date_on_sale_from, product.date_on_sale_to)
+            .await
+            .map_err(failed)?;
+        product.price = if product.on_sale { product.sale_price } else { product.regular_price };
+
+        let has_stock = matches!(product.product_type, ProductType::Simple | ProductType::Variable);
+        product.stock_status = if product.stock_quantity == 0 && has_stock {
+            StockStatus::OutOfStock
+        } else {
+            StockStatus::InStock
+        };
+
+        if let Some(paths) = record.categories.as_ref() {
+            product.categories = vec![];
+            for path in paths {
+                let category_id = self.category(path, &mut row.messages).await.map_err(failed)?;
+                if !product.categories.contains(&category_id) {
+                    product.categories.push(category_id);
+                }
+            }
+            if !product.categories.contains(&product.primary_category) {
+                product.primary_category = 0;
+            }
+        }
+
+        let mut operations = HashMap::new();
+        if let Some(sources) = record.images.as_ref() {
+            let mut images = vec![];
+            for (position, src) in sources.iter().enumerate() {
+                let media_id = self.media(src, &product.name, &mut row.messages).await.map_err(failed)?;
+                let current = product.images.iter().find(|image| image.id == media_id);
+                operations.insert(media_id, if current.is_some() { ImageOperation::Update } else { ImageOperation::Insert });
+                images.push(ProductImage {
+                    id: media_id,
+                    src: src.clone(),
+                    name: current.map(|image| image.name.clone()).unwrap_or_else(|| media_name(src)),
+                    alt: current.map(|image| image.alt.clone()).unwrap_or_else(|| product.name.clone()),
+                    position: position as i32,
+                });
+            }
+            for image in product.images.iter() {
+                operations.entry(image.id).or_insert(ImageOperation::Delete);
+            }
+            product.images = images;
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if row.action == "create" {
+            row.product_id = backend.add(&product).await.map_err(failed)?;
+        } else {
+            // The images taken off the product stay in the media library
+            backend.update(&product, &operations, false).await.map_err(failed)?;
+        }
+
+        Ok(())
+    }
+
+    // The category at the end of the path, the missing ones are added
+    async fn category(&mut self, path: &[String], messages: &mut Vec<String>) -> Result<i32, anyhow::Error> {
+        let mut parent = 0;
+        for (depth, name) in path.iter().enumerate() {
+            let key = (parent, name.to_lowercase());
+            parent = match self.categories.get(&key) {
+                Some(id) => *id,
+                None => {
+                    let id = if self.dry_run {
+                        self.next_id -= 1;
+                        self.next_id
+                    } else {
+                        categories::Categories::new(self.pool.clone()).backend().add(name, parent).await?
+                    };
+                    messages.push(format!("New category: {}", path[..=depth].join(" > ")));
+                    self.categories.insert(key, id);
+                    id
+                },
+            };
+        }
+        Ok(parent)
+    }
+
+    // The media of the address, added to the library when it is new
+    async fn media(&mut self, src: &str, alt: &str, messages: &mut Vec<String>) -> Result<i32, anyhow::Error> {
+        if let Some(id) = self.media.get(src) {
+            return Ok(*id);
+        }
+
+        let id = if self.dry_run {
+            self.next_id -= 1;
+            self.next_id
+        } else {
+            sqlx::query(r#"
+                INSERT INTO media (src, name, alt) VALUES ($1, $2, $3) RETURNING id;
+            "#)
+                .bind(src)
+                .bind(media_name(src))
+                .bind(alt)
+                .fetch_one(&self.pool)
+                .await?
+                .get(0)
+        };
+
+        messages.push(format!("New image: {}", src));
+        self.media.insert(src.to_string(), id);
+        Ok(id)
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>, dry_run: bool) -> Self {
+        ProductCsv {
+            pool,
+            categories: HashMap::new(),
+            media: HashMap::new(),
+            dry_run,
+            next_id: 0,
+        }
+    }
+}
+
+// "https://example.com/images/shirt.jpg" => "shirt.jpg"
+fn media_name(src: &str) -> String {
+    src.trim_end_matches('/').rsplit('/').next().unwrap_or(src).to_string()
+}
+
+fn new_product(name: &str, slug: String) -> backend::Product {
+    backend::Product {
+        id: 0,
+        sku: "".to_string(),
+        name: name.to_string(),
+        permalink: format!("/product/{}", slug),
+        slug,
+        description: "".to_string(),
+        short_description: "".to_string(),
+        price: 0.00,
+        regular_price: 0.00,
+        sale_price: 0.00,
+        on_sale: false,
+        date_on_sale_from: None,
+        date_on_sale_to: None,
+        stock_status: StockStatus::InStock,
+        stock_quantity: 0,
+        weight: 0,
+        status: Status::Draft,
+        product_type: ProductType::Simple,
+        catalog_visibility: products::CatalogVisibility::Visible,
+        external_url: "".to_string(),
+        button_text: "".to_string(),
+        grouped_products: vec![],
+        upsells: vec![],
+        cross_sells: vec![],
+        primary_category: 0,
+        categories: vec![],
+        images: vec![],
+        tags: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn columns(header: &[&'static str]) -> HashMap<&'static str, usize> {
+        header.iter().enumerate().map(|(index, column)| (*column, index)).collect()
+    }
+
+    #[test]
+    fn parse_reads_quoted_commas_and_quotes() {
+        let records = parse("SKU,Name\nA1,\"Shirt, blue\"\nA2,\"The \"\"best\"\" hat\"\n").unwrap();
+        assert_eq!(records, vec![
+            (1, fields(&["SKU", "Name"])),
+            (2, fields(&["A1", "Shirt, blue"])),
+            (3, fields(&["A2", "The \"best\" hat"])),
+        ]);
+    }
+
+    #[test]
+    fn parse_reads_crlf_lines() {
+        let records = parse("SKU,Name\r\nA1,Shirt\r\nA2,Hat").unwrap();
+        assert_eq!(records, vec![
+            (1, fields(&["SKU", "Name"])),
+            (2, fields(&["A1", "Shirt"])),
+            (3, fields(&["A2", "Hat"])),
+        ]);
+    }
+
+    // A record is reported on the line it starts on, the next one after its line breaks
+    #[test]
+    fn parse_counts_the_lines_of_embedded_line_breaks() {
+        let records = parse("SKU,Name\nA1,\"Shirt\nblue\r\nlarge\"\nA2,Hat\n").unwrap();
+        assert_eq!(records, vec![
+            (1, fields(&["SKU", "Name"])),
+            (2, fields(&["A1", "Shirt\nblue\r\nlarge"])),
+            (5, fields(&["A2", "Hat"])),
+        ]);
+    }
+
+    #[test]
+    fn parse_skips_blank_lines() {
+        let records = parse("SKU,Name\n\nA1,Shirt\r\n\r\nA2,Hat\n\n").unwrap();
+        assert_eq!(records, vec![
+            (1, fields(&["SKU", "Name"])),
+            (3, fields(&["A1", "Shirt"])),
+            (5, fields(&["A2", "Hat"])),
+        ]);
+    }
+
+    #[test]
+    fn parse_ignores_the_byte_order_mark() {
+        let records = parse("\u{feff}SKU,Name\nA1,Shirt\n").unwrap();
+        assert_eq!(records[0], (1, fields(&["SKU", "Name"])));
+    }
+
+    #[test]
+    fn parse_rejects_an_unterminated_quote() {
+        let error = parse("SKU,Name\nA1,Shirt\nA2,\"Hat\nA3,Cap\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: a quoted field is not closed");
+    }
+
+    #[test]
+    fn export_lists_are_split_back() {
+        let paths = fields(&["Clothing > Shirts", "Hats, Caps", "The \"best\" > Tops"]);
+        let list = join_list(&paths);
+        assert_eq!(list, "Clothing > Shirts, \"Hats, Caps\", \"The \"\"best\"\" > Tops\"");
+        assert_eq!(split_list(&list), paths);
+
+        let record = vec![list];
+        let mut output = String::new();
+        push_record(&mut output, &record);
+        assert_eq!(parse(&output).unwrap(), vec![(1, record)]);
+    }
+
+    #[test]
+    fn parse_record_reads_the_category_paths() {
+        let columns = columns(&["SKU", "Categories", "Images"]);
+        let record = parse_record(&columns, &fields(&[
+            " A1 ",
+            "Clothing > Shirts, \"Hats, Caps\"",
+            "https://example.com/a.jpg, https://example.com/b.jpg",
+        ])).unwrap();
+        assert_eq!(record.sku, "A1");
+        assert_eq!(record.categories, Some(vec![
+            fields(&["Clothing", "Shirts"]),
+            fields(&["Hats, Caps"]),
+        ]));
+        assert_eq!(record.images, Some(fields(&["https://example.com/a.jpg", "https://example.com/b.jpg"])));
+    }
+
+    #[test]
+    fn parse_record_reports_every_invalid_field() {
+        let columns = columns(&["SKU", "Regular price", "Sale price", "Stock", "Categories", "Images", "Status"]);
+        let errors = parse_record(&columns, &fields(&["", "-1", "", "many", "Clothing > ", "/a.jpg", "trash"])).unwrap_err();
+        assert_eq!(errors, vec![
+            "the SKU is required",
+            "Regular price: \"-1\" is not a price",
+            "Stock: \"many\" is not a quantity",
+            "Categories: \"Clothing >\" is not a category path",
+            "Images: \"/a.jpg\" is not an http or https address",
+            "Status: \"trash\" is not one of draft, pending, private or publish",
+        ]);
+    }
+
+    // An empty Sale price ends the sale, a missing column keeps it
+    #[test]
+    fn parse_record_tells_an_empty_column_from_a_missing_one() {
+        let record = parse_record(&columns(&["SKU", "Sale price"]), &fields(&["A1", ""])).unwrap();
+        assert_eq!(record.sale_price, Some(None));
+
+        let record = parse_record(&columns(&["SKU"]), &fields(&["A1"])).unwrap();
+        assert_eq!(record.sale_price, None);
+    }
+}
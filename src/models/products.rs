@@ -3,25 +3,35 @@
 //
 
 use crate::types;
+use crate::models::audit;
 use crate::models::frontend;
+use crate::models::revisions;
+use crate::models::settings;
 use crate::models::backend;
+use crate::models::tags;
+use crate::models::variations;
 
 use anyhow;
 use num_traits::ToPrimitive;
 use std::collections::HashMap;
-use url::Url;
 use chrono::NaiveDateTime;
 
 use std::{
     fs,
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 
-use strum::EnumIter;
+use strum::{
+    EnumIter,
+    IntoEnumIterator,
+};
 
 use sqlx::{
     postgres::PgRow,
     types::{Json, Decimal},
+    PgConnection,
+    Postgres,
+    QueryBuilder,
     Row,
 };
 
@@ -33,7 +43,7 @@ use serde_json::Value as JsonValue;
 
 use super::frontend::ProductPage;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, EnumIter)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
 #[sqlx(type_name = "status", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
@@ -41,6 +51,7 @@ pub enum Status {
     Pending,
     Private,
     Publish,
+    Trash, // hidden from the store and the default lists, see Backend::delete
 }
 
 impl Status{
@@ -50,11 +61,33 @@ impl Status{
             Status::Pending => "pending",
             Status::Private => "private",
             Status::Publish => "publish",
+            Status::Trash => "trash",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
+#[sqlx(type_name = "type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ProductType {
+    Simple,
+    Grouped,
+    External,
+    Variable, // sold through its variations
+}
+
+impl ProductType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ProductType::Simple => "simple",
+            ProductType::Grouped => "grouped",
+            ProductType::External => "external",
+            ProductType::Variable => "variable",
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
 #[sqlx(type_name = "stock_status", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum StockStatus {
@@ -64,23 +97,50 @@ pub enum StockStatus {
 }
 
 impl StockStatus {
-    fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &str {
         match self {
             StockStatus::InStock => "instock",
             StockStatus::OutOfStock => "outofstock",
             StockStatus::OnBackorder => "onbackorder",
         }
     }
+
+    pub fn label(&self) -> &str {
+        match self {
+            StockStatus::InStock => "In stock",
+            StockStatus::OutOfStock => "Out of stock",
+            StockStatus::OnBackorder => "On backorder",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "link_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum LinkType {
+    Upsell, // offered instead of the product, on its page
+    CrossSell, // offered with the product, in the cart
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
 #[sqlx(type_name = "catalog_visibility", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum CatalogVisibility {
-    Visible, // Default
-    Catalog,
-    Search,
-    Hidden,
+    Visible, // Default, listed in the shop and the search results
+    Catalog, // listed in the shop only
+    Search, // listed in the search results only
+    Hidden, // only reachable from its own page
+}
+
+impl CatalogVisibility {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CatalogVisibility::Visible => "visible",
+            CatalogVisibility::Catalog => "catalog",
+            CatalogVisibility::Search => "search",
+            CatalogVisibility::Hidden => "hidden",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,6 +152,10 @@ pub struct Parameters {
     pub order_by: Option<String>,
     pub featured: Option<bool>,
     pub category: Option<u32>, // Limit result set to products assigned a specific category ID.
+    pub tag: Option<u32>, // Limit result set to products assigned a specific tag ID.
+    pub tags: Option<String>, // array - Limit result set to products assigned any of the tag IDs.
+    pub attributes: Option<String>, // "Size:M,Size:L,Colour:Red" - any option of an attribute, every attribute.
+    pub rating: Option<u32>, // Limit result set to products rated at least this many stars.
     pub sku: Option<String>, // Limit result set to products with a specific SKU.
     pub exclude: Option<String>, // array - Ensure result set excludes specific IDs.
     pub include: Option<String>, // array - Limit result set to specific ids.
@@ -162,57 +226,414 @@ pub fn products_order_by(parameter: &Option<String>) -> &str {
             "title" => "name",
             // "slug" => "?",
             "price" => "price",
-            // "popularity" => "?",
-            // "rating" => "?",
+            "popularity" => "total_sales",
+            "rating" => "average_rating",
             _ => "date_created", // The default case
         },
         None => "date_created",
     }
 }
 
-fn where_clause_parts(parameters: &Parameters) -> Vec<String> {
-    let mut where_parts = Vec::new();
+// Closes the "WITH RECURSIVE tree AS (SELECT id FROM categories WHERE ..." of a
+// category filter, the tree holds the category and all of its descendants
+const CATEGORY_DESCENDANTS: &str = " UNION ALL SELECT categories.id FROM categories \
+    JOIN tree ON categories.parent = tree.id) SELECT id FROM tree))";
+
+// Upper bound for the include/exclude/sku lists accepted from the query string
+const MAX_LIST_ITEMS: usize = 100;
+
+// The address the uploaded media are saved with, their files are in static/uploads
+const MEDIA_ORIGIN: &str = "http://127.0.0.1:8080";
+
+// "1,2,3" -> [1, 2, 3], a single invalid id rejects the whole list
+pub fn parse_ids(list: &str) -> Result<Vec<i32>, anyhow::Error> {
+    let ids = list.split(',')
+        .map(|id| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| match id.parse::<i32>() {
+            Ok(id) if id > 0 => Ok(id),
+            _ => Err(anyhow::anyhow!("invalid product id: {:?}", id)),
+        })
+        .collect::<Result<Vec<i32>, anyhow::Error>>()?;
+
+    if ids.len() > MAX_LIST_ITEMS {
+        return Err(anyhow::anyhow!("too many product ids, the limit is {}", MAX_LIST_ITEMS));
+    }
+
+    Ok(ids)
+}
+
+// "Size:M, Size:L,Colour:Red" -> [("Colour", ["Red"]), ("Size", ["M", "L"])]
+pub fn parse_attributes(list: &str) -> Result<Vec<(String, Vec<String>)>, anyhow::Error> {
+    let mut attributes: Vec<(String, Vec<String>)> = vec![];
+    let mut count = 0;
+
+    for pair in list.split(',').map(|pair| pair.trim()).filter(|pair| !pair.is_empty()) {
+        let (name, option) = match pair.split_once(':') {
+            Some((name, option)) if !name.trim().is_empty() && !option.trim().is_empty() =>
+                (name.trim().to_string(), option.trim().to_string()),
+            _ => return Err(anyhow::anyhow!("invalid attribute filter: {:?}, expected name:option", pair)),
+        };
+
+        count += 1;
+        match attributes.iter_mut().find(|(other, _)| *other == name) {
+            Some((_, options)) => if !options.contains(&option) { options.push(option) },
+            None => attributes.push((name, vec![option])),
+        }
+    }
 
-    if parameters.status.is_some() {
-        where_parts.push(format!("products.status = '{}'",
-            parameters.status.as_ref().unwrap().as_str().to_string()));
+    if count > MAX_LIST_ITEMS {
+        return Err(anyhow::anyhow!("too many attribute filters, the limit is {}", MAX_LIST_ITEMS));
     }
-    if parameters.on_sale.is_some() {
-        where_parts.push(format!("products.on_sale = {}",
-            parameters.on_sale.unwrap()));
+
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(attributes)
+}
+
+pub fn parse_skus(list: &str) -> Result<Vec<String>, anyhow::Error> {
+    let skus: Vec<String> = list.split(',')
+        .map(|sku| sku.trim().to_string())
+        .filter(|sku| !sku.is_empty())
+        .collect();
+
+    if skus.len() > MAX_LIST_ITEMS {
+        return Err(anyhow::anyhow!("too many product skus, the limit is {}", MAX_LIST_ITEMS));
     }
-    if parameters.featured.is_some() {
-        where_parts.push(format!("products.featured = {}",
-            parameters.featured.unwrap()));
+
+    Ok(skus)
+}
+
+// A sale runs while its window is open, an empty date leaves the window
+// open on that side. The storefront reads the price through these so a sale
+// starts and ends on time, the stored price and on_sale are kept in step by
+// schedule_sales for the filters and the back office.
+// The sale dates are wall clock times in the time zone of the database
+// (its TimeZone setting), every check reads the database LOCALTIMESTAMP,
+// see Backend::sale_is_active for the products being saved.
+macro_rules! on_sale_sql {
+    () => {
+        "(products.sale_price > 0 AND products.sale_price < products.regular_price \
+        AND (products.date_on_sale_from IS NULL OR products.date_on_sale_from <= LOCALTIMESTAMP) \
+        AND (products.date_on_sale_to IS NULL OR products.date_on_sale_to > LOCALTIMESTAMP))"
+    };
+}
+const ON_SALE: &str = on_sale_sql!();
+pub const PRICE: &str = concat!("CASE WHEN ", on_sale_sql!(), " THEN products.sale_price ELSE products.regular_price END");
+
+// How often schedule_sales looks for the sales that started or ended
+const SALE_SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Runs for the life of the server
+pub async fn schedule_sales(pool: sqlx::Pool<sqlx::Postgres>) {
+    let mut interval = tokio::time::interval(SALE_SCHEDULE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let products_manager = Products::new(pool.clone());
+        match products_manager.backend().apply_sale_schedule().await {
+            Ok(ids) if !ids.is_empty() => println!("Scheduled sales updated for products {:?}", ids),
+            Ok(_) => {},
+            Err(e) => eprintln!("Error updating the scheduled sales: {}", e),
+        }
     }
-    if parameters.min_price.is_some() {
-        where_parts.push(format!("products.price >= {}",
-            parameters.min_price.unwrap()));
+}
+
+// How often schedule_trash_purge looks for the products to delete
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+// Runs for the life of the server, the trash_days of the settings are read
+// every time so a change applies without a restart
+pub async fn schedule_trash_purge(pool: sqlx::Pool<sqlx::Postgres>) {
+    let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let trash_days = match settings::Settings::new(pool.clone()).get().await {
+            Ok(store_settings) => store_settings.trash_days,
+            Err(e) => {
+                eprintln!("Error reading the settings: {}", e);
+                continue;
+            },
+        };
+        if trash_days <= 0 {
+            continue;
+        }
+
+        let products_manager = Products::new(pool.clone());
+        match products_manager.backend().purge_trash(trash_days).await {
+            Ok(ids) if !ids.is_empty() => println!("Deleted the products {:?} from the trash", ids),
+            Ok(_) => {},
+            Err(e) => eprintln!("Error emptying the trash: {}", e),
+        }
     }
-    if parameters.max_price.is_some() {
-        where_parts.push(format!("products.price <= {}",
-            parameters.max_price.unwrap()));
+}
+
+// Only the products shown in the shop listings, see CatalogVisibility
+const LISTED: &str = "products.catalog_visibility IN ('visible', 'catalog')";
+
+// Typed filters shared by the frontend, backend and shortcode listings.
+// Every value is bound as a query argument, nothing is interpolated.
+#[derive(Debug, Clone)]
+pub struct Filters {
+    pub status: Option<Status>,
+    pub on_sale: Option<bool>,
+    pub featured: Option<bool>,
+    pub min_price: Option<f32>,
+    pub max_price: Option<f32>,
+    pub stock_status: Option<StockStatus>,
+    pub category: Option<i32>,
+    pub category_slug: Option<String>,
+    pub tag: Option<i32>,
+    pub tags: Vec<i32>, // any of them
+    pub attributes: Vec<(String, Vec<String>)>, // any option of an attribute, every attribute
+    pub rating: Option<i32>, // minimum average rating
+    pub listed: bool, // only the products shown in the shop, see CatalogVisibility
+    pub skus: Vec<String>,
+    pub include: Vec<i32>,
+    pub exclude: Vec<i32>,
+}
+
+impl Filters {
+
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, anyhow::Error> {
+        let mut filters = Filters::new();
+
+        filters.status = parameters.status.clone();
+        filters.on_sale = parameters.on_sale;
+        filters.featured = parameters.featured;
+        filters.min_price = parameters.min_price;
+        filters.max_price = parameters.max_price;
+        filters.stock_status = parameters.stock_status.clone();
+        filters.category = match parameters.category {
+            Some(category) => Some(i32::try_from(category)?),
+            None => None,
+        };
+        filters.tag = match parameters.tag {
+            Some(tag) => Some(i32::try_from(tag)?),
+            None => None,
+        };
+        filters.rating = match parameters.rating {
+            Some(rating) if (1..=5).contains(&rating) => Some(rating as i32),
+            Some(rating) => return Err(anyhow::anyhow!("invalid rating: {}, expected 1 to 5 stars", rating)),
+            None => None,
+        };
+
+        if let Some(tags) = parameters.tags.as_ref() {
+            filters.tags = parse_ids(tags)?;
+        }
+        if let Some(attributes) = parameters.attributes.as_ref() {
+            filters.attributes = parse_attributes(attributes)?;
+        }
+
+        if let Some(sku) = parameters.sku.as_ref() {
+            filters.skus = parse_skus(sku)?;
+        }
+        if let Some(include) = parameters.include.as_ref() {
+            filters.include = parse_ids(include)?;
+        }
+        if let Some(exclude) = parameters.exclude.as_ref() {
+            filters.exclude = parse_ids(exclude)?;
+        }
+
+        Ok(filters)
     }
-    if parameters.stock_status.is_some() {
-        where_parts.push(format!("products.stock_status = '{}'",
-            parameters.stock_status.as_ref().unwrap().as_str().to_string()));
+
+    // Appends " WHERE ... AND ..." (or nothing) to a query selecting FROM products
+    pub fn push_where<'args>(&'args self, query: &mut QueryBuilder<'args, Postgres>) {
+        let mut first = true;
+        let mut condition = |query: &mut QueryBuilder<'args, Postgres>, sql: &str| {
+            query.push(if first { " WHERE " } else { " AND " });
+            query.push(sql);
+            first = false;
+        };
+
+        // The trash is only listed when asked for
+        match self.status.as_ref() {
+            Some(status) => {
+                condition(query, "products.status = ");
+                query.push_bind(status);
+            },
+            None => condition(query, "products.status <> 'trash'"),
+        }
+        if let Some(on_sale) = self.on_sale {
+            condition(query, "products.on_sale = ");
+            query.push_bind(on_sale);
+        }
+        if let Some(featured) = self.featured {
+            condition(query, "products.featured = ");
+            query.push_bind(featured);
+        }
+        if let Some(min_price) = self.min_price {
+            condition(query, "products.price >= ");
+            query.push_bind(min_price as f64);
+        }
+        if let Some(max_price) = self.max_price {
+            condition(query, "products.price <= ");
+            query.push_bind(max_price as f64);
+        }
+        if let Some(stock_status) = self.stock_status.as_ref() {
+            condition(query, "products.stock_status = ");
+            query.push_bind(stock_status);
+        }
+        // A category also holds the products of its subcategories
+        if let Some(category) = self.category {
+            condition(query, "EXISTS (SELECT 1 FROM product_categories \
+                WHERE product_categories.product_id = products.id AND product_categories.category_id IN (\
+                WITH RECURSIVE tree AS (SELECT id FROM categories WHERE id = ");
+            query.push_bind(category);
+            query.push(CATEGORY_DESCENDANTS);
+        }
+        if let Some(slug) = self.category_slug.as_ref() {
+            condition(query, "EXISTS (SELECT 1 FROM product_categories \
+                WHERE product_categories.product_id = products.id AND product_categories.category_id IN (\
+                WITH RECURSIVE tree AS (SELECT id FROM categories WHERE slug = ");
+            query.push_bind(slug.as_str());
+            query.push(CATEGORY_DESCENDANTS);
+        }
+        if let Some(tag) = self.tag {
+            condition(query, "EXISTS (SELECT 1 FROM product_tags \
+                WHERE product_tags.product_id = products.id AND product_tags.tag_id = ");
+            query.push_bind(tag);
+            query.push(")");
+        }
+        if !self.tags.is_empty() {
+            condition(query, "EXISTS (SELECT 1 FROM product_tags \
+                WHERE product_tags.product_id = products.id AND product_tags.tag_id = ANY(");
+            query.push_bind(self.tags.as_slice());
+            query.push("))");
+        }
+        for (name, options) in self.attributes.iter() {
+            condition(query, "EXISTS (SELECT 1 FROM product_attributes \
+                WHERE product_attributes.product_id = products.id AND product_attributes.name = ");
+            query.push_bind(name.as_str());
+            query.push(" AND jsonb_exists_any(product_attributes.options, ");
+            query.push_bind(options.as_slice());
+            query.push("))");
+        }
+        if let Some(rating) = self.rating {
+            condition(query, "products.average_rating >= ");
+            query.push_bind(rating);
+        }
+        if self.listed {
+            condition(query, LISTED);
+        }
+        if !self.skus.is_empty() {
+            condition(query, "products.sku = ANY(");
+            query.push_bind(self.skus.as_slice());
+            query.push(")");
+        }
+        if !self.include.is_empty() {
+            condition(query, "products.id = ANY(");
+            query.push_bind(self.include.as_slice());
+            query.push(")");
+        }
+        if !self.exclude.is_empty() {
+            condition(query, "products.id <> ALL(");
+            query.push_bind(self.exclude.as_slice());
+            query.push(")");
+        }
     }
-    if parameters.sku.is_some() {
-        where_parts.push(format!("products.sku IN ({})",
-            parameters.sku.as_ref().unwrap().as_str().to_string()));
+
+    pub fn new() -> Self {
+        Filters {
+            status: None,
+            on_sale: None,
+            featured: None,
+            min_price: None,
+            max_price: None,
+            stock_status: None,
+            category: None,
+            category_slug: None,
+            tag: None,
+            tags: Vec::new(),
+            attributes: Vec::new(),
+            rating: None,
+            listed: false,
+            skus: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
     }
-    if parameters.include.is_some() {
-        where_parts.push(format!("products.id IN ({})",
-            parameters.include.as_ref().unwrap().as_str().to_string()));
+}
+
+// The products of a storefront listing
+fn listing_filters(parameters: &Parameters, category_slug: Option<&str>) -> Result<Filters, anyhow::Error> {
+    let mut filters = Filters::from_parameters(parameters)?;
+
+    // Only published products are listed in the store
+    filters.status = Some(Status::Publish);
+    filters.listed = true;
+
+    // http://127.0.0.1:8080/product-category/nam-vitae-magna/?category=1
+    filters.category_slug = category_slug.map(|slug| slug.to_string());
+
+    Ok(filters)
+}
+
+// The listings share the columns, see the SELECT of get_page
+fn product_short(row: &PgRow) -> frontend::ProductShort {
+    frontend::ProductShort {
+        id: row.get::<i32, _>("id"),
+        sku: row.get::<String, _>("sku"),
+        name: row.get::<String, _>("name"),
+        slug: row.get::<String, _>("slug"),
+        permalink: row.get::<String, _>("permalink"),
+        description: row.get::<String, _>("description"),
+        short_description: row.get::<String, _>("short_description"),
+        price: row.get::<Decimal, _>("price").to_f32().unwrap_or(0.00),
+        regular_price: row.get::<Decimal, _>("regular_price").to_f32().unwrap_or(0.00),
+        sale_price: row.get::<Decimal, _>("sale_price").to_f32().unwrap_or(0.00),
+        on_sale: row.get::<bool, _>("on_sale"),
+        stock_quantity: row.get::<i32, _>("stock_quantity"),
+        stock_status: row.get::<StockStatus, _>("stock_status"),
+        weight: row.get::<i32, _>("weight") as u32,
+        product_type: row.get::<ProductType, _>("type"),
+        external_url: row.get::<String, _>("external_url"),
+        button_text: row.get::<String, _>("button_text"),
+        gallery: row.get::<Json<Vec<Media>>, _>("gallery"),
     }
-    if parameters.exclude.is_some() {
-        where_parts.push(format!("products.id NOT IN ({})",
-            parameters.exclude.as_ref().unwrap().as_str().to_string()));
+}
+
+// Upper bound for the words of a search, the rest of the query is ignored
+const MAX_SEARCH_TERMS: usize = 10;
+
+// The descriptions are HTML, the snippets are cut from their text only so
+// that no tag is split or shown in the results
+const PLAIN_DESCRIPTION: &str = "regexp_replace(COALESCE(products.short_description, '') || ' ' || \
+    COALESCE(products.description, ''), '<[^>]*>', ' ', 'g')";
+
+// Markers put by ts_headline around the matched words, they can not be
+// typed in a product description so the text is escaped before they are
+// turned into <mark> tags
+const HIGHLIGHT_START: char = '\u{1}';
+const HIGHLIGHT_STOP: char = '\u{2}';
+
+// "blue cott" -> "blue:* & cott:*", every word is matched as a prefix so the
+// results follow the typing. None when nothing is left to search.
+pub fn search_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .take(MAX_SEARCH_TERMS)
+        .map(|term| format!("{}:*", term.to_lowercase()))
+        .collect();
+
+    if terms.is_empty() {
+        return None;
     }
+    Some(terms.join(" & "))
+}
 
-    where_parts
+fn highlight(headline: &str) -> String {
+    tera::escape_html(headline)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_STOP, "</mark>")
 }
 
+// Only the products shown in the search results, see CatalogVisibility
+const SEARCHABLE: &str = "products.status = 'publish' AND products.catalog_visibility IN ('visible', 'search') \
+    AND products.search_vector @@ to_tsquery('english', $1)";
+
 pub struct Products {
     pool: sqlx::Pool<sqlx::Postgres>,
 }
@@ -245,15 +666,17 @@ pub struct Frontend<'a> {
 impl<'a> Frontend<'a> {
 
     pub async fn count_all_category_by_slug(&self, slug: &str)  -> Result<i32, anyhow::Error> {
-        let total_count: (i64, ) = sqlx::query_as(&format!(r#"
+        let total_count: (i64, ) = sqlx::query_as(r#"
             SELECT COUNT(*)
                 FROM products, product_categories, categories
             WHERE
                 products.status = 'publish' AND
+                products.catalog_visibility IN ('visible', 'catalog') AND
                 products.id = product_categories.product_id AND
                 categories.id = product_categories.category_id AND
-                categories.slug = '{}';
-        "#, slug))
+                categories.slug = $1;
+        "#)
+            .bind(slug)
             .fetch_one(self.pool)
             .await?;
 
@@ -272,51 +695,27 @@ impl<'a> Frontend<'a> {
         let products = sqlx::query(&format!(r#"
             SELECT
                 products.id, products.sku, products.name, products.slug, products.permalink, products.description,
-                products.short_description, products.price, products.regular_price, products.sale_price,
-                products.on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
                 products.date_created, products.date_modified,
-                (SELECT (JSON_AGG(ti)::jsonb) FROM (
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
                     SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
                     FROM media, product_media
                     WHERE product_media.product_id = products.id AND product_media.media_id = media.id
                     ORDER BY product_media.position
                 ) ti
-            ) AS gallery
+            ), '[]'::jsonb) AS gallery
             FROM products, product_categories, categories
-            WHERE products.status = 'publish' AND products.id = product_categories.product_id AND categories.id = product_categories.category_id AND categories.slug = $1
+            WHERE products.status = 'publish' AND {} AND products.id = product_categories.product_id AND categories.id = product_categories.category_id AND categories.slug = $1
             ORDER BY
                 products.{} {}
             LIMIT $2 OFFSET $3;
-        "#, order_by.as_str(), order.as_str()))
+        "#, LISTED, order_by.as_str(), order.as_str(), price = PRICE, on_sale = ON_SALE))
             .bind(slug)
             .bind(per_page)
             .bind(offset)
-            .map(|row: PgRow| frontend::ProductShort {
-                id: row.get::<i32, _>("id"),
-                sku: row.get::<String, _>("sku"),
-                name: row.get::<String, _>("name"),
-                slug: row.get::<String, _>("slug"),
-                permalink: row.get::<String, _>("permalink"),
-                description: row.get::<String, _>("description"),
-                short_description: row.get::<String, _>("short_description"),
-                price: match row.get::<Decimal, _>("price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                regular_price: match row.get::<Decimal, _>("regular_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                sale_price: match row.get::<Decimal, _>("sale_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                on_sale: row.get::<bool, _>("on_sale"),
-                stock_quantity: row.get::<i32, _>("stock_quantity"),
-                stock_status: row.get::<StockStatus, _>("stock_status"),
-                weight: row.get::<i32, _>("weight") as u32,
-                gallery: row.get::<Json<Vec<Media>>, _>("gallery"),
-            })
+            .map(|row: PgRow| product_short(&row))
             .fetch_all(self.pool)
             .await?;
 
@@ -324,7 +723,7 @@ impl<'a> Frontend<'a> {
     }
 
     pub async fn categories(&self) -> Result<Vec<Category>, anyhow::Error> {
-        let categories: Vec<Category> = sqlx::query_as::<_, Category>(r#"
+        let categories: Vec<Category> = sqlx::query_as::<_, Category>(&format!(r#"
             WITH RECURSIVE category_tree AS (
                 SELECT id, name, slug, parent, name::VARCHAR AS path,
                     EXISTS(SELECT 1 FROM categories c2 WHERE c2.parent = c.id) AS has_childs,
@@ -335,13 +734,17 @@ impl<'a> Frontend<'a> {
                     ct.branches + 1 AS branches FROM categories c
                 INNER JOIN category_tree ct ON ct.id = c.parent
             ),
-            product_count AS (SELECT category_id, COUNT(*) AS count FROM product_categories GROUP BY category_id),
+            product_count AS (
+                SELECT category_id, COUNT(*) AS count FROM product_categories, products
+                WHERE products.id = product_categories.product_id AND products.status = 'publish' AND {}
+                GROUP BY category_id
+            ),
             category_with_products AS (
                 SELECT ct.*, COALESCE(pc.count, 0) AS count FROM category_tree ct
                 LEFT JOIN product_count pc ON ct.id = pc.category_id
             )
             SELECT id, name, slug, parent, path, has_childs, branches, count FROM category_with_products ORDER BY path;
-        "#)
+        "#, LISTED))
             .fetch_all(self.pool)
             .await?;
 
@@ -349,71 +752,39 @@ impl<'a> Frontend<'a> {
     }
 
     pub async fn get_by_parameters(&self,
-        ids: &Vec<i32>,
-        skus: &Vec<String>,
+        filters: &Filters,
         page: i32,
         per_page: i32,
+        order_by: &str, // a column of products_order_by
         order: types::Order) -> Result<Vec<frontend::ProductShort>, anyhow::Error> {
 
-        let mut where_parts = Vec::new();
-        where_parts.push("products.status = 'publish'".to_string());
-        if !ids.is_empty() {
-            let ids_string: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
-            where_parts.push(format!("products.id IN ({})", ids_string.join(",")));
-        }
-
-        if !skus.is_empty() {
-            where_parts.push(format!("products.sku IN ({})", skus.join(",")));
-        }
-
         let offset = (page - 1) * per_page;
 
-        let products = sqlx::query(&format!(r#"
+        let mut query = QueryBuilder::new(format!(r#"
             SELECT
                 products.id, products.sku, products.name, products.slug, products.permalink, products.description,
-                products.short_description, products.price, products.regular_price, products.sale_price,
-                products.on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
                 products.date_created, products.date_modified,
-                (SELECT (JSON_AGG(ti)::jsonb) FROM (
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
                     SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
                     FROM media, product_media
                     WHERE product_media.product_id = products.id AND product_media.media_id = media.id
                     ORDER BY product_media.position
                 ) ti
-            ) AS gallery
-            FROM products WHERE {}
-            ORDER BY
-                products.date_created {}
-            LIMIT $1 OFFSET $2;
-        "#, where_parts.join(" AND "), order.as_str()))
-            .bind(per_page)
-            .bind(offset)
-            .map(|row: PgRow| frontend::ProductShort {
-                id: row.get::<i32, _>("id"),
-                sku: row.get::<String, _>("sku"),
-                name: row.get::<String, _>("name"),
-                slug: row.get::<String, _>("slug"),
-                permalink: row.get::<String, _>("permalink"),
-                description: row.get::<String, _>("description"),
-                short_description: row.get::<String, _>("short_description"),
-                price: match row.get::<Decimal, _>("price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                regular_price: match row.get::<Decimal, _>("regular_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                sale_price: match row.get::<Decimal, _>("sale_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                on_sale: row.get::<bool, _>("on_sale"),
-                stock_quantity: row.get::<i32, _>("stock_quantity"),
-                stock_status: row.get::<StockStatus, _>("stock_status"),
-                weight: row.get::<i32, _>("weight") as u32,
-                gallery: row.get::<Json<Vec<Media>>, _>("gallery"),
-            })
+            ), '[]'::jsonb) AS gallery
+            FROM products"#, price = PRICE, on_sale = ON_SALE));
+
+        filters.push_where(&mut query);
+
+        query.push(format!(" ORDER BY products.{} {} LIMIT ", order_by, order.as_str()));
+        query.push_bind(per_page);
+        query.push(" OFFSET ");
+        query.push_bind(offset);
+
+        let products = query.build()
+            .map(|row: PgRow| product_short(&row))
             .fetch_all(self.pool)
             .await?;
 
@@ -422,19 +793,21 @@ impl<'a> Frontend<'a> {
 
     pub async fn get_one_by_slug(&self, slug: &str) -> Result<frontend::Product, anyhow::Error> {
 
-        let product = sqlx::query(r#"
+        let product = sqlx::query(&format!(r#"
             SELECT
                 products.id, products.sku, products.name, products.slug, products.permalink,
-                products.description, products.short_description,products.price, products.regular_price,
-                products.sale_price, products.on_sale, products.stock_quantity, products.stock_status,
-                products.weight, products.date_created, products.date_modified,
-                (SELECT (JSON_AGG(ti)::jsonb) FROM (
+                products.description, products.short_description,{price} AS price, products.regular_price,
+                products.sale_price, {on_sale} AS on_sale, products.stock_quantity, products.stock_status,
+                products.weight, products.type, products.external_url, products.button_text,
+                products.average_rating, products.rating_count,
+                products.date_created, products.date_modified,
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
                     SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
                     FROM media, product_media WHERE product_media.product_id = products.id AND product_media.media_id = media.id
-                    ORDER BY product_media.position) ti) AS gallery
+                    ORDER BY product_media.position) ti), '[]'::jsonb) AS gallery
             FROM products
             WHERE products.slug = $1 AND products.status = 'publish';
-        "#)
+        "#, price = PRICE, on_sale = ON_SALE))
             .bind(slug)
             .map(|row: PgRow| frontend::Product {
                 id: row.get::<i32, _>("id"),
@@ -460,6 +833,11 @@ impl<'a> Frontend<'a> {
                 stock_quantity: row.get::<i32, _>("stock_quantity"),
                 stock_status: row.get::<StockStatus, _>("stock_status"),
                 weight: row.get::<i32, _>("weight") as u32,
+                product_type: row.get::<ProductType, _>("type"),
+                external_url: row.get::<String, _>("external_url"),
+                button_text: row.get::<String, _>("button_text"),
+                average_rating: row.get::<Decimal, _>("average_rating").to_f32().unwrap_or(0.00),
+                rating_count: row.get::<i32, _>("rating_count"),
                 gallery: row.get::<Json<Vec<Media>>, _>("gallery"),
             })
             .fetch_one(self.pool)
@@ -468,66 +846,422 @@ impl<'a> Frontend<'a> {
         Ok(product)
     }
 
-    pub async fn get_page(&self,
-        parameters: &Parameters,
-        category_slug: Option<&str>,
-    ) -> Result<frontend::ProductPage, anyhow::Error> {
-
-        let per_page = parameters.per_page.unwrap_or(3) as i32;
+    // The published products of a grouped product, in the order set in the backend
+    pub async fn grouped_children(&self, product_id: i32) -> Result<Vec<frontend::ProductShort>, anyhow::Error> {
+        let products = sqlx::query(&format!(r#"
+            SELECT
+                products.id, products.sku, products.name, products.slug, products.permalink, products.description,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
+                products.date_created, products.date_modified,
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
+                    SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
+                    FROM media, product_media
+                    WHERE product_media.product_id = products.id AND product_media.media_id = media.id
+                    ORDER BY product_media.position
+                ) ti
+            ), '[]'::jsonb) AS gallery
+            FROM grouped_products, products
+            WHERE grouped_products.product_id = $1 AND grouped_products.child_id = products.id
+                AND products.status = 'publish'
+            ORDER BY grouped_products.position;
+        "#, price = PRICE, on_sale = ON_SALE))
+            .bind(product_id)
+            .map(|row: PgRow| product_short(&row))
+            .fetch_all(self.pool)
+            .await?;
 
-        let order = parameters.order.as_ref().unwrap_or(&types::Order::Desc);
-        let order_by = products_order_by(&parameters.order_by);
+        Ok(products)
+    }
 
-        // println!("{:?}", parameters);
+    // The published products linked to any of the given products, without
+    // the given products themselves, e.g. the cross-sells of the cart
+    pub async fn linked_products(&self,
+        product_ids: &[i32],
+        link_type: LinkType,
+        limit: i32) -> Result<Vec<frontend::ProductShort>, anyhow::Error> {
 
-        let (min_price, max_price): (Decimal, Decimal) = sqlx::query_as(r#"
+        let products = sqlx::query(&format!(r#"
+            WITH links AS (
+                SELECT linked_id, MIN(position) AS position FROM linked_products
+                WHERE product_id = ANY($1) AND link_type = $2 AND NOT linked_id = ANY($1)
+                GROUP BY linked_id
+            )
             SELECT
-                COALESCE(MIN(price), 0.00) AS min_price,
-                COALESCE(MAX(price), 0.00) AS max_price
-            FROM products WHERE products.status = $1;
-        "#)
-            .bind(Status::Publish)
-            .fetch_one(self.pool)
+                products.id, products.sku, products.name, products.slug, products.permalink, products.description,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
+                products.date_created, products.date_modified,
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
+                    SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
+                    FROM media, product_media
+                    WHERE product_media.product_id = products.id AND product_media.media_id = media.id
+                    ORDER BY product_media.position
+                ) ti
+            ), '[]'::jsonb) AS gallery
+            FROM links, products
+            WHERE links.linked_id = products.id AND products.status = 'publish'
+            ORDER BY links.position, products.id
+            LIMIT $3;
+        "#, price = PRICE, on_sale = ON_SALE))
+            .bind(product_ids)
+            .bind(link_type)
+            .bind(limit)
+            .map(|row: PgRow| product_short(&row))
+            .fetch_all(self.pool)
             .await?;
 
+        Ok(products)
+    }
 
-        let mut where_parts = where_clause_parts(&parameters);
+    // The listed products sharing the most categories and tags with the
+    // product, its upsells are already shown and left out
+    pub async fn related(&self,
+        product_id: i32,
+        limit: i32) -> Result<Vec<frontend::ProductShort>, anyhow::Error> {
 
-        let mut tables = vec!["products"];
+        let products = sqlx::query(&format!(r#"
+            WITH shared AS (
+                SELECT matches.product_id, COUNT(*) AS score FROM (
+                    SELECT others.product_id FROM product_categories own, product_categories others
+                    WHERE own.product_id = $1 AND others.category_id = own.category_id
+                    UNION ALL
+                    SELECT others.product_id FROM product_tags own, product_tags others
+                    WHERE own.product_id = $1 AND others.tag_id = own.tag_id
+                ) matches
+                WHERE matches.product_id <> $1
+                GROUP BY matches.product_id
+            )
+            SELECT
+                products.id, products.sku, products.name, products.slug, products.permalink, products.description,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
+                products.date_created, products.date_modified,
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
+                    SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
+                    FROM media, product_media
+                    WHERE product_media.product_id = products.id AND product_media.media_id = media.id
+                    ORDER BY product_media.position
+                ) ti
+            ), '[]'::jsonb) AS gallery
+            FROM shared, products
+            WHERE shared.product_id = products.id AND products.status = 'publish' AND {listed}
+                AND products.id NOT IN (
+                    SELECT linked_id FROM linked_products WHERE product_id = $1 AND link_type = 'upsell'
+                )
+            ORDER BY shared.score DESC, products.date_created DESC
+            LIMIT $2;
+        "#, price = PRICE, on_sale = ON_SALE, listed = LISTED))
+            .bind(product_id)
+            .bind(limit)
+            .map(|row: PgRow| product_short(&row))
+            .fetch_all(self.pool)
+            .await?;
 
-        if parameters.category.is_some() || category_slug.is_some() {
+        Ok(products)
+    }
 
-            // http://127.0.0.1:8080/product-category/nam-vitae-magna/
-            if parameters.category.is_some() {
-                where_parts.push(format!("product_categories.category_id={} AND product_categories.product_id=products.id",
-                    parameters.category.unwrap()));
-            }
+    // Ranked by weight: name and SKU, then the short description, then the description
+    pub async fn search(&self,
+        query: &str,
+        page: i32,
+        per_page: i32) -> Result<frontend::SearchPage, anyhow::Error> {
 
-            // http://127.0.0.1:8080/product-category/nam-vitae-magna/?category=1
-            if category_slug.is_some() {
-                let slug = category_slug.unwrap();
-                where_parts.push(format!("categories.slug='{}' AND categories.id=product_categories.category_id AND product_categories.product_id=products.id", slug));
-                tables.push("categories");
-            };
+        let total: i64 = sqlx::query(&format!("SELECT COUNT(*) FROM products WHERE {};", SEARCHABLE))
+            .bind(query)
+            .fetch_one(self.pool)
+            .await?
+            .get(0);
 
-            tables.push("product_categories");
-        }
+        let total_pages = ((total as f32 / per_page as f32).ceil() as i32).max(1);
+        let page = page.clamp(1, total_pages);
 
-        let from = if where_parts.is_empty() {
-            "products".to_string()
-        } else {
-            format!("{} WHERE {}", tables.join(", "), where_parts.join(" AND "))
-        };
+        let options = format!("StartSel={}, StopSel={}", HIGHLIGHT_START, HIGHLIGHT_STOP);
 
-        let total: (i64, ) = sqlx::query_as(&format!(r#"
-            SELECT COUNT(*) FROM {};
-        "#, from))
-            .fetch_one(self.pool)
+        let results = sqlx::query(&format!(r#"
+            SELECT
+                products.id, products.sku, products.name, products.slug, products.permalink, products.description,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
+                products.date_created, products.date_modified,
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
+                    SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
+                    FROM media, product_media
+                    WHERE product_media.product_id = products.id AND product_media.media_id = media.id
+                    ORDER BY product_media.position
+                ) ti
+            ), '[]'::jsonb) AS gallery,
+                ts_headline('english', products.name, to_tsquery('english', $1),
+                    $4 || ', HighlightAll=true') AS name_highlight,
+                ts_headline('english', {plain_text},
+                    to_tsquery('english', $1),
+                    $4 || ', MaxWords=30, MinWords=10, MaxFragments=2, FragmentDelimiter=" … "') AS snippet
+            FROM products
+            WHERE {}
+            ORDER BY ts_rank(products.search_vector, to_tsquery('english', $1)) DESC, products.name
+            LIMIT $2 OFFSET $3;
+        "#, SEARCHABLE, price = PRICE, on_sale = ON_SALE, plain_text = PLAIN_DESCRIPTION))
+            .bind(query)
+            .bind(per_page)
+            .bind((page - 1) * per_page)
+            .bind(&options)
+            .map(|row: PgRow| frontend::SearchResult {
+                product: product_short(&row),
+                name_highlight: highlight(&row.get::<String, _>("name_highlight")),
+                snippet: highlight(&row.get::<String, _>("snippet")),
+            })
+            .fetch_all(self.pool)
             .await?;
 
-        if total.0 == 0 {
-            return Ok(ProductPage {
-                products: Vec::new(),
+        Ok(frontend::SearchPage {
+            results,
+            total_count: total as i32,
+            current_page: page,
+            per_page,
+            total_pages,
+        })
+    }
+
+    // The best matches for an autocomplete box
+    pub async fn suggestions(&self, query: &str, limit: i32) -> Result<Vec<frontend::Suggestion>, anyhow::Error> {
+        let suggestions = sqlx::query(&format!(r#"
+            SELECT products.id, products.name, products.permalink, {price} AS price,
+                (SELECT media.src FROM media, product_media
+                WHERE product_media.product_id = products.id AND product_media.media_id = media.id
+                ORDER BY product_media.position LIMIT 1) AS image
+            FROM products
+            WHERE {}
+            ORDER BY ts_rank(products.search_vector, to_tsquery('english', $1)) DESC, products.name
+            LIMIT $2;
+        "#, SEARCHABLE, price = PRICE))
+            .bind(query)
+            .bind(limit)
+            .map(|row: PgRow| frontend::Suggestion {
+                id: row.get::<i32, _>("id"),
+                name: row.get::<String, _>("name"),
+                permalink: row.get::<String, _>("permalink"),
+                price: row.get::<Decimal, _>("price").to_f32().unwrap_or(0.00),
+                image: row.get::<Option<String>, _>("image"),
+            })
+            .fetch_all(self.pool)
+            .await?;
+
+        Ok(suggestions)
+    }
+
+    // (name, option, count) of the attribute options of the filtered products,
+    // of a single attribute when a name is given
+    async fn attribute_counts(&self,
+        filters: &Filters,
+        name: Option<&str>) -> Result<Vec<(String, String, i64)>, anyhow::Error> {
+
+        let mut query = QueryBuilder::new(r#"
+            SELECT product_attributes.name, options.value, COUNT(DISTINCT products.id)
+            FROM products
+            JOIN product_attributes ON product_attributes.product_id = products.id"#);
+        if let Some(name) = name {
+            query.push(" AND product_attributes.name = ");
+            query.push_bind(name);
+        }
+        query.push(" CROSS JOIN LATERAL jsonb_array_elements_text(product_attributes.options) AS options(value)");
+        filters.push_where(&mut query);
+        query.push(" GROUP BY product_attributes.name, options.value ORDER BY product_attributes.name, options.value");
+
+        let counts = query.build_query_as::<(String, String, i64)>()
+            .fetch_all(self.pool)
+            .await?;
+
+        Ok(counts)
+    }
+
+    // The counts of a facet leave out its own selection, so that more values
+    // of the facet can be picked
+    pub async fn facets(&self,
+        parameters: &Parameters,
+        category_slug: Option<&str>,
+    ) -> Result<frontend::Facets, anyhow::Error> {
+
+        let filters = listing_filters(parameters, category_slug)?;
+
+        let mut others = filters.clone();
+        others.category = None;
+        others.category_slug = None;
+
+        let mut query = QueryBuilder::new(r#"
+            WITH RECURSIVE tree AS (
+                SELECT id, id AS root FROM categories
+                UNION ALL
+                SELECT categories.id, tree.root FROM categories
+                JOIN tree ON categories.parent = tree.id
+            )
+            SELECT tree.root, COUNT(DISTINCT products.id) FROM tree
+            JOIN product_categories ON product_categories.category_id = tree.id
+            JOIN products ON products.id = product_categories.product_id"#);
+        others.push_where(&mut query);
+        query.push(" GROUP BY tree.root");
+
+        let counts: HashMap<i32, i64> = query.build_query_as::<(i32, i64)>()
+            .fetch_all(self.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut categories = self.categories().await?;
+        for category in categories.iter_mut() {
+            category.count = counts.get(&category.id).copied().unwrap_or(0);
+        }
+
+        let mut others = filters.clone();
+        others.tags = Vec::new();
+
+        let mut query = QueryBuilder::new(r#"
+            SELECT tags.id, tags.name, COUNT(*) AS count FROM tags
+            JOIN product_tags ON product_tags.tag_id = tags.id
+            JOIN products ON products.id = product_tags.product_id"#);
+        others.push_where(&mut query);
+        query.push(" GROUP BY tags.id, tags.name ORDER BY tags.name");
+
+        let tags = query.build()
+            .map(|row: PgRow| {
+                let id = row.get::<i32, _>("id");
+                frontend::FacetOption {
+                    value: id.to_string(),
+                    label: row.get::<String, _>("name"),
+                    count: row.get::<i64, _>("count"),
+                    selected: filters.tags.contains(&id),
+                }
+            })
+            .fetch_all(self.pool)
+            .await?;
+
+        // The attributes without a selection are counted together, each
+        // selected attribute on its own
+        let mut counts: Vec<(String, String, i64)> = self.attribute_counts(&filters, None)
+            .await?
+            .into_iter()
+            .filter(|(name, _, _)| !filters.attributes.iter().any(|(selected, _)| selected == name))
+            .collect();
+        for (name, _) in filters.attributes.iter() {
+            let mut others = filters.clone();
+            others.attributes.retain(|(other, _)| other != name);
+            counts.extend(self.attribute_counts(&others, Some(name)).await?);
+        }
+        counts.sort();
+
+        let mut attributes: Vec<frontend::AttributeFacet> = vec![];
+        for (name, option, count) in counts {
+            let selected = filters.attributes.iter()
+                .any(|(selected, options)| *selected == name && options.contains(&option));
+            let option = frontend::FacetOption {
+                value: format!("{}:{}", name, option),
+                label: option,
+                count,
+                selected,
+            };
+            match attributes.last_mut() {
+                Some(attribute) if attribute.name == name => attribute.options.push(option),
+                _ => attributes.push(frontend::AttributeFacet { name, options: vec![option] }),
+            }
+        }
+
+        let mut others = filters.clone();
+        others.stock_status = None;
+
+        let mut query = QueryBuilder::new("SELECT products.stock_status, COUNT(*) FROM products");
+        others.push_where(&mut query);
+        query.push(" GROUP BY products.stock_status");
+
+        let counts = query.build_query_as::<(StockStatus, i64)>()
+            .fetch_all(self.pool)
+            .await?;
+
+        let stock_statuses = StockStatus::iter()
+            .map(|status| frontend::FacetOption {
+                value: status.as_str().to_string(),
+                label: status.label().to_string(),
+                count: counts.iter()
+                    .find(|(other, _)| *other == status)
+                    .map_or(0, |(_, count)| *count),
+                selected: filters.stock_status.as_ref() == Some(&status),
+            })
+            .collect();
+
+        let mut others = filters.clone();
+        others.rating = None;
+
+        let mut query = QueryBuilder::new("SELECT FLOOR(products.average_rating)::INT, COUNT(*) FROM products");
+        others.push_where(&mut query);
+        query.push(" GROUP BY 1");
+
+        let counts = query.build_query_as::<(i32, i64)>()
+            .fetch_all(self.pool)
+            .await?;
+
+        // "4 stars & up" counts the products rated 4 and 5
+        let ratings = (1..=5).rev()
+            .map(|stars| frontend::FacetOption {
+                value: stars.to_string(),
+                label: match stars {
+                    5 => "5 stars".to_string(),
+                    1 => "1 star & up".to_string(),
+                    _ => format!("{} stars & up", stars),
+                },
+                count: counts.iter()
+                    .filter(|(rating, _)| *rating >= stars)
+                    .map(|(_, count)| count)
+                    .sum(),
+                selected: filters.rating == Some(stars),
+            })
+            .collect();
+
+        Ok(frontend::Facets {
+            categories,
+            tags,
+            attributes,
+            stock_statuses,
+            ratings,
+        })
+    }
+
+    pub async fn get_page(&self,
+        parameters: &Parameters,
+        category_slug: Option<&str>,
+    ) -> Result<frontend::ProductPage, anyhow::Error> {
+
+        let per_page = parameters.per_page.unwrap_or(3) as i32;
+
+        let order = parameters.order.as_ref().unwrap_or(&types::Order::Desc);
+        let order_by = products_order_by(&parameters.order_by);
+
+        // println!("{:?}", parameters);
+
+        let (min_price, max_price): (Decimal, Decimal) = sqlx::query_as(&format!(r#"
+            SELECT
+                COALESCE(MIN(price), 0.00) AS min_price,
+                COALESCE(MAX(price), 0.00) AS max_price
+            FROM products WHERE products.status = $1 AND {};
+        "#, LISTED))
+            .bind(Status::Publish)
+            .fetch_one(self.pool)
+            .await?;
+
+
+        let filters = listing_filters(parameters, category_slug)?;
+
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM products");
+        filters.push_where(&mut query);
+
+        let total: (i64, ) = query.build_query_as()
+            .fetch_one(self.pool)
+            .await?;
+
+        if total.0 == 0 {
+            return Ok(ProductPage {
+                products: Vec::new(),
                 total_count: 0,
                 current_page: 0,
                 per_page: 0,
@@ -552,52 +1286,31 @@ impl<'a> Frontend<'a> {
 
         let offset = (page - 1) * per_page;
 
-        let products = sqlx::query(&format!(r#"
+        let mut query = QueryBuilder::new(format!(r#"
             SELECT
                 products.id, products.sku, products.name, products.slug, products.permalink, products.description,
-                products.short_description, products.price, products.regular_price, products.sale_price,
-                products.on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.short_description, {price} AS price, products.regular_price, products.sale_price,
+                {on_sale} AS on_sale, products.stock_quantity, products.stock_status, products.weight,
+                products.type, products.external_url, products.button_text,
                 products.date_created, products.date_modified,
-                (SELECT (JSON_AGG(ti)::jsonb) FROM (
+                COALESCE((SELECT (JSON_AGG(ti)::jsonb) FROM (
                     SELECT media.id, media.src, media.name, media.alt, media.date_created, media.date_modified, product_media.position
                     FROM media, product_media
                     WHERE product_media.product_id = products.id AND product_media.media_id = media.id
                     ORDER BY product_media.position
                 ) ti
-            ) AS gallery
-            FROM {}
-            ORDER BY
-                products.{} {}
-            LIMIT $1 OFFSET $2;
-        "#, from, order_by, order.as_str()))
-            .bind(per_page)
-            .bind(offset)
-            .map(|row: PgRow| frontend::ProductShort {
-                id: row.get::<i32, _>("id"),
-                sku: row.get::<String, _>("sku"),
-                name: row.get::<String, _>("name"),
-                slug: row.get::<String, _>("slug"),
-                permalink: row.get::<String, _>("permalink"),
-                description: row.get::<String, _>("description"),
-                short_description: row.get::<String, _>("short_description"),
-                price: match row.get::<Decimal, _>("price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                regular_price: match row.get::<Decimal, _>("regular_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                sale_price: match row.get::<Decimal, _>("sale_price").to_f32() {
-                    Some(f) => f,
-                    None => 0.00,
-                },
-                on_sale: row.get::<bool, _>("on_sale"),
-                stock_quantity: row.get::<i32, _>("stock_quantity"),
-                stock_status: row.get::<StockStatus, _>("stock_status"),
-                weight: row.get::<i32, _>("weight") as u32,
-                gallery: row.get::<Json<Vec<Media>>, _>("gallery"),
-            })
+            ), '[]'::jsonb) AS gallery
+            FROM products"#, price = PRICE, on_sale = ON_SALE));
+
+        filters.push_where(&mut query);
+
+        query.push(format!(" ORDER BY products.{} {} LIMIT ", order_by, order.as_str()));
+        query.push_bind(per_page);
+        query.push(" OFFSET ");
+        query.push_bind(offset);
+
+        let products = query.build()
+            .map(|row: PgRow| product_short(&row))
             .fetch_all(self.pool)
             .await?;
 
@@ -630,6 +1343,18 @@ pub enum ImageOperation {
     Insert,
 }
 
+// A change made to every selected product of the list, see Backend::bulk_edit
+#[derive(Debug)]
+pub enum BulkAction {
+    Status(Status),
+    Trash,
+    Restore, // to the status before the trash
+    AddCategories(Vec<i32>),
+    RemoveCategories(Vec<i32>),
+    Stock(Option<i32>, Option<StockStatus>), // the quantity and the status, none is left as it is
+    Price(f32), // a percentage of the regular and sale prices, e.g. 10.0 or -25.0
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProductImage {
     pub id: i32,
@@ -669,6 +1394,449 @@ impl ProductImage {
     }
 }
 
+// Prices are f32, they are stored in the audit log as they are shown (19.99, not 19.989999771118164)
+fn audit_snapshot(product: &backend::Product) -> Result<JsonValue, anyhow::Error> {
+    let mut snapshot = serde_json::to_value(product)?;
+    for (key, price) in [
+        ("price", product.price),
+        ("regular_price", product.regular_price),
+        ("sale_price", product.sale_price),
+    ] {
+        snapshot[key] = JsonValue::String(format!("{:.2}", price));
+    }
+    Ok(snapshot)
+}
+
+// The product of a snapshot, e.g. of a revision, the prices are numbers again
+fn from_snapshot(snapshot: &JsonValue) -> Result<backend::Product, anyhow::Error> {
+    let mut snapshot = snapshot.clone();
+    for key in ["price", "regular_price", "sale_price"] {
+        let price = snapshot[key].as_str()
+            .and_then(|price| price.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        snapshot[key] = JsonValue::from(price);
+    }
+    Ok(serde_json::from_value(snapshot)?)
+}
+
+// A grouped product is listed from the price of its cheapest product,
+// and in stock while one of them is
+async fn sync_grouped<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        UPDATE products
+        SET price = children.price, regular_price = children.price, sale_price = 0.00, on_sale = FALSE,
+            stock_status = CASE WHEN children.in_stock
+                THEN 'instock'::stock_status ELSE 'outofstock'::stock_status END
+        FROM (
+            SELECT MIN(products.price) AS price, BOOL_OR(products.stock_status = 'instock') AS in_stock
+            FROM grouped_products, products
+            WHERE grouped_products.product_id = $1 AND grouped_products.child_id = products.id
+                AND products.status = 'publish'
+            HAVING COUNT(*) > 0
+        ) AS children
+        WHERE products.id = $1;
+    "#)
+        .bind(product_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+async fn delete_categories<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        DELETE FROM product_categories WHERE product_id = $1;
+    "#)
+        .bind(product_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+async fn add_category<'c, E: sqlx::PgExecutor<'c>>(executor: E, category_id: i32, product_id: i32) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        INSERT INTO product_categories (product_id, category_id)
+        VALUES ($1, $2);
+    "#)
+        .bind(product_id)
+        .bind(category_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+// A grouped product can not contain itself or another grouped product
+async fn set_grouped_products(connection: &mut PgConnection, product_id: i32, children: &[i32]) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        DELETE FROM grouped_products WHERE product_id = $1;
+    "#)
+        .bind(product_id)
+        .execute(&mut *connection)
+        .await?;
+
+    sqlx::query(r#"
+        INSERT INTO grouped_products (product_id, child_id, position)
+        SELECT $1, products.id, children.position
+        FROM UNNEST($2::INT[]) WITH ORDINALITY AS children(id, position)
+        JOIN products ON products.id = children.id
+        WHERE products.id <> $1 AND products.type <> 'grouped'
+        ON CONFLICT DO NOTHING;
+    "#)
+        .bind(product_id)
+        .bind(children)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(())
+}
+
+// A product is not linked to itself, the ids are kept in the given order
+async fn set_linked_products(connection: &mut PgConnection, product_id: i32, link_type: LinkType, linked: &[i32]) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        DELETE FROM linked_products WHERE product_id = $1 AND link_type = $2;
+    "#)
+        .bind(product_id)
+        .bind(&link_type)
+        .execute(&mut *connection)
+        .await?;
+
+    sqlx::query(r#"
+        INSERT INTO linked_products (product_id, linked_id, link_type, position)
+        SELECT $1, products.id, $2, linked.position
+        FROM UNNEST($3::INT[]) WITH ORDINALITY AS linked(id, position)
+        JOIN products ON products.id = linked.id
+        WHERE products.id <> $1
+        ON CONFLICT DO NOTHING;
+    "#)
+        .bind(product_id)
+        .bind(&link_type)
+        .bind(linked)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(())
+}
+
+// Saves the product, its relations, revision and audit entry with the
+// connection of the caller's transaction. The files of the deleted media are
+// returned, they are removed once the transaction is committed.
+async fn save(connection: &mut PgConnection,
+    product: &backend::Product,
+    images: &HashMap<i32, ImageOperation>,
+    delete_media: bool) -> Result<Vec<PathBuf>, anyhow::Error> {
+
+    let before = audit_snapshot(&fetch(&mut *connection, product.id).await?)?;
+    revisions::record_original_with(&mut *connection, product.id, &before).await?;
+
+    sqlx::query(r#"
+        UPDATE products
+        SET name = $1, slug = $2, description = $3, short_description = $4, sku = $5,
+            price = $6, regular_price = $7, sale_price = $8, on_sale = $9,
+            stock_quantity = $10, stock_status= $11, weight = $12, permalink = $13, status = $14, primary_category = $15,
+            type = $16, external_url = $17, button_text = $18, catalog_visibility = $19,
+            date_on_sale_from = $20, date_on_sale_to = $21,
+            trashed_status = CASE WHEN $14 = 'trash' THEN trashed_status END,
+            date_trashed = CASE WHEN $14 = 'trash' THEN COALESCE(date_trashed, LOCALTIMESTAMP) END
+        WHERE id = $22;
+    "#)
+        .bind(&product.name)
+        .bind(&product.slug)
+        .bind(&product.description)
+        .bind(&product.short_description)
+        .bind(&product.sku)
+        .bind(&product.price)
+        .bind(&product.regular_price)
+        .bind(&product.sale_price)
+        .bind(&product.on_sale)
+        .bind(product.stock_quantity)
+        .bind(&product.stock_status)
+        .bind(product.weight as i32)
+        .bind(&product.permalink)
+        .bind(&product.status)
+        .bind(&product.primary_category)
+        .bind(&product.product_type)
+        .bind(&product.external_url)
+        .bind(&product.button_text)
+        .bind(&product.catalog_visibility)
+        .bind(product.date_on_sale_from)
+        .bind(product.date_on_sale_to)
+        .bind(&product.id)
+        .execute(&mut *connection)
+        .await?;
+
+    // An empty list leaves the current categories as they are
+    if !product.categories.is_empty() {
+        delete_categories(&mut *connection, product.id).await?;
+        for category_id in &product.categories {
+            add_category(&mut *connection, *category_id, product.id).await?;
+        }
+    }
+
+    for image in &product.images {
+        println!("IMAGE {:?}", image);
+        if !images.contains_key(&image.id) {
+            continue;
+        }
+
+        let operation = match images.get(&image.id) {
+            Some(operation) => operation,
+            None => continue,
+        };
+
+        println!("IMAGE OPERATION {:?}", operation);
+
+        if *operation == ImageOperation::Insert {
+            // insert image in product_media
+            sqlx::query(r#"
+                INSERT INTO product_media (product_id, media_id, position)
+                VALUES ($1, $2, $3);
+            "#)
+                .bind(&product.id)
+                .bind(&image.id)
+                .bind(&image.position)
+                .execute(&mut *connection)
+                .await?;
+
+            // images.remove(&image.id);
+
+            continue;
+        }
+
+        if *operation == ImageOperation::Update {
+            // update image from product_media
+            sqlx::query(r#"
+                UPDATE product_media
+                SET position = $1
+                WHERE product_id = $2 AND media_id = $3;
+            "#)
+                .bind(&image.position)
+                .bind(&product.id)
+                .bind(&image.id)
+                .execute(&mut *connection)
+                .await?;
+
+            // update media name and alt in table media
+            sqlx::query(r#"
+                UPDATE media
+                SET name = $1, alt = $2
+                WHERE id = $3;
+            "#)
+                .bind(&image.name)
+                .bind(&image.alt)
+                .bind(&image.id)
+                .execute(&mut *connection)
+                .await?;
+        }
+    }
+    
+    let mut files = vec![];
+    for (image_id, operation) in images {
+        if *operation != ImageOperation::Delete {
+            continue;
+        }
+        println!("Deleting image {}", image_id);
+
+        sqlx::query(r#"
+            DELETE FROM product_media
+            WHERE media_id = $1 AND product_id = $2;
+        "#)
+            .bind(&image_id)
+            .bind(product.id)
+            .execute(&mut *connection)
+            .await?;
+
+        if !delete_media {
+            continue;
+        }
+
+        let media = sqlx::query(r#"
+            DELETE FROM media
+            WHERE id = $1
+            RETURNING src, name, alt;
+        "#)
+            .bind(&image_id)
+            .fetch_one(&mut *connection)
+            .await?;
+
+        let image_src = media.get::<String, _>("src");
+
+        files.extend(media_file(&image_src));
+
+        audit::record_with(&mut *connection, "media.delete", "media", *image_id,
+            Some(serde_json::json!({
+                "src": image_src,
+                "name": media.get::<String, _>("name"),
+                "alt": media.get::<Option<String>, _>("alt"),
+                "product_id": product.id,
+            })), None)
+            .await?;
+    }
+
+    tags::set_product_tags(&mut *connection, product.id, &product.tags).await?;
+    set_grouped_products(&mut *connection, product.id, &product.grouped_products).await?;
+    set_linked_products(&mut *connection, product.id, LinkType::Upsell, &product.upsells).await?;
+    set_linked_products(&mut *connection, product.id, LinkType::CrossSell, &product.cross_sells).await?;
+
+    match product.product_type {
+        ProductType::Variable => variations::sync_product(&mut *connection, product.id).await?,
+        ProductType::Grouped => sync_grouped(&mut *connection, product.id).await?,
+        _ => {},
+    }
+
+    let after = audit_snapshot(&fetch(&mut *connection, product.id).await?)?;
+    revisions::record_with(&mut *connection, product.id, &after).await?;
+    audit::record_with(&mut *connection, "product.update", "product", product.id, Some(before), Some(after)).await?;
+
+    Ok(files)
+}
+
+// The product as the edit form shows it, e.g. before and after a change in a transaction
+async fn fetch<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<backend::Product, anyhow::Error> {
+    let row = sqlx::query(r#"
+        SELECT
+            products.id, products.sku, products.name, products.slug,
+            products.description, products.short_description,
+            products.price, products.regular_price, products.sale_price, products.on_sale,
+            products.date_on_sale_from, products.date_on_sale_to,
+            products.stock_quantity, products.stock_status, products.weight, products.permalink,
+            products.date_created, products.status, products.primary_category, products.type,
+            products.catalog_visibility, products.external_url, products.button_text,
+            COALESCE( (SELECT to_jsonb(ARRAY_AGG(child_id ORDER BY position))
+            FROM grouped_products WHERE grouped_products.product_id = products.id), '[]') AS grouped_products,
+            COALESCE( (SELECT to_jsonb(ARRAY_AGG(linked_id ORDER BY position))
+            FROM linked_products WHERE linked_products.product_id = products.id
+                AND linked_products.link_type = 'upsell'), '[]') AS upsells,
+            COALESCE( (SELECT to_jsonb(ARRAY_AGG(linked_id ORDER BY position))
+            FROM linked_products WHERE linked_products.product_id = products.id
+                AND linked_products.link_type = 'cross_sell'), '[]') AS cross_sells,
+            COALESCE( (SELECT (JSON_AGG(ti)::jsonb)
+            FROM (
+                SELECT media.id, media.src, media.name, media.alt, product_media.position
+                FROM media, product_media
+                WHERE media.id = product_media.media_id AND product_media.product_id = products.id
+                ORDER BY product_media.position
+            ) ti), '[]') AS images,
+            COALESCE( (SELECT to_jsonb(ARRAY_AGG(category_id ORDER BY category_id))
+            FROM product_categories WHERE product_categories.product_id = products.id), '[]') AS categories,
+            COALESCE( (SELECT to_jsonb(ARRAY_AGG(tags.name ORDER BY tags.name))
+            FROM product_tags, tags WHERE product_tags.product_id = products.id AND tags.id = product_tags.tag_id), '[]') AS tags
+        FROM products WHERE products.id = $1;
+    "#)
+        .bind(&product_id)
+        .fetch_one(executor)
+        .await?;
+
+    let images_json: JsonValue = row.get("images");
+    let categories_json: JsonValue = row.get("categories");
+    let grouped_products_json: JsonValue = row.get("grouped_products");
+    let upsells_json: JsonValue = row.get("upsells");
+    let cross_sells_json: JsonValue = row.get("cross_sells");
+    let tags_json: JsonValue = row.get("tags");
+
+    Ok(backend::Product {
+        id: row.get::<i32, _>("id"),
+        sku: row.get::<String, _>("sku"),
+        name: row.get::<String, _>("name"),
+        slug: row.get::<String, _>("slug"),
+        description: row.get::<String, _>("description"),
+        short_description: row.get::<String, _>("short_description"),
+        regular_price: match row.get::<Decimal, _>("regular_price").to_f32() {
+            Some(f) => f,
+            None => 0.00,
+        },
+        price: match row.get::<Decimal, _>("price").to_f32() {
+            Some(f) => f,
+            None => 0.00,
+        },
+        sale_price: match row.get::<Decimal, _>("sale_price").to_f32() {
+            Some(f) => f,
+            None => 0.00,
+        },
+        on_sale: row.get::<bool, _>("on_sale"),
+        date_on_sale_from: row.get::<Option<NaiveDateTime>, _>("date_on_sale_from"),
+        date_on_sale_to: row.get::<Option<NaiveDateTime>, _>("date_on_sale_to"),
+        stock_status: row.get::<StockStatus, _>("stock_status"),
+        stock_quantity: row.get::<i32, _>("stock_quantity"),
+        weight: row.get::<i32, _>("weight") as u32,
+        status: row.get::<Status, _>("status"),
+        product_type: row.get::<ProductType, _>("type"),
+        catalog_visibility: row.get::<CatalogVisibility, _>("catalog_visibility"),
+        external_url: row.get::<String, _>("external_url"),
+        button_text: row.get::<String, _>("button_text"),
+        grouped_products: serde_json::from_value(grouped_products_json).unwrap(),
+        upsells: serde_json::from_value(upsells_json).unwrap(),
+        cross_sells: serde_json::from_value(cross_sells_json).unwrap(),
+        permalink: row.get::<String, _>("permalink"),
+        primary_category: row.get::<i32, _>("primary_category"),
+        images: serde_json::from_value(images_json).unwrap(),
+        categories: serde_json::from_value(categories_json).unwrap(),
+        tags: serde_json::from_value(tags_json).unwrap(),
+    })
+}
+
+// The slug of a new product, numbered when it is taken, e.g. "t-shirt-2"
+pub async fn unique_slug<'c, E: sqlx::PgExecutor<'c>>(executor: E, name: &str) -> Result<String, anyhow::Error> {
+    let slug = slug::slugify(name);
+
+    let taken: Vec<String> = sqlx::query(r#"
+        SELECT slug FROM products WHERE slug = $1 OR slug LIKE $1 || '-%';
+    "#)
+        .bind(&slug)
+        .map(|row: PgRow| row.get::<String, _>("slug"))
+        .fetch_all(executor)
+        .await?;
+
+    let mut candidate = slug.clone();
+    let mut number = 1;
+    while taken.contains(&candidate) {
+        number += 1;
+        candidate = format!("{}-{}", slug, number);
+    }
+
+    Ok(candidate)
+}
+
+// The uploaded file of a media, "/static/uploads/2024/06/file.png" or the same
+// path on MEDIA_ORIGIN. None for the media hosted elsewhere, they have no file here.
+fn media_file(src: &str) -> Option<PathBuf> {
+    let path = src.strip_prefix(MEDIA_ORIGIN).unwrap_or(src);
+    let path = Path::new(path.strip_prefix("/static/uploads/")?);
+
+    // e.g. "/static/uploads/../../Cargo.toml"
+    if path.as_os_str().is_empty() || !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(Path::new("static/uploads").join(path))
+}
+
+// The SKU of a copy, e.g. "TSHIRT-copy" then "TSHIRT-copy-2", an empty SKU stays empty
+async fn unique_sku<'c, E: sqlx::PgExecutor<'c>>(executor: E, sku: &str) -> Result<String, anyhow::Error> {
+    if sku.is_empty() {
+        return Ok("".to_string());
+    }
+
+    let sku = format!("{}-copy", sku);
+    let taken: Vec<String> = sqlx::query(r#"
+        SELECT sku FROM products WHERE sku = $1 OR sku LIKE $1 || '-%';
+    "#)
+        .bind(&sku)
+        .map(|row: PgRow| row.get::<String, _>("sku"))
+        .fetch_all(executor)
+        .await?;
+
+    let mut candidate = sku.clone();
+    let mut number = 1;
+    while taken.contains(&candidate) {
+        number += 1;
+        candidate = format!("{}-{}", sku, number);
+    }
+
+    Ok(candidate)
+}
+
 pub struct Backend<'a> {
     pool: &'a sqlx::Pool<sqlx::Postgres>,
 }
@@ -685,7 +1853,7 @@ impl<'a> Backend<'a> {
             INSERT INTO media (src, name, alt)
             VALUES ($1, $2, $3) RETURNING id;
         "#)
-            .bind(format!("{}/{}", "http://127.0.0.1:8080", filepath))
+            .bind(format!("{}/{}", MEDIA_ORIGIN, filepath))
             .bind(name)
             .bind(alt)
             .fetch_one(self.pool)
@@ -694,37 +1862,59 @@ impl<'a> Backend<'a> {
         Ok(media_row.0)
     }
 
-    pub async fn delete_categories(&self,
-        product_id: i32,
-    ) -> Result<(), anyhow::Error> {
+    pub async fn sync_grouped(&self, product_id: i32) -> Result<(), anyhow::Error> {
+        sync_grouped(self.pool, product_id).await
+    }
 
-        sqlx::query(r#"
-            DELETE FROM product_categories WHERE product_id = $1;
+    // The same window as on_sale_sql, for a product about to be saved
+    pub async fn sale_is_active(&self,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>) -> Result<bool, anyhow::Error> {
+
+        let active: bool = sqlx::query(r#"
+            SELECT ($1::TIMESTAMP IS NULL OR $1 <= LOCALTIMESTAMP)
+                AND ($2::TIMESTAMP IS NULL OR $2 > LOCALTIMESTAMP);
         "#)
-            .bind(&product_id)
-            .execute(self.pool)
-            .await?;
+            .bind(from)
+            .bind(to)
+            .fetch_one(self.pool)
+            .await?
+            .get(0);
 
-        Ok(())
+        Ok(active)
     }
 
-    pub async fn add_category(&self,
-        category_id: &i32,
-        product_id: &i32) -> Result<(), anyhow::Error> {
-        // Implementation to add a category to a product
+    // Stores the price and on_sale of the products whose sale started or ended,
+    // variable products follow their variations and grouped ones their products
+    pub async fn apply_sale_schedule(&self) -> Result<Vec<i32>, anyhow::Error> {
+        let ids: Vec<i32> = sqlx::query(&format!(r#"
+            UPDATE products SET on_sale = {on_sale}, price = {price}
+            WHERE products.type IN ('simple', 'external')
+                AND (products.on_sale IS DISTINCT FROM {on_sale} OR products.price IS DISTINCT FROM {price})
+            RETURNING products.id;
+        "#, on_sale = ON_SALE, price = PRICE))
+            .fetch_all(self.pool)
+            .await?
+            .iter()
+            .map(|row| row.get::<i32, _>("id"))
+            .collect();
 
-        println!("adding category {} to product {}", category_id, product_id);
+        if ids.is_empty() {
+            return Ok(ids);
+        }
 
-        sqlx::query(r#"
-            INSERT INTO product_categories (product_id, category_id)
-            VALUES ($1, $2);
+        let parents = sqlx::query(r#"
+            SELECT DISTINCT product_id FROM grouped_products WHERE child_id = ANY($1);
         "#)
-           .bind(product_id)
-           .bind(category_id)
-           .execute(self.pool)
-           .await?;
+            .bind(&ids)
+            .fetch_all(self.pool)
+            .await?;
 
-        Ok(())
+        for parent in parents {
+            self.sync_grouped(parent.get::<i32, _>("product_id")).await?;
+        }
+
+        Ok(ids)
     }
 
     pub async fn update(&self,
@@ -733,194 +1923,85 @@ impl<'a> Backend<'a> {
         delete_media: bool) -> Result<(), anyhow::Error> {
         // Implementation to update a product
 
-        sqlx::query(r#"
-            UPDATE products
-            SET name = $1, slug = $2, description = $3, short_description = $4, sku = $5,
-                price = $6, regular_price = $7, sale_price = $8, on_sale = $9,
-                stock_quantity = $10, stock_status= $11, weight = $12, permalink = $13, status = $14, primary_category = $15
-            WHERE id = $16;
-        "#)
-            .bind(&product.name)
-            .bind(&product.slug)
-            .bind(&product.description)
-            .bind(&product.short_description)
-            .bind(&product.sku)
-            .bind(&product.price)
-            .bind(&product.regular_price)
-            .bind(&product.sale_price)
-            .bind(&product.on_sale)
-            .bind(product.stock_quantity)
-            .bind(&product.stock_status)
-            .bind(product.weight as i32)
-            .bind(&product.permalink)
-            .bind(&product.status)
-            .bind(&product.primary_category)
-            .bind(&product.id)
-            .execute(self.pool)
-            .await?;
-
+        let mut transaction = self.pool.begin().await?;
+        let files = save(&mut transaction, product, images, delete_media).await?;
+        transaction.commit().await?;
 
-        for image in &product.images {
-            println!("IMAGE {:?}", image);
-            if !images.contains_key(&image.id) {
-                continue;
+        // The files are removed once the rows and their audit entries are saved
+        for file_path in files {
+            if file_path.exists() {
+                fs::remove_file(file_path).expect("Failed to remove file");
             }
+        }
 
-            let operation = match images.get(&image.id) {
-                Some(operation) => operation,
-                None => continue,
-            };
+        Ok(())
+    }
 
-            println!("IMAGE OPERATION {:?}", operation);
+    pub async fn get(&self, product_id: i32) -> Result<backend::Product, anyhow::Error> {
+        fetch(self.pool, product_id).await
+    }
 
-            if *operation == ImageOperation::Insert {
-                // insert image in product_media
-                sqlx::query(r#"
-                    INSERT INTO product_media (product_id, media_id, position)
-                    VALUES ($1, $2, $3);
-                "#)
-                    .bind(&product.id)
-                    .bind(&image.id)
-                    .bind(&image.position)
-                    .execute(self.pool)
-                    .await?;
+    // Saves the product as it was in the revision, which makes a new revision.
+    // The images, categories and products deleted since then are left out,
+    // and a product of the trash stays there.
+    pub async fn rollback(&self, product_id: i32, revision_id: i32) -> Result<(), anyhow::Error> {
+        let revision = revisions::Revisions::new(self.pool.clone()).get(product_id, revision_id).await?;
 
-                // images.remove(&image.id);
+        let mut transaction = self.pool.begin().await?;
+        let current = fetch(&mut *transaction, product_id).await?;
+        let mut product = from_snapshot(&revision.snapshot)?;
+        product.id = product_id;
 
-                continue;
-            }
+        if current.status == Status::Trash || product.status == Status::Trash {
+            product.status = current.status;
+        }
 
-            if *operation == ImageOperation::Update {
-                // update image from product_media
-                sqlx::query(r#"
-                    UPDATE product_media
-                    SET position = $1
-                    WHERE product_id = $2 AND media_id = $3;
-                "#)
-                    .bind(&image.position)
-                    .bind(&product.id)
-                    .bind(&image.id)
-                    .execute(self.pool)
-                    .await?;
+        let media_ids: Vec<i32> = product.images.iter().map(|image| image.id).collect();
+        let existing_media: Vec<i32> = sqlx::query(r#"
+            SELECT id FROM media WHERE id = ANY($1);
+        "#)
+            .bind(&media_ids)
+            .map(|row: PgRow| row.get::<i32, _>("id"))
+            .fetch_all(&mut *transaction)
+            .await?;
+        product.images.retain(|image| existing_media.contains(&image.id));
 
-                // update media name and alt in table media
-                sqlx::query(r#"
-                    UPDATE media
-                    SET name = $1, alt = $2
-                    WHERE id = $3;
-                "#)
-                    .bind(&image.name)
-                    .bind(&image.alt)
-                    .bind(&image.id)
-                    .execute(self.pool)
-                    .await?;
-            }
+        let mut images = HashMap::new();
+        for image in &product.images {
+            let operation = match current.images.iter().any(|current| current.id == image.id) {
+                true => ImageOperation::Update,
+                false => ImageOperation::Insert,
+            };
+            images.insert(image.id, operation);
+        }
+        for image in &current.images {
+            images.entry(image.id).or_insert(ImageOperation::Delete);
         }
-        
-        for (image_id, operation) in images {
-            if *operation != ImageOperation::Delete {
-                continue;
-            }
-            println!("Deleting image {}", image_id);
-
-            sqlx::query(r#"
-                DELETE FROM product_media
-                WHERE media_id = $1;
-            "#)
-                .bind(&image_id)
-                .execute(self.pool)
-                .await?;
 
-            if delete_media {
-                let image_src: String = sqlx::query(r#"
-                    DELETE FROM media
-                    WHERE id = $1
-                    RETURNING src;
-                "#)
-                    .bind(&image_id)
-                    .fetch_one(self.pool)
-                    .await?
-                    .get(0);
-
-
-                // http://127.0.0.1:8080/uploads/2024-06-18/file.png
-                // let image_src = image_row.get::<String, _>("src");
-                let parsed_url = match Url::parse(&image_src) {
-                    Ok(url) => url,
-                    Err(_) => {
-                        return Err(anyhow::anyhow!("Invalid URL: {}", image_src));
-                    }
-                };
+        let existing_categories: Vec<i32> = sqlx::query(r#"
+            SELECT id FROM categories WHERE id = ANY($1);
+        "#)
+            .bind(&product.categories)
+            .map(|row: PgRow| row.get::<i32, _>("id"))
+            .fetch_all(&mut *transaction)
+            .await?;
+        product.categories.retain(|category_id| existing_categories.contains(category_id));
 
-                let path = parsed_url.path();
-                let file_path = PathBuf::from(format!("static/{}", path));
-            
-                if file_path.exists() {
-                    fs::remove_file(file_path).expect("Failed to remove file");
-                }
-            }
+        // save() keeps the categories when there are none
+        if product.categories.is_empty() {
+            delete_categories(&mut *transaction, product_id).await?;
         }
 
-        Ok(())
-    }
-
-    pub async fn get(&self, product_id: i32) -> Result<backend::Product, anyhow::Error> {
-        // Implementation to get a product by ID
+        // The unlinked images stay in the media library, no file is removed
+        save(&mut transaction, &product, &images, false).await?;
 
-        let row = sqlx::query(r#"
-            SELECT
-                products.id, products.sku, products.name, products.slug,
-                products.description, products.short_description,
-                products.price, products.regular_price, products.sale_price, products.on_sale,
-                products.stock_quantity, products.stock_status, products.weight, products.permalink,
-                products.date_created, products.status, products.primary_category,
-                COALESCE( (SELECT (JSON_AGG(ti)::jsonb)
-                FROM (
-                    SELECT media.id, media.src, media.name, media.alt, product_media.position
-                    FROM media, product_media
-                    WHERE media.id = product_media.media_id AND product_media.product_id = products.id
-                    ORDER BY product_media.position
-                ) ti), '[]') AS images,
-                COALESCE( (SELECT to_jsonb(ARRAY_AGG(category_id))
-                FROM product_categories WHERE product_categories.product_id = products.id), '[]') AS categories
-            FROM products WHERE products.id = $1;
-        "#)
-            .bind(&product_id)
-            .fetch_one(self.pool)
+        audit::record_with(&mut *transaction, "product.rollback", "product", product_id,
+            None, Some(serde_json::json!({ "revision": revision_id })))
             .await?;
 
-        let images_json: JsonValue = row.get("images");
-        let categories_json: JsonValue = row.get("categories");
-
-        Ok(backend::Product {
-            id: row.get::<i32, _>("id"),
-            sku: row.get::<String, _>("sku"),
-            name: row.get::<String, _>("name"),
-            slug: row.get::<String, _>("slug"),
-            description: row.get::<String, _>("description"),
-            short_description: row.get::<String, _>("short_description"),
-            regular_price: match row.get::<Decimal, _>("regular_price").to_f32() {
-                Some(f) => f,
-                None => 0.00,
-            },
-            price: match row.get::<Decimal, _>("price").to_f32() {
-                Some(f) => f,
-                None => 0.00,
-            },
-            sale_price: match row.get::<Decimal, _>("sale_price").to_f32() {
-                Some(f) => f,
-                None => 0.00,
-            },
-            on_sale: row.get::<bool, _>("on_sale"),
-            stock_status: row.get::<StockStatus, _>("stock_status"),
-            stock_quantity: row.get::<i32, _>("stock_quantity"),
-            weight: row.get::<i32, _>("weight") as u32,
-            status: row.get::<Status, _>("status"),
-            permalink: row.get::<String, _>("permalink"),
-            primary_category: row.get::<i32, _>("primary_category"),
-            images: serde_json::from_value(images_json).unwrap(),
-            categories: serde_json::from_value(categories_json).unwrap(),
-        })
+        transaction.commit().await?;
+
+        Ok(())
     }
 
     pub async fn add(&self,
@@ -928,12 +2009,15 @@ impl<'a> Backend<'a> {
     ) -> Result<i32, anyhow::Error> {
         // Implementation to add a new product
 
+        let mut transaction = self.pool.begin().await?;
+
         let product_id: i32 = sqlx::query(r#"
             INSERT INTO products (
                 name, slug, description, sku,
                 price, regular_price, sale_price, on_sale,
-                stock_quantity, stock_status, weight, permalink, status
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING id;
+                stock_quantity, stock_status, weight, permalink, status, type,
+                external_url, button_text, catalog_visibility, date_on_sale_from, date_on_sale_to
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) RETURNING id;
         "#)
            .bind(&product.name)
            .bind(&product.slug)
@@ -948,7 +2032,13 @@ impl<'a> Backend<'a> {
            .bind(product.weight as i32)
            .bind(&product.permalink)
            .bind(&product.status)
-           .fetch_one(self.pool)
+           .bind(&product.product_type)
+           .bind(&product.external_url)
+           .bind(&product.button_text)
+           .bind(&product.catalog_visibility)
+           .bind(product.date_on_sale_from)
+           .bind(product.date_on_sale_to)
+           .fetch_one(&mut *transaction)
            .await?
            .get(0);
 
@@ -960,13 +2050,418 @@ impl<'a> Backend<'a> {
                .bind(&product_id)
                .bind(&image.id)
                .bind(&image.position)
-               .execute(self.pool)
+               .execute(&mut *transaction)
                .await?;
         }
 
+        for category_id in &product.categories {
+            add_category(&mut *transaction, *category_id, product_id).await?;
+        }
+
+        tags::set_product_tags(&mut transaction, product_id, &product.tags).await?;
+        set_grouped_products(&mut transaction, product_id, &product.grouped_products).await?;
+        set_linked_products(&mut transaction, product_id, LinkType::Upsell, &product.upsells).await?;
+        set_linked_products(&mut transaction, product_id, LinkType::CrossSell, &product.cross_sells).await?;
+        if product.product_type == ProductType::Grouped {
+            sync_grouped(&mut *transaction, product_id).await?;
+        }
+
+        let after = audit_snapshot(&fetch(&mut *transaction, product_id).await?)?;
+        revisions::record_with(&mut *transaction, product_id, &after).await?;
+        audit::record_with(&mut *transaction, "product.create", "product", product_id, None, Some(after)).await?;
+
+        transaction.commit().await?;
+
         Ok(product_id)
     }
 
+    // A draft copy of the product, with the same images, categories, tags,
+    // attributes, variations and linked products. The images are linked,
+    // the media files are not copied. The sales and reviews are not copied.
+    pub async fn duplicate(&self, product_id: i32) -> Result<i32, anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let product = fetch(&mut *transaction, product_id).await?;
+        let name = format!("{} (Copy)", product.name);
+        let slug = unique_slug(&mut *transaction, &name).await?;
+        let sku = unique_sku(&mut *transaction, &product.sku).await?;
+
+        let copy_id: i32 = sqlx::query(r#"
+            INSERT INTO products (
+                sku, name, slug, permalink, type, status, featured, catalog_visibility,
+                description, short_description, price, regular_price, sale_price, on_sale,
+                date_on_sale_from, date_on_sale_to, manage_stock, stock_quantity, stock_status, weight,
+                primary_category, external_url, button_text
+            )
+            SELECT
+                $2, $3, $4, $5, type, 'draft', featured, catalog_visibility,
+                description, short_description, price, regular_price, sale_price, on_sale,
+                date_on_sale_from, date_on_sale_to, manage_stock, stock_quantity, stock_status, weight,
+                primary_category, external_url, button_text
+            FROM products WHERE id = $1
+            RETURNING id;
+        "#)
+            .bind(product_id)
+            .bind(&sku)
+            .bind(&name)
+            .bind(&slug)
+            .bind(format!("/product/{}", slug))
+            .fetch_one(&mut *transaction)
+            .await?
+            .get(0);
+
+        // The variations get an empty SKU, the copies would share theirs
+        for query in [
+            "INSERT INTO product_media (product_id, media_id, position) SELECT $2, media_id, position FROM product_media WHERE product_id = $1;",
+            "INSERT INTO product_categories (product_id, category_id) SELECT $2, category_id FROM product_categories WHERE product_id = $1;",
+            "INSERT INTO product_tags (product_id, tag_id) SELECT $2, tag_id FROM product_tags WHERE product_id = $1;",
+            "INSERT INTO product_attributes (product_id, name, options, position) SELECT $2, name, options, position FROM product_attributes WHERE product_id = $1;",
+            "INSERT INTO dimentions (product_id, length, width, height) SELECT $2, length, width, height FROM dimentions WHERE product_id = $1;",
+            "INSERT INTO grouped_products (product_id, child_id, position) SELECT $2, child_id, position FROM grouped_products WHERE product_id = $1;",
+            "INSERT INTO linked_products (product_id, linked_id, link_type, position) SELECT $2, linked_id, link_type, position FROM linked_products WHERE product_id = $1;",
+            r#"INSERT INTO product_variations (product_id, sku, attributes, price, regular_price, sale_price, on_sale, stock_quantity, stock_status, weight, media_id, position)
+                SELECT $2, '', attributes, price, regular_price, sale_price, on_sale, stock_quantity, stock_status, weight, media_id, position
+                FROM product_variations WHERE product_id = $1 ORDER BY id;"#,
+        ] {
+            sqlx::query(query)
+                .bind(product_id)
+                .bind(copy_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        let mut after = audit_snapshot(&fetch(&mut *transaction, copy_id).await?)?;
+        revisions::record_with(&mut *transaction, copy_id, &after).await?;
+        after["duplicate_of"] = JsonValue::from(product_id);
+        audit::record_with(&mut *transaction, "product.duplicate", "product", copy_id, None, Some(after))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(copy_id)
+    }
+
+    // Applies the action to all the products or none of them. The prices and
+    // the stock of variable and grouped products follow their variations and
+    // products, the price change goes to the variations and the stock change
+    // skips them. Returns the number of products changed.
+    pub async fn bulk_edit(&self, product_ids: &[i32], action: &BulkAction) -> Result<usize, anyhow::Error> {
+        if let BulkAction::Price(percentage) = action {
+            if !percentage.is_finite() || *percentage <= -100.0 {
+                return Err(anyhow::anyhow!("The price change must be above -100%"));
+            }
+        }
+
+        let mut transaction = self.pool.begin().await?;
+        let mut changed = 0;
+
+        for product_id in product_ids {
+            let before = match fetch(&mut *transaction, *product_id).await {
+                Err(e) if matches!(e.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => {
+                    return Err(anyhow::anyhow!("The product {} does not exist", product_id));
+                },
+                result => result?,
+            };
+
+            match action {
+                BulkAction::Status(status) => {
+                    sqlx::query(r#"
+                        UPDATE products SET status = $2, trashed_status = NULL, date_trashed = NULL WHERE id = $1;
+                    "#)
+                        .bind(product_id)
+                        .bind(status)
+                        .execute(&mut *transaction)
+                        .await?;
+                },
+                BulkAction::Trash => {
+                    let result = sqlx::query(r#"
+                        UPDATE products SET trashed_status = status, status = 'trash', date_trashed = LOCALTIMESTAMP
+                        WHERE id = $1 AND status <> 'trash';
+                    "#)
+                        .bind(product_id)
+                        .execute(&mut *transaction)
+                        .await?;
+                    if result.rows_affected() == 0 {
+                        continue;
+                    }
+                },
+                BulkAction::Restore => {
+                    let result = sqlx::query(r#"
+                        UPDATE products SET status = COALESCE(trashed_status, 'draft'), trashed_status = NULL, date_trashed = NULL
+                        WHERE id = $1 AND status = 'trash';
+                    "#)
+                        .bind(product_id)
+                        .execute(&mut *transaction)
+                        .await?;
+                    if result.rows_affected() == 0 {
+                        continue;
+                    }
+                },
+                BulkAction::AddCategories(category_ids) => {
+                    sqlx::query(r#"
+                        INSERT INTO product_categories (product_id, category_id)
+                        SELECT $1, categories.id FROM categories WHERE categories.id = ANY($2)
+                        ON CONFLICT DO NOTHING;
+                    "#)
+                        .bind(product_id)
+                        .bind(category_ids)
+                        .execute(&mut *transaction)
+                        .await?;
+                },
+                BulkAction::RemoveCategories(category_ids) => {
+                    sqlx::query("DELETE FROM product_categories WHERE product_id = $1 AND category_id = ANY($2);")
+                        .bind(product_id)
+                        .bind(category_ids)
+                        .execute(&mut *transaction)
+                        .await?;
+                },
+                BulkAction::Stock(quantity, status) => {
+                    if matches!(before.product_type, ProductType::Variable | ProductType::Grouped) {
+                        continue;
+                    }
+                    sqlx::query(r#"
+                        UPDATE products
+                        SET stock_quantity = COALESCE($2, stock_quantity), stock_status = COALESCE($3, stock_status)
+                        WHERE id = $1;
+                    "#)
+                        .bind(product_id)
+                        .bind(quantity)
+                        .bind(status)
+                        .execute(&mut *transaction)
+                        .await?;
+                },
+                BulkAction::Price(percentage) => {
+                    let factor = 1.0 + *percentage as f64 / 100.0;
+                    match before.product_type {
+                        ProductType::Grouped => continue,
+                        ProductType::Variable => {
+                            sqlx::query(r#"
+                                UPDATE product_variations
+                                SET regular_price = ROUND(regular_price * $2::NUMERIC, 2),
+                                    sale_price = ROUND(sale_price * $2::NUMERIC, 2)
+                                WHERE product_id = $1;
+                            "#)
+                                .bind(product_id)
+                                .bind(factor)
+                                .execute(&mut *transaction)
+                                .await?;
+                            sqlx::query(r#"
+                                UPDATE product_variations
+                                SET on_sale = (sale_price > 0 AND sale_price < regular_price),
+                                    price = CASE WHEN sale_price > 0 AND sale_price < regular_price
+                                        THEN sale_price ELSE regular_price END
+                                WHERE product_id = $1;
+                            "#)
+                                .bind(product_id)
+                                .execute(&mut *transaction)
+                                .await?;
+                            variations::sync_product(&mut *transaction, *product_id).await?;
+                        },
+                        _ => {
+                            sqlx::query(r#"
+                                UPDATE products
+                                SET regular_price = ROUND(regular_price * $2::NUMERIC, 2),
+                                    sale_price = ROUND(sale_price * $2::NUMERIC, 2)
+                                WHERE id = $1;
+                            "#)
+                                .bind(product_id)
+                                .bind(factor)
+                                .execute(&mut *transaction)
+                                .await?;
+                            sqlx::query(&format!(r#"
+                                UPDATE products SET on_sale = {on_sale}, price = {price} WHERE id = $1;
+                            "#, on_sale = ON_SALE, price = PRICE))
+                                .bind(product_id)
+                                .execute(&mut *transaction)
+                                .await?;
+                        },
+                    }
+                },
+            }
+
+            // The main category is one of the categories of the product
+            if matches!(action, BulkAction::AddCategories(_) | BulkAction::RemoveCategories(_)) {
+                sqlx::query(r#"
+                    UPDATE products
+                    SET primary_category = COALESCE((
+                        SELECT category_id FROM product_categories
+                        WHERE product_id = products.id
+                        ORDER BY category_id = products.primary_category DESC, category_id LIMIT 1
+                    ), 0)
+                    WHERE id = $1;
+                "#)
+                    .bind(product_id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+
+            let audit_action = match action {
+                BulkAction::Trash => "product.trash",
+                BulkAction::Restore => "product.restore",
+                _ => "product.update",
+            };
+            let before = audit_snapshot(&before)?;
+            let after = audit_snapshot(&fetch(&mut *transaction, *product_id).await?)?;
+            revisions::record_original_with(&mut *transaction, *product_id, &before).await?;
+            revisions::record_with(&mut *transaction, *product_id, &after).await?;
+            audit::record_with(&mut *transaction, audit_action, "product", *product_id,
+                Some(before), Some(after))
+                .await?;
+            changed += 1;
+        }
+
+        // The grouped products show the price and stock of their published products
+        let parents: Vec<i32> = sqlx::query(r#"
+            SELECT DISTINCT product_id FROM grouped_products WHERE child_id = ANY($1);
+        "#)
+            .bind(product_ids)
+            .map(|row: PgRow| row.get::<i32, _>("product_id"))
+            .fetch_all(&mut *transaction)
+            .await?;
+
+        for parent in parents {
+            sync_grouped(&mut *transaction, parent).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(changed)
+    }
+
+    // Deletes products of the trash for good, all of them or none. Their
+    // images are removed from the media library too when delete_media is
+    // set, except those used by another product, a variation or a category.
+    pub async fn delete(&self, product_ids: &[i32], delete_media: bool) -> Result<usize, anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let mut parents = vec![];
+        let mut files = vec![];
+
+        for product_id in product_ids {
+            let product = match fetch(&mut *transaction, *product_id).await {
+                Err(e) if matches!(e.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => {
+                    return Err(anyhow::anyhow!("The product {} does not exist", product_id));
+                },
+                result => result?,
+            };
+            if product.status != Status::Trash {
+                return Err(anyhow::anyhow!("The product {} is not in the trash", product_id));
+            }
+
+            let rows = sqlx::query(r#"
+                SELECT product_id FROM grouped_products WHERE child_id = $1;
+            "#)
+                .bind(product_id)
+                .fetch_all(&mut *transaction)
+                .await?;
+            parents.extend(rows.iter().map(|row| row.get::<i32, _>("product_id")));
+
+            sqlx::query("DELETE FROM products WHERE id = $1;")
+                .bind(product_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            audit::record_with(&mut *transaction, "product.delete", "product", *product_id,
+                Some(audit_snapshot(&product)?), None)
+                .await?;
+
+            if !delete_media {
+                continue;
+            }
+
+            for image in &product.images {
+                let media = sqlx::query(r#"
+                    DELETE FROM media
+                    WHERE id = $1
+                        AND NOT EXISTS (SELECT 1 FROM product_media WHERE media_id = media.id)
+                        AND NOT EXISTS (SELECT 1 FROM product_variations WHERE media_id = media.id)
+                        AND NOT EXISTS (SELECT 1 FROM categories WHERE media_id = media.id)
+                    RETURNING src, name, alt;
+                "#)
+                    .bind(image.id)
+                    .fetch_optional(&mut *transaction)
+                    .await?;
+
+                let Some(media) = media else {
+                    continue;
+                };
+
+                let image_src = media.get::<String, _>("src");
+                files.extend(media_file(&image_src));
+
+                audit::record_with(&mut *transaction, "media.delete", "media", image.id,
+                    Some(serde_json::json!({
+                        "src": image_src,
+                        "name": media.get::<String, _>("name"),
+                        "alt": media.get::<Option<String>, _>("alt"),
+                        "product_id": product_id,
+                    })), None)
+                    .await?;
+            }
+        }
+
+        for parent in parents.iter().filter(|parent| !product_ids.contains(parent)) {
+            sync_grouped(&mut *transaction, *parent).await?;
+        }
+
+        transaction.commit().await?;
+
+        // The files are removed once the rows and their audit entries are saved
+        for file_path in files {
+            if file_path.exists() {
+                if let Err(e) = fs::remove_file(&file_path) {
+                    eprintln!("Error removing {}: {}", file_path.display(), e);
+                }
+            }
+        }
+
+        Ok(product_ids.len())
+    }
+
+    // Deletes the products trashed more than the given days ago, their images
+    // stay in the media library
+    pub async fn purge_trash(&self, days: i32) -> Result<Vec<i32>, anyhow::Error> {
+        let ids: Vec<i32> = sqlx::query(r#"
+            SELECT id FROM products
+            WHERE status = 'trash' AND date_trashed < LOCALTIMESTAMP - MAKE_INTERVAL(days => $1)
+            ORDER BY id;
+        "#)
+            .bind(days)
+            .map(|row: PgRow| row.get::<i32, _>("id"))
+            .fetch_all(self.pool)
+            .await?;
+
+        if !ids.is_empty() {
+            self.delete(&ids, false).await?;
+        }
+
+        Ok(ids)
+    }
+
+    pub async fn count_trash(&self) -> Result<i32, anyhow::Error> {
+        let total_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM products WHERE status = 'trash'")
+            .fetch_one(self.pool)
+            .await?;
+
+        Ok(total_count.0 as i32)
+    }
+
+    // The ids of the products with this SKU, the SKU alone is not unique
+    pub async fn find_by_sku(&self, sku: &str) -> Result<Vec<i32>, anyhow::Error> {
+        let ids: Vec<i32> = sqlx::query(r#"
+            SELECT id FROM products WHERE sku = $1 ORDER BY id;
+        "#)
+            .bind(sku)
+            .map(|row: PgRow| row.get::<i32, _>("id"))
+            .fetch_all(self.pool)
+            .await?;
+
+        Ok(ids)
+    }
+
+    pub async fn unique_slug(&self, name: &str) -> Result<String, anyhow::Error> {
+        unique_slug(self.pool, name).await
+    }
+
     pub async fn count_all(&self) -> Result<i32, anyhow::Error> {
         let total_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM products")
             .fetch_one(self.pool)
@@ -984,19 +2479,12 @@ impl<'a> Backend<'a> {
         let order = parameters.order.as_ref().unwrap_or(&types::Order::Asc);
         let order_by = products_order_by(&parameters.order_by);
 
-        let where_parts = where_clause_parts(&parameters);
+        let filters = Filters::from_parameters(parameters)?;
 
-        let from = if where_parts.is_empty() {
-            "products".to_string()
-        } else {
-            format!("products WHERE {}", where_parts.join(" AND "))
-        };
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM products");
+        filters.push_where(&mut query);
 
-        let total: (i64, ) = sqlx::query_as(&format!(r#"
-            SELECT 
-                COUNT(*)
-            FROM {};
-        "#, from))
+        let total: (i64, ) = query.build_query_as()
             .fetch_one(self.pool)
             .await?;
 
@@ -1019,12 +2507,12 @@ impl<'a> Backend<'a> {
 
         let offset = (page - 1) * per_page;
 
-        let products = sqlx::query(&format!(r#"
+        let mut query = QueryBuilder::new(r#"
             SELECT
                 products.id, products.sku, products.name, products.price,
                 products.regular_price, products.sale_price, products.on_sale,
                 products.stock_quantity, products.stock_status, products.date_created,
-                products.status, products.primary_category,
+                products.status, products.primary_category, products.total_sales,
                 COALESCE(image.src, '/assets/images/product.jpg') AS image_src, 
                 COALESCE(image.name, 'Unnamed product') AS image_name, 
                 COALESCE(image.alt, 'Unnamed product') AS image_alt
@@ -1036,13 +2524,16 @@ impl<'a> Backend<'a> {
                 JOIN media
                 ON product_media.media_id = media.id WHERE product_media.product_id = products.id
                 ORDER BY product_media.position LIMIT 1
-            ) AS image ON true 
-            ORDER BY 
-                products.{} {}
-            LIMIT $1 OFFSET $2;
-        "#, order_by, order.as_str()))
-            .bind(per_page)
-            .bind(offset)
+            ) AS image ON true"#);
+
+        filters.push_where(&mut query);
+
+        query.push(format!(" ORDER BY products.{} {} LIMIT ", order_by, order.as_str()));
+        query.push_bind(per_page);
+        query.push(" OFFSET ");
+        query.push_bind(offset);
+
+        let products = query.build()
             .map(|row: PgRow| backend::ProductShort {
                 id: row.get::<i32, _>("id"),
                 sku: row.get::<String, _>("sku"),
@@ -1060,6 +2551,7 @@ impl<'a> Backend<'a> {
                 stock_quantity: row.get::<i32, _>("stock_quantity"),
                 image_src: row.get::<String, _>("image_src"),
                 image_alt: row.get::<String, _>("image_alt"),
+                total_sales: row.get::<i32, _>("total_sales"),
                 date_created: || -> String {
                     let date_created = row.get::<NaiveDateTime, _>("date_created");
                     date_created.format("%Y/%m/%d at %H:%M:%S").to_string()
@@ -1083,4 +2575,30 @@ impl<'a> Backend<'a> {
             pool,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_file_is_the_upload_of_a_local_media() {
+        assert_eq!(media_file("/static/uploads/2024/06/file.png"), Some(PathBuf::from("static/uploads/2024/06/file.png")));
+        assert_eq!(media_file("http://127.0.0.1:8080/static/uploads/2024/06/file.png"), Some(PathBuf::from("static/uploads/2024/06/file.png")));
+    }
+
+    #[test]
+    fn media_file_skips_the_media_hosted_elsewhere() {
+        for src in [
+            "https://picsum.photos/240/300",
+            "https://example.com/static/uploads/2024/06/file.png",
+            "/static/assets/logo.png",
+            "/static/uploads/../../Cargo.toml",
+            "/static/uploads//etc/passwd",
+            "/static/uploads/",
+            "",
+        ] {
+            assert_eq!(media_file(src), None, "{}", src);
+        }
+    }
+}
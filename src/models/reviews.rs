@@ -0,0 +1,297 @@
+//
+// Description: Customer reviews and star ratings of the products
+// Last Modification: 2026-10-18 10:17:38
+//
+
+use crate::models::audit;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use sqlx::{
+    postgres::PgRow,
+    Postgres,
+    QueryBuilder,
+    Row,
+};
+use strum::{
+    EnumIter,
+    IntoEnumIterator,
+};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+
+// Upper bound for the text of a review, in characters
+pub const MAX_CONTENT_LENGTH: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type, EnumIter)]
+#[sqlx(type_name = "review_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewStatus {
+    Pending, // Default, waiting for moderation
+    Approved, // shown on the product page and counted in its rating
+    Spam,
+    Trash,
+}
+
+impl ReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::Spam => "spam",
+            ReviewStatus::Trash => "trash",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "Pending",
+            ReviewStatus::Approved => "Approved",
+            ReviewStatus::Spam => "Spam",
+            ReviewStatus::Trash => "Trash",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ReviewStatus> {
+        ReviewStatus::iter().find(|status| status.as_str() == value)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Review {
+    pub id: i32,
+    pub product_id: i32,
+    pub product_name: String,
+    pub user_id: i32,
+    pub author: String,
+    pub rating: i32, // 1 to 5 stars
+    pub content: String,
+    pub status: ReviewStatus,
+    pub verified: bool, // the author bought the product
+    pub date_created: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewPage {
+    pub reviews: Vec<Review>,
+    pub total_count: i32,
+    pub current_page: i32,
+    pub per_page: i32,
+    pub total_pages: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Parameters {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    pub status: Option<String>, // empty lists every status
+}
+
+fn review(row: &PgRow) -> Review {
+    Review {
+        id: row.get::<i32, _>("id"),
+        product_id: row.get::<i32, _>("product_id"),
+        product_name: row.get::<String, _>("product_name"),
+        user_id: row.get::<i32, _>("user_id"),
+        author: row.get::<String, _>("author"),
+        rating: row.get::<i16, _>("rating") as i32,
+        content: row.get::<String, _>("content"),
+        status: row.get::<ReviewStatus, _>("status"),
+        verified: row.get::<bool, _>("verified"),
+        date_created: row.get::<NaiveDateTime, _>("date_created").format("%b %d, %Y").to_string(),
+    }
+}
+
+const REVIEW_COLUMNS: &str = "reviews.id, reviews.product_id, products.name AS product_name, reviews.user_id, \
+    reviews.author, reviews.rating, reviews.content, reviews.status, reviews.verified, reviews.date_created";
+
+fn push_where<'args>(status: Option<&'args ReviewStatus>, query: &mut QueryBuilder<'args, Postgres>) {
+    query.push(" WHERE reviews.product_id = products.id");
+    if let Some(status) = status {
+        query.push(" AND reviews.status = ").push_bind(status);
+    }
+}
+
+// The cached rating of a product, from its approved reviews
+async fn sync_rating<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        UPDATE products SET
+            average_rating = COALESCE(approved.average, 0.00),
+            rating_count = approved.count
+        FROM (
+            SELECT ROUND(AVG(rating), 2) AS average, COUNT(*) AS count
+            FROM reviews WHERE product_id = $1 AND status = 'approved'
+        ) approved
+        WHERE products.id = $1;
+    "#)
+        .bind(product_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub struct Reviews {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl Reviews {
+
+    // A verified review comes from a customer with a paid order of the
+    // product. The review waits for moderation.
+    pub async fn add(&self,
+        product_id: i32,
+        user_id: i32,
+        author: &str,
+        rating: i32,
+        content: &str) -> Result<i32, anyhow::Error> {
+
+        if !(1..=5).contains(&rating) {
+            return Err(anyhow::anyhow!("invalid rating: {}, expected 1 to 5 stars", rating));
+        }
+        if content.chars().count() > MAX_CONTENT_LENGTH {
+            return Err(anyhow::anyhow!("the review is longer than {} characters", MAX_CONTENT_LENGTH));
+        }
+
+        let review_id: i32 = sqlx::query(r#"
+            INSERT INTO reviews (product_id, user_id, author, rating, content, verified)
+            VALUES ($1, $2, $3, $4, $5, EXISTS (
+                SELECT 1 FROM orders
+                WHERE orders.customer_id = $2 AND orders.status IN ('processing', 'completed')
+                    AND orders.line_items @> jsonb_build_array(jsonb_build_object('product_id', $1::INT))
+            ))
+            RETURNING id;
+        "#)
+            .bind(product_id)
+            .bind(user_id)
+            .bind(author)
+            .bind(rating as i16)
+            .bind(content)
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        Ok(review_id)
+    }
+
+    // A customer reviews a product once
+    pub async fn has_reviewed(&self, product_id: i32, user_id: i32) -> Result<bool, anyhow::Error> {
+        let reviewed: bool = sqlx::query(r#"
+            SELECT EXISTS (SELECT 1 FROM reviews WHERE product_id = $1 AND user_id = $2);
+        "#)
+            .bind(product_id)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        Ok(reviewed)
+    }
+
+    // The reviews shown on the product page, the newest first
+    pub async fn approved(&self, product_id: i32) -> Result<Vec<Review>, anyhow::Error> {
+        let reviews = sqlx::query(&format!(r#"
+            SELECT {} FROM reviews, products
+            WHERE reviews.product_id = products.id AND reviews.product_id = $1 AND reviews.status = 'approved'
+            ORDER BY reviews.date_created DESC, reviews.id DESC;
+        "#, REVIEW_COLUMNS))
+            .bind(product_id)
+            .map(|row: PgRow| review(&row))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(reviews)
+    }
+
+    pub async fn get_page(&self, parameters: &Parameters) -> Result<ReviewPage, anyhow::Error> {
+        let per_page = parameters.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, 100) as i32;
+
+        let status = match parameters.status.as_deref().filter(|status| !status.is_empty()) {
+            Some(status) => Some(ReviewStatus::parse(status)
+                .ok_or(anyhow::anyhow!("unknown review status: {}", status))?),
+            None => None,
+        };
+
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM reviews, products");
+        push_where(status.as_ref(), &mut query);
+
+        let total: i64 = query
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        let total_pages = ((total as f32 / per_page as f32).ceil() as i32).max(1);
+        let page = (parameters.page.unwrap_or(1) as i32).clamp(1, total_pages);
+
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM reviews, products", REVIEW_COLUMNS));
+        push_where(status.as_ref(), &mut query);
+        query.push(" ORDER BY reviews.date_created DESC, reviews.id DESC LIMIT ")
+            .push_bind(per_page)
+            .push(" OFFSET ")
+            .push_bind((page - 1) * per_page);
+
+        let reviews = query
+            .build()
+            .map(|row: PgRow| review(&row))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(ReviewPage {
+            reviews,
+            total_count: total as i32,
+            current_page: page,
+            per_page,
+            total_pages,
+        })
+    }
+
+    // The rating of the product follows the moderation, in the same
+    // transaction as the change and its audit entry
+    pub async fn set_status(&self, id: i32, status: ReviewStatus) -> Result<(), anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let row = sqlx::query(r#"
+            SELECT product_id, status FROM reviews WHERE id = $1 FOR UPDATE;
+        "#)
+            .bind(id)
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        let product_id = row.get::<i32, _>("product_id");
+        let before = row.get::<ReviewStatus, _>("status");
+
+        if before == status {
+            return Ok(());
+        }
+
+        sqlx::query(r#"
+            UPDATE reviews SET status = $1 WHERE id = $2;
+        "#)
+            .bind(&status)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+
+        sync_rating(&mut *transaction, product_id).await?;
+
+        audit::record_with(&mut *transaction, "review.status", "review", id,
+            Some(serde_json::json!({ "status": before.as_str(), "product_id": product_id })),
+            Some(serde_json::json!({ "status": status.as_str(), "product_id": product_id })))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        Reviews {
+            pool,
+        }
+    }
+}
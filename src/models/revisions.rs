@@ -0,0 +1,167 @@
+//
+// Description: Revisions of the products, a snapshot taken at each save
+// Last Modification: 2026-10-18 11:02:13
+//
+
+use crate::models::audit;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use serde_json::Value as JsonValue;
+use sqlx::{
+    postgres::PgRow,
+    Row,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Revision {
+    pub id: i32,
+    pub product_id: i32,
+    pub actor_id: Option<i32>,
+    pub actor: String,
+    pub snapshot: JsonValue,
+    pub date_created: String,
+}
+
+// A field of the product that differs between two revisions
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub field: String,
+    pub before: JsonValue,
+    pub after: JsonValue,
+}
+
+// The revisions to compare, e.g. ?from=12&to=15
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    pub from: Option<i32>,
+    pub to: Option<i32>,
+}
+
+// The fields of the second snapshot that are not the same in the first one,
+// the images are compared as a list so a change of their order is shown
+pub fn diff(before: &JsonValue, after: &JsonValue) -> Vec<Change> {
+    let mut changes = vec![];
+
+    if let (JsonValue::Object(before), JsonValue::Object(after)) = (before, after) {
+        for (field, value) in after {
+            if before.get(field) != Some(value) {
+                changes.push(Change {
+                    field: field.clone(),
+                    before: before.get(field).cloned().unwrap_or(JsonValue::Null),
+                    after: value.clone(),
+                });
+            }
+        }
+        for (field, value) in before {
+            if !after.contains_key(field) {
+                changes.push(Change {
+                    field: field.clone(),
+                    before: value.clone(),
+                    after: JsonValue::Null,
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+fn from_row(row: PgRow) -> Revision {
+    Revision {
+        id: row.get::<i32, _>("id"),
+        product_id: row.get::<i32, _>("product_id"),
+        actor_id: row.get::<Option<i32>, _>("actor_id"),
+        actor: row.get::<String, _>("actor"),
+        snapshot: row.get::<JsonValue, _>("snapshot"),
+        date_created: row.get::<NaiveDateTime, _>("date_created").format("%Y/%m/%d %H:%M:%S").to_string(),
+    }
+}
+
+// Writes the revision with the given executor, a save that changed nothing
+// since the last revision, e.g. a form sent as it was, is not recorded
+pub async fn record_with<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    product_id: i32,
+    snapshot: &JsonValue) -> Result<(), anyhow::Error> {
+
+    let actor_id = audit::ACTOR.try_with(|actor| actor.user_id).ok();
+
+    sqlx::query(r#"
+        INSERT INTO product_revisions (product_id, actor_id, actor, snapshot)
+        SELECT $1, $2, COALESCE((SELECT username FROM users WHERE id = $2), 'system'), $3
+        WHERE $3 IS DISTINCT FROM (
+            SELECT snapshot FROM product_revisions WHERE product_id = $1 ORDER BY id DESC LIMIT 1
+        );
+    "#)
+        .bind(product_id)
+        .bind(actor_id)
+        .bind(snapshot)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+// A product saved for the first time since the revisions are kept gets the
+// state it had before that save as its first revision, made by the system
+pub async fn record_original_with<'c, E: sqlx::PgExecutor<'c>>(executor: E,
+    product_id: i32,
+    snapshot: &JsonValue) -> Result<(), anyhow::Error> {
+
+    sqlx::query(r#"
+        INSERT INTO product_revisions (product_id, snapshot)
+        SELECT $1, $2
+        WHERE NOT EXISTS (SELECT 1 FROM product_revisions WHERE product_id = $1);
+    "#)
+        .bind(product_id)
+        .bind(snapshot)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub struct Revisions {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl Revisions {
+
+    // The latest first
+    pub async fn get_all(&self, product_id: i32) -> Result<Vec<Revision>, anyhow::Error> {
+        let revisions = sqlx::query(r#"
+            SELECT id, product_id, actor_id, actor, snapshot, date_created
+            FROM product_revisions WHERE product_id = $1
+            ORDER BY id DESC;
+        "#)
+            .bind(product_id)
+            .map(from_row)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(revisions)
+    }
+
+    pub async fn get(&self, product_id: i32, revision_id: i32) -> Result<Revision, anyhow::Error> {
+        sqlx::query(r#"
+            SELECT id, product_id, actor_id, actor, snapshot, date_created
+            FROM product_revisions WHERE product_id = $1 AND id = $2;
+        "#)
+            .bind(product_id)
+            .bind(revision_id)
+            .map(from_row)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("The revision {} of the product {} does not exist", revision_id, product_id))
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        Revisions {
+            pool,
+        }
+    }
+}
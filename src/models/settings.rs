@@ -0,0 +1,101 @@
+//
+// Last Modification: 2026-10-18 11:40:43
+//
+
+use crate::models::audit;
+
+use anyhow::Result;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use sqlx::Row;
+
+// Store wide options kept in the single row of the settings table
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreSettings {
+    pub require_admin_2fa: bool,
+    pub site_url: String,
+    pub trash_days: i32, // the products in the trash longer are deleted, 0 keeps them
+}
+
+impl StoreSettings {
+
+    // Emailed links are built from the configured address, never from the
+    // Host header of the request
+    pub fn link(&self, path: &str) -> Result<String, anyhow::Error> {
+        let site_url = self.site_url.trim().trim_end_matches('/');
+        if site_url.is_empty() {
+            return Err(anyhow::anyhow!("the site url is not set in the settings"));
+        }
+        Ok(format!("{}{}", site_url, path))
+    }
+}
+
+pub struct Settings {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl Settings {
+
+    pub async fn get(&self) -> Result<StoreSettings, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT require_admin_2fa, site_url, trash_days FROM settings ORDER BY id LIMIT 1;
+        "#)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(match row {
+            Some(row) => StoreSettings {
+                require_admin_2fa: row.get::<bool, _>("require_admin_2fa"),
+                site_url: row.get::<String, _>("site_url"),
+                trash_days: row.get::<i32, _>("trash_days"),
+            },
+            None => StoreSettings {
+                require_admin_2fa: false,
+                site_url: String::new(),
+                trash_days: 30,
+            },
+        })
+    }
+
+    pub async fn update(&self, settings: &StoreSettings) -> Result<(), anyhow::Error> {
+        let before = serde_json::to_value(self.get().await?)?;
+
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(r#"
+            UPDATE settings SET require_admin_2fa = $1, site_url = $2, trash_days = $3
+            WHERE id = (SELECT id FROM settings ORDER BY id LIMIT 1);
+        "#)
+            .bind(settings.require_admin_2fa)
+            .bind(&settings.site_url)
+            .bind(settings.trash_days)
+            .execute(&mut *transaction)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            sqlx::query(r#"
+                INSERT INTO settings (require_admin_2fa, site_url, trash_days) VALUES ($1, $2, $3);
+            "#)
+                .bind(settings.require_admin_2fa)
+                .bind(&settings.site_url)
+                .bind(settings.trash_days)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        audit::record_with(&mut *transaction, "settings.update", "settings", 1, Some(before), Some(serde_json::to_value(settings)?))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        Settings {
+            pool,
+        }
+    }
+}
\ No newline at end of file
@@ -1,9 +1,113 @@
 //
-// Last Modification: 2024-07-24 19:15:21
+// Description: Product tags
+// Last Modification: 2026-10-18 11:40:43
 //
 
+use crate::models::audit;
+
+use anyhow::Result;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use slug::slugify;
+use sqlx::{
+    postgres::PgRow,
+    PgConnection,
+    Row,
+};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tag {
-    id: i32,
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    pub description: String,
+    pub count: i32, // number of products
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagPage {
+    pub tags: Vec<Tag>,
+    pub total_count: i32,
+    pub current_page: i32,
+    pub per_page: i32,
+    pub total_pages: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+// "Red, summer ,, red" -> ["Red", "summer"], names are compared by their slug
+pub fn parse_names(list: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for name in list.split(',').map(|name| name.trim()) {
+        if name.is_empty() || slugify(name).is_empty() {
+            continue;
+        }
+        if names.iter().any(|other| slugify(other) == slugify(name)) {
+            continue;
+        }
+        names.push(name.to_string());
+    }
+    names
+}
+
+fn tag(row: &PgRow) -> Tag {
+    Tag {
+        id: row.get::<i32, _>("id"),
+        name: row.get::<String, _>("name"),
+        slug: row.get::<String, _>("slug"),
+        description: row.get::<String, _>("description"),
+        count: row.get::<i64, _>("count") as i32,
+    }
+}
+
+// Replaces the tags of a product, the unknown names are added as new tags.
+// Takes the connection of the transaction that saves the product.
+pub async fn set_product_tags(connection: &mut PgConnection, product_id: i32, names: &[String]) -> Result<(), anyhow::Error> {
+    let slugs: Vec<String> = names.iter().map(slugify).collect();
+
+    let created = sqlx::query(r#"
+        INSERT INTO tags (name, slug)
+        SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[])
+        ON CONFLICT (slug) DO NOTHING
+        RETURNING id, name, slug;
+    "#)
+        .bind(names)
+        .bind(&slugs)
+        .fetch_all(&mut *connection)
+        .await?;
+
+    for row in created {
+        audit::record_with(&mut *connection, "tag.create", "tag", row.get::<i32, _>("id"), None,
+            Some(serde_json::json!({ "name": row.get::<String, _>("name"), "slug": row.get::<String, _>("slug") })))
+            .await?;
+    }
+
+    sqlx::query(r#"
+        DELETE FROM product_tags WHERE product_id = $1;
+    "#)
+        .bind(product_id)
+        .execute(&mut *connection)
+        .await?;
+
+    sqlx::query(r#"
+        INSERT INTO product_tags (product_id, tag_id)
+        SELECT $1, tags.id FROM tags WHERE tags.slug = ANY($2)
+        ON CONFLICT DO NOTHING;
+    "#)
+        .bind(product_id)
+        .bind(&slugs)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(())
 }
 
 pub struct Tags {
@@ -12,11 +116,163 @@ pub struct Tags {
 
 impl Tags {
 
-    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
-        // Implementation to create a new instance of Tags
+    pub async fn get_page(&self, parameters: &Parameters) -> Result<TagPage, anyhow::Error> {
+        let per_page = parameters.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, 100) as i32;
 
+        let total: i64 = sqlx::query(r#"
+            SELECT COUNT(*) FROM tags;
+        "#)
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        let total_pages = ((total as f32 / per_page as f32).ceil() as i32).max(1);
+        let page = (parameters.page.unwrap_or(1) as i32).clamp(1, total_pages);
+
+        let tags = sqlx::query(r#"
+            SELECT tags.id, tags.name, tags.slug, tags.description,
+                (SELECT COUNT(*) FROM product_tags WHERE product_tags.tag_id = tags.id) AS count
+            FROM tags
+            ORDER BY tags.name
+            LIMIT $1 OFFSET $2;
+        "#)
+            .bind(per_page)
+            .bind((page - 1) * per_page)
+            .map(|row: PgRow| tag(&row))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(TagPage {
+            tags,
+            total_count: total as i32,
+            current_page: page,
+            per_page,
+            total_pages,
+        })
+    }
+
+    pub async fn get(&self, id: i32) -> Result<Tag, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT tags.id, tags.name, tags.slug, tags.description,
+                (SELECT COUNT(*) FROM product_tags WHERE product_tags.tag_id = tags.id) AS count
+            FROM tags WHERE tags.id = $1;
+        "#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(tag(&row))
+    }
+
+    // The count of a storefront tag only includes the published products
+    pub async fn get_by_slug(&self, slug: &str) -> Result<Option<Tag>, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT tags.id, tags.name, tags.slug, tags.description,
+                (SELECT COUNT(*) FROM product_tags, products
+                WHERE product_tags.tag_id = tags.id AND products.id = product_tags.product_id
+                    AND products.status = 'publish') AS count
+            FROM tags WHERE tags.slug = $1;
+        "#)
+            .bind(slug)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| tag(&row)))
+    }
+
+    pub async fn product_tags(&self, product_id: i32) -> Result<Vec<Tag>, anyhow::Error> {
+        let tags = sqlx::query(r#"
+            SELECT tags.id, tags.name, tags.slug, tags.description,
+                (SELECT COUNT(*) FROM product_tags pt WHERE pt.tag_id = tags.id) AS count
+            FROM tags, product_tags
+            WHERE product_tags.tag_id = tags.id AND product_tags.product_id = $1
+            ORDER BY tags.name;
+        "#)
+            .bind(product_id)
+            .map(|row: PgRow| tag(&row))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn add(&self, name: &str, slug: &str, description: &str) -> Result<i32, anyhow::Error> {
+        let slug = if slug.is_empty() { slugify(name) } else { slugify(slug) };
+
+        let mut transaction = self.pool.begin().await?;
+
+        let tag_id: i32 = sqlx::query(r#"
+            INSERT INTO tags (name, slug, description)
+            VALUES ($1, $2, $3) RETURNING id;
+        "#)
+            .bind(name)
+            .bind(&slug)
+            .bind(description)
+            .fetch_one(&mut *transaction)
+            .await?
+            .get(0);
+
+        audit::record_with(&mut *transaction, "tag.create", "tag", tag_id, None,
+            Some(serde_json::json!({ "name": name, "slug": slug, "description": description })))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(tag_id)
+    }
+
+    pub async fn update(&self, tag: &Tag) -> Result<(), anyhow::Error> {
+        let before = self.get(tag.id).await?;
+        let slug = if tag.slug.is_empty() { slugify(&tag.name) } else { slugify(&tag.slug) };
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            UPDATE tags SET name = $1, slug = $2, description = $3 WHERE id = $4;
+        "#)
+            .bind(&tag.name)
+            .bind(&slug)
+            .bind(&tag.description)
+            .bind(tag.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        audit::record_with(&mut *transaction, "tag.update", "tag", tag.id,
+            Some(serde_json::json!({ "name": before.name, "slug": before.slug, "description": before.description })),
+            Some(serde_json::json!({ "name": tag.name, "slug": slug, "description": tag.description })))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    // The products keep their other tags
+    pub async fn delete(&self, id: i32) -> Result<(), anyhow::Error> {
+        let before = self.get(id).await?;
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            DELETE FROM tags WHERE id = $1;
+        "#)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+
+        audit::record_with(&mut *transaction, "tag.delete", "tag", id,
+            Some(serde_json::json!({ "name": before.name, "slug": before.slug, "description": before.description })),
+            None)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
         Tags {
             pool,
         }
     }
-}
\ No newline at end of file
+}
@@ -1,13 +1,39 @@
 //
-// Last Modification: 2024-08-09 22:39:40
+// Last Modification: 2026-10-18 11:32:30
 //
 
 use crate::models::users;
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc, Duration};
 use uuid::Uuid;
+use serde::Serialize;
 use sqlx::Row;
+use sha2::{Digest, Sha256};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 
+// Reset links are short-lived, a new request replaces the previous one
+const RESET_TOKEN_HOURS: i64 = 1;
+const EMAIL_TOKEN_HOURS: i64 = 48;
+const LAST_SEEN_MINUTES: i64 = 5;
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// A logged in device
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: String,
+    pub ip_address: String,
+    pub last_seen: String,
+    pub date_created: String,
+    pub expires: String,
+}
+
+// The expiry columns hold UTC times without a zone, they are always compared
+// with the UTC time of the application and never with the NOW() of the
+// database session, which depends on its time zone
 pub struct Tokens {
     pool: sqlx::Pool<sqlx::Postgres>,
 }
@@ -17,33 +43,34 @@ impl Tokens {
     pub async fn delete(&self, token: &str) -> Result<(), anyhow::Error> {
         // Implementation to delete a token
         sqlx::query(r#"
-            DELETE FROM tokens WHERE token = $1;
+            DELETE FROM tokens WHERE token_hash = $1;
         "#)
-            .bind(token)
+            .bind(hash_token(token))
             .execute(&self.pool)
             .await?;
 
         Ok(())
     }
 
-    pub async fn is_valid(&self, token: &str) -> Result<users::UserRoles, anyhow::Error> {
+    pub async fn is_valid(&self, token: &str) -> Result<(i32, i32, users::UserRoles), anyhow::Error> {
         let row = sqlx::query(r#"
-            SELECT tokens.user_id, tokens.expires, users.role FROM tokens, users WHERE token = $1;
+            SELECT tokens.id, tokens.user_id, tokens.expires, tokens.last_seen, users.role
+            FROM tokens
+            JOIN users ON users.id = tokens.user_id
+            WHERE token_hash = $1;
         "#)
-            .bind(token)
+            .bind(hash_token(token))
             .fetch_one(&self.pool)
             .await?;
 
-        println!("TOKEN IS VALID: {}", token);
-
+        let session_id = row.get::<i32, _>("id");
+        let user_id = row.get::<i32, _>("user_id");
         let role = row.get::<users::UserRoles, _>("role");
         let expires = row.get::<NaiveDateTime, _>("expires");
         
         // let expires_utc: DateTime<Utc> = DateTime::from_utc(expires, Utc);
         let expires_utc: DateTime<Utc> = Utc.from_utc_datetime(&expires);
 
-        println!(">>> Expires: {}", expires);
-
         let now: DateTime<Utc> = Utc::now();
         if now > expires_utc {
             // Remove the expired token
@@ -55,33 +82,247 @@ impl Tokens {
             return Err(anyhow!(error));
         }
 
-        // if expired remove token
+        // Not written on every request, the session list only needs an approximation
+        let last_seen = row.get::<NaiveDateTime, _>("last_seen");
+        if now.naive_utc() - last_seen > Duration::minutes(LAST_SEEN_MINUTES) {
+            sqlx::query(r#"
+                UPDATE tokens SET last_seen = $1 WHERE id = $2;
+            "#)
+                .bind(now.naive_utc())
+                .bind(session_id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok((session_id, user_id, role))
+    }
 
-        println!("Role {:?}: {:?}", role, expires);
+    // Every login gets its own token, so each device keeps its session.
+    // Like the emailed tokens only its sha256 is stored, the cookie holds the token
+    pub async fn add(&self, user_id: &i32, user_agent: &str, ip_address: &str) -> Result<String, anyhow::Error> {
+        let token = Uuid::new_v4().to_string();
+        let now = Utc::now().naive_utc();
+        let expires_time = now + Duration::hours(24);
+
+        let mut transaction = self.pool.begin().await?;
 
-        Ok(role)
+        sqlx::query(r#"
+            DELETE FROM tokens WHERE user_id = $1 AND expires < $2;
+        "#)
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(r#"
+            INSERT INTO tokens (token_hash, user_id, user_agent, ip_address, last_seen, date_created, expires)
+            VALUES ($1, $2, $3, $4, $5, $5, $6);
+        "#)
+            .bind(hash_token(&token))
+            .bind(user_id)
+            .bind(user_agent)
+            .bind(ip_address)
+            .bind(now)
+            .bind(expires_time)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(token)
     }
 
-    pub async fn add(&self, user_id: &i32) -> Result<String, anyhow::Error> {
-        // Implementation to add a new token
+    pub async fn sessions(&self, user_id: &i32) -> Result<Vec<Session>, anyhow::Error> {
+        let rows = sqlx::query(r#"
+            SELECT id, user_agent, ip_address, last_seen, date_created, expires
+            FROM tokens
+            WHERE user_id = $1 AND expires > $2
+            ORDER BY last_seen DESC;
+        "#)
+            .bind(user_id)
+            .bind(Utc::now().naive_utc())
+            .fetch_all(&self.pool)
+            .await?;
 
-        let token = Uuid::new_v4().to_string();
-        let expires_time = Utc::now().naive_utc() + Duration::hours(24);
+        let sessions = rows
+            .iter()
+            .map(|row| Session {
+                id: row.get::<i32, _>("id"),
+                user_agent: row.get::<String, _>("user_agent"),
+                ip_address: row.get::<String, _>("ip_address"),
+                last_seen: row.get::<NaiveDateTime, _>("last_seen").format("%Y/%m/%d %H:%M").to_string(),
+                date_created: row.get::<NaiveDateTime, _>("date_created").format("%Y/%m/%d %H:%M").to_string(),
+                expires: row.get::<NaiveDateTime, _>("expires").format("%Y/%m/%d %H:%M").to_string(),
+            })
+            .collect();
 
+        Ok(sessions)
+    }
+
+    // Scoped to the user, a session id of someone else is simply not found
+    pub async fn revoke(&self, user_id: &i32, session_id: &i32) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query(r#"
+            DELETE FROM tokens WHERE id = $1 AND user_id = $2;
+        "#)
+            .bind(session_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Sign out everywhere except the current device
+    pub async fn revoke_others(&self, user_id: &i32, session_id: &i32) -> Result<u64, anyhow::Error> {
+        let result = sqlx::query(r#"
+            DELETE FROM tokens WHERE user_id = $1 AND id <> $2;
+        "#)
+            .bind(user_id)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Remove every login token of the user, e.g. after a password change
+    pub async fn delete_all(&self, user_id: &i32) -> Result<(), anyhow::Error> {
         sqlx::query(r#"
-            INSERT INTO tokens (token, user_id, expires) VALUES ($1, $2, $3)
-            ON CONFLICT (user_id)
-            DO UPDATE SET token = EXCLUDED.token, expires = EXCLUDED.expires;
+            DELETE FROM tokens WHERE user_id = $1;
         "#)
-            .bind(&token)
-            .bind(&user_id)
-            .bind(&expires_time)
+            .bind(user_id)
             .execute(&self.pool)
             .await?;
 
+        Ok(())
+    }
+
+    pub async fn add_reset(&self, user_id: &i32) -> Result<String, anyhow::Error> {
+
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let expires_time = Utc::now().naive_utc() + Duration::hours(RESET_TOKEN_HOURS);
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            DELETE FROM password_resets WHERE user_id = $1 OR expires < $2;
+        "#)
+            .bind(user_id)
+            .bind(Utc::now().naive_utc())
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(r#"
+            INSERT INTO password_resets (token_hash, user_id, expires) VALUES ($1, $2, $3);
+        "#)
+            .bind(hash_token(&token))
+            .bind(user_id)
+            .bind(expires_time)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
         Ok(token)
     }
 
+    // Only checks the reset token, used before showing the new password form
+    pub async fn is_valid_reset(&self, token: &str) -> Result<i32, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT user_id FROM password_resets WHERE token_hash = $1 AND expires > $2;
+        "#)
+            .bind(hash_token(token))
+            .bind(Utc::now().naive_utc())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(row.get::<i32, _>("user_id")),
+            None => Err(anyhow!("Invalid or expired reset token")),
+        }
+    }
+
+    // The row is deleted when read so the same link can not be used twice
+    pub async fn use_reset(&self, token: &str) -> Result<i32, anyhow::Error> {
+        let row = sqlx::query(r#"
+            DELETE FROM password_resets WHERE token_hash = $1 RETURNING user_id, expires;
+        "#)
+            .bind(hash_token(token))
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Err(anyhow!("Invalid or expired reset token")),
+        };
+
+        let expires = row.get::<NaiveDateTime, _>("expires");
+        if Utc::now() > Utc.from_utc_datetime(&expires) {
+            return Err(anyhow!("Invalid or expired reset token"));
+        }
+
+        Ok(row.get::<i32, _>("user_id"))
+    }
+
+    // The token is bound to the address it was sent to
+    pub async fn add_email_verification(&self, user_id: &i32, email: &str) -> Result<String, anyhow::Error> {
+
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let expires_time = Utc::now().naive_utc() + Duration::hours(EMAIL_TOKEN_HOURS);
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            DELETE FROM email_verifications WHERE user_id = $1 OR expires < $2;
+        "#)
+            .bind(user_id)
+            .bind(Utc::now().naive_utc())
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(r#"
+            INSERT INTO email_verifications (token_hash, user_id, email, expires) VALUES ($1, $2, $3, $4);
+        "#)
+            .bind(hash_token(&token))
+            .bind(user_id)
+            .bind(email)
+            .bind(expires_time)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(token)
+    }
+
+    // Single use, returns the user id and the verified email
+    pub async fn use_email_verification(&self, token: &str) -> Result<(i32, String), anyhow::Error> {
+        let row = sqlx::query(r#"
+            DELETE FROM email_verifications WHERE token_hash = $1 RETURNING user_id, email, expires;
+        "#)
+            .bind(hash_token(token))
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Err(anyhow!("Invalid or expired confirmation link")),
+        };
+
+        let expires = row.get::<NaiveDateTime, _>("expires");
+        if Utc::now() > Utc.from_utc_datetime(&expires) {
+            return Err(anyhow!("Invalid or expired confirmation link"));
+        }
+
+        Ok((row.get::<i32, _>("user_id"), row.get::<String, _>("email")))
+    }
+
     pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
         Tokens {
             pool,
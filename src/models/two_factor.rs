@@ -0,0 +1,369 @@
+//
+// Last Modification: 2026-10-18 11:31:47
+//
+
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+// RFC 6238 defaults, the ones every authenticator app understands
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+// Accept the previous and next step to tolerate clock drift
+const WINDOW: i64 = 1;
+const SECRET_BYTES: usize = 20;
+const RECOVERY_CODES: usize = 10;
+const ISSUER: &str = "Store";
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    output
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in data.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET.iter().position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+fn hash_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+// Returns the time step the code belongs to, if any
+fn matching_step(secret: &str, code: &str) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    matching_step_at(secret, code, Utc::now().timestamp())
+}
+
+fn matching_step_at(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let key = base32_decode(secret)?;
+    let current = timestamp / STEP_SECONDS;
+
+    (current - WINDOW..=current + WINDOW).find(|step| {
+        let expected = format!("{:0width$}", hotp(&key, *step as u64), width = DIGITS as usize);
+        crate::utils::constant_time_eq(expected.as_bytes(), code.as_bytes())
+    })
+}
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    base32_encode(&bytes)
+}
+
+// Checks a code against a secret that is not stored yet (enrolment),
+// the returned time step is passed to enable so the code can not be reused
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    matching_step(secret, code)
+}
+
+pub fn otpauth_uri(account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = ISSUER,
+        account = url::form_urlencoded::byte_serialize(account.as_bytes()).collect::<String>(),
+        secret = secret,
+    )
+}
+
+pub fn qr_svg(uri: &str) -> Result<String, anyhow::Error> {
+    let code = qrcode::QrCode::new(uri.as_bytes())?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+fn generate_recovery_code() -> String {
+    format!("{:05}-{:05}", OsRng.next_u32() % 100_000, OsRng.next_u32() % 100_000)
+}
+
+pub struct TwoFactor {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl TwoFactor {
+
+    pub async fn is_enabled(&self, user_id: &i32) -> Result<bool, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT totp_secret IS NOT NULL AS enabled FROM users WHERE id = $1;
+        "#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<bool, _>("enabled"))
+    }
+
+    // Stores the confirmed secret and returns a fresh set of recovery codes,
+    // only their hashes are kept so they can be shown once
+    pub async fn enable(&self, user_id: &i32, secret: &str, step: i64) -> Result<Vec<String>, anyhow::Error> {
+        let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| generate_recovery_code()).collect();
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            UPDATE users SET totp_secret = $1, totp_last_step = $2 WHERE id = $3;
+        "#)
+            .bind(secret)
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(r#"
+            DELETE FROM recovery_codes WHERE user_id = $1;
+        "#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        for code in &codes {
+            sqlx::query(r#"
+                INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING;
+            "#)
+                .bind(user_id)
+                .bind(hash_code(code))
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(codes)
+    }
+
+    pub async fn disable(&self, user_id: &i32) -> Result<(), anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            UPDATE users SET totp_secret = NULL, totp_last_step = 0 WHERE id = $1;
+        "#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(r#"
+            DELETE FROM recovery_codes WHERE user_id = $1;
+        "#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn recovery_codes_left(&self, user_id: &i32) -> Result<i64, anyhow::Error> {
+        let row = sqlx::query(r#"
+            SELECT COUNT(*) AS count FROM recovery_codes WHERE user_id = $1;
+        "#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
+    // Accepts either a current TOTP code or an unused recovery code
+    pub async fn verify(&self, user_id: &i32, code: &str) -> Result<bool, anyhow::Error> {
+        let code = code.trim();
+
+        if code.contains('-') {
+            let row = sqlx::query(r#"
+                DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2
+                RETURNING user_id;
+            "#)
+                .bind(user_id)
+                .bind(hash_code(code))
+                .fetch_optional(&self.pool)
+                .await?;
+
+            return Ok(row.is_some());
+        }
+
+        let row = sqlx::query(r#"
+            SELECT totp_secret FROM users WHERE id = $1;
+        "#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let secret = row
+            .get::<Option<String>, _>("totp_secret")
+            .ok_or_else(|| anyhow!("Two-factor authentication is not enabled"))?;
+
+        let step = match matching_step(&secret, code) {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        // A code can only be used once, later steps only move forward
+        let result = sqlx::query(r#"
+            UPDATE users SET totp_last_step = $1
+            WHERE id = $2 AND totp_last_step < $1;
+        "#)
+            .bind(step)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        TwoFactor {
+            pool,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The key of the RFC 4226 and RFC 6238 test vectors
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    fn code_at(key: &[u8], timestamp: i64) -> String {
+        format!("{:06}", hotp(key, (timestamp / STEP_SECONDS) as u64))
+    }
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_KEY, counter as u64), *code, "counter {}", counter);
+        }
+    }
+
+    // The RFC 6238 codes have 8 digits, the last 6 are the ones of a 6 digit code
+    #[test]
+    fn totp_matches_rfc_6238_sha1() {
+        for (timestamp, code) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(code_at(RFC_KEY, timestamp), code[2..], "time {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), data.as_bytes());
+        }
+
+        // Padding, spaces and lower case are accepted, other characters are not
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for length in 0..data.len() {
+            assert_eq!(base32_decode(&base32_encode(&data[..length])).unwrap(), &data[..length]);
+        }
+
+        let secret = generate_secret();
+        assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_BYTES);
+    }
+
+    #[test]
+    fn codes_of_the_next_and_previous_steps_are_accepted() {
+        let secret = base32_encode(RFC_KEY);
+        let timestamp = 1234567890;
+        let step = timestamp / STEP_SECONDS;
+
+        for offset in -WINDOW..=WINDOW {
+            let code = code_at(RFC_KEY, timestamp + offset * STEP_SECONDS);
+            assert_eq!(matching_step_at(&secret, &code, timestamp), Some(step + offset));
+        }
+
+        for offset in [-2, 2] {
+            let code = code_at(RFC_KEY, timestamp + offset * STEP_SECONDS);
+            assert_eq!(matching_step_at(&secret, &code, timestamp), None);
+        }
+    }
+
+    #[test]
+    fn malformed_codes_are_refused() {
+        let secret = base32_encode(RFC_KEY);
+        let code = code_at(RFC_KEY, Utc::now().timestamp());
+
+        assert!(matching_step(&secret, &code).is_some());
+        assert!(matching_step(&secret, &format!(" {} ", code)).is_some());
+        assert_eq!(matching_step(&secret, &code[..5]), None);
+        assert_eq!(matching_step(&secret, "12345a"), None);
+        assert_eq!(matching_step(&secret, ""), None);
+    }
+}
@@ -1,14 +1,29 @@
 //
-// Last Modification: 2024-08-09 19:01:11
+// Last Modification: 2026-10-18 11:40:43
 //
 
 use crate::types;
+use crate::utils;
+use crate::models::audit;
+use crate::models::orders;
 
 use chrono::NaiveDateTime;
 use anyhow;
 
+use argon2::{
+    password_hash::{
+        rand_core::OsRng,
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString,
+    },
+    Argon2,
+};
+
 use sqlx::{
     postgres::PgRow,
+    types::Json,
     Row,
 };
 
@@ -16,27 +31,63 @@ use serde::{
     Deserialize,
     Serialize,
 };
+use strum::EnumString;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq)]
-#[sqlx(type_name = "user_roles", rename_all = "lowercase")]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type, PartialEq, EnumString)]
+#[sqlx(type_name = "user_roles", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 pub enum UserRoles {
     Admin,
+    ShopManager,
+    Editor,
+    Support,
     Customer,
     Guest,
 }
 
+// What a role is allowed to do in the backend
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Capability {
+    AccessBackend,
+    ManageProducts, // products, categories and media
+    ManageOrders, // orders and the customer details in them
+    ManageUsers, // accounts and roles
+    ManageSettings, // store wide options
+    ViewAuditLog, // who changed what in the backend
+    ModerateReviews, // customer reviews of the products
+}
+
 impl UserRoles {
     fn as_str(&self) -> &str {
         match self {
             UserRoles::Admin => "admin",
+            UserRoles::ShopManager => "shop_manager",
+            UserRoles::Editor => "editor",
+            UserRoles::Support => "support",
             UserRoles::Customer => "customer",
             UserRoles::Guest => "guest",
         }
     }
 
-    pub fn is_admin(&self) -> bool {
-        self == &UserRoles::Admin
+    pub fn can(&self, capability: Capability) -> bool {
+        match self {
+            UserRoles::Admin => true,
+            UserRoles::ShopManager => matches!(capability,
+                Capability::AccessBackend | Capability::ManageProducts | Capability::ManageOrders |
+                Capability::ModerateReviews),
+            UserRoles::Editor => matches!(capability,
+                Capability::AccessBackend | Capability::ManageProducts | Capability::ModerateReviews),
+            UserRoles::Support => matches!(capability,
+                Capability::AccessBackend | Capability::ManageOrders | Capability::ModerateReviews),
+            UserRoles::Customer | UserRoles::Guest => false,
+        }
+    }
+
+    // Staff members are sent to the backend after login
+    pub fn is_staff(&self) -> bool {
+        self.can(Capability::AccessBackend)
     }
 }
 
@@ -54,6 +105,31 @@ pub struct User {
     pub date_modified: NaiveDateTime,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewUser {
+    pub username: String,
+    pub email: String,
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub role: UserRoles,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub phone: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Addresses {
+    pub billing: orders::Billing,
+    pub shipping: orders::Shipping,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserShort {
     pub id: i32,
@@ -105,6 +181,59 @@ pub struct Cardentials {
     pub role: UserRoles,
 }
 
+impl Cardentials {
+
+    // Legacy rows store the password in plain text instead of a PHC hash string
+    pub fn is_legacy(&self) -> bool {
+        PasswordHash::new(&self.password).is_err()
+    }
+
+    // Argon2 takes tens of milliseconds, it runs on the blocking pool
+    // so that a burst of logins does not stall the other requests
+    pub async fn verify(&self, password: &str) -> bool {
+        let stored = self.password.clone();
+        let password = password.to_string();
+
+        tokio::task::spawn_blocking(move || match PasswordHash::new(&stored) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => utils::constant_time_eq(password.as_bytes(), stored.as_bytes()),
+        })
+            .await
+            .unwrap_or(false)
+    }
+}
+
+// The hash of a password no account has, an unknown user is checked against
+// it so that the login takes as long as for a known user
+const UNKNOWN_USER_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$gdF+kbSrpjBd4EbHgD3ZRA$mzy00A4SFvuC1/U9HbfYOsNXhYPTABkfhNY0h18ZMgY";
+
+pub async fn verify_unknown_user(password: &str) {
+    let password = password.to_string();
+
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Ok(hash) = PasswordHash::new(UNKNOWN_USER_HASH) {
+            let _ = Argon2::default().verify_password(password.as_bytes(), &hash);
+        }
+    }).await;
+}
+
+// Runs on the blocking pool, see Cardentials::verify
+pub async fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    let password = password.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow::anyhow!("failed to hash password: {}", e))?;
+
+        Ok(hash.to_string())
+    }).await?
+}
+
 fn users_order_by(parameter: &Option<String>) -> &str {
     match parameter.as_ref() {
         Some(v) => match v.as_str() {
@@ -124,14 +253,153 @@ pub struct Users {
 
 impl Users {
 
-    fn update(&self) {
-        // Implementation to update a user by ID
+    pub async fn update(&self,
+        id: i32,
+        profile: &Profile,
+    ) -> Result<(), anyhow::Error> {
+
+        sqlx::query(r#"
+            UPDATE users
+            SET first_name = $1, last_name = $2, email = $3, phone = $4, date_modified = CURRENT_TIMESTAMP
+            WHERE id = $5;
+        "#)
+            .bind(&profile.first_name)
+            .bind(&profile.last_name)
+            .bind(&profile.email)
+            .bind(&profile.phone)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_addresses(&self, id: i32) -> Result<Addresses, anyhow::Error> {
+
+        let row = sqlx::query(r#"
+            SELECT billing, shipping FROM users WHERE id = $1;
+        "#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Addresses {
+            billing: match row.get::<Option<Json<orders::Billing>>, _>("billing") {
+                Some(billing) => billing.0,
+                None => orders::Billing::new(),
+            },
+            shipping: match row.get::<Option<Json<orders::Shipping>>, _>("shipping") {
+                Some(shipping) => shipping.0,
+                None => orders::Shipping::new(),
+            },
+        })
+    }
+
+    pub async fn update_addresses(&self,
+        id: i32,
+        addresses: &Addresses,
+    ) -> Result<(), anyhow::Error> {
+
+        sqlx::query(r#"
+            UPDATE users
+            SET billing = $1, shipping = $2, date_modified = CURRENT_TIMESTAMP
+            WHERE id = $3;
+        "#)
+            .bind(Json(&addresses.billing))
+            .bind(Json(&addresses.shipping))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
     }
 
     fn delete(&self) {
         // Implementation to delete a user by ID
     }
 
+    // Only once the new address is confirmed, see Tokens::add_email_verification
+    pub async fn update_email(&self,
+        user_id: i32,
+        email: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        sqlx::query(r#"
+            UPDATE users SET email = $1, date_modified = CURRENT_TIMESTAMP WHERE id = $2;
+        "#)
+            .bind(email)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_password(&self,
+        user_id: &i32,
+        password: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        let hash = hash_password(password).await?;
+
+        sqlx::query(r#"
+            UPDATE users SET password = $1, date_modified = CURRENT_TIMESTAMP WHERE id = $2;
+        "#)
+            .bind(&hash)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn reset_password(&self,
+        email: &str,
+        password: &str,
+    ) -> Result<i32, anyhow::Error> {
+
+        let cardentials = self.cardentials(email).await?;
+        self.update_password(&cardentials.user_id, password).await?;
+
+        Ok(cardentials.user_id)
+    }
+
+    pub async fn set_role(&self,
+        email: &str,
+        role: &UserRoles,
+    ) -> Result<(), anyhow::Error> {
+
+        let mut transaction = self.pool.begin().await?;
+
+        let row = sqlx::query(r#"
+            SELECT id, role FROM users WHERE email = $1 FOR UPDATE;
+        "#)
+            .bind(email)
+            .fetch_optional(&mut *transaction)
+            .await?
+            .ok_or_else(|| anyhow::Error::msg("User not found"))?;
+
+        let user_id = row.get::<i32, _>("id");
+        let old_role = row.get::<UserRoles, _>("role");
+
+        sqlx::query(r#"
+            UPDATE users SET role = $1, date_modified = CURRENT_TIMESTAMP WHERE id = $2;
+        "#)
+            .bind(role)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        audit::record_with(&mut *transaction, "user.role", "user", user_id,
+            Some(serde_json::json!({ "role": old_role })),
+            Some(serde_json::json!({ "role": role })))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn cardentials(&self, user: &str) -> Result<Cardentials, anyhow::Error> {
         // Implementation to get users by username and password
 
@@ -153,12 +421,67 @@ impl Users {
         }
     }
 
-    fn get(&self) {
-        // Implementation to get a user by ID
+    pub async fn get(&self, id: i32) -> Result<User, anyhow::Error> {
+
+        let user = sqlx::query(r#"
+            SELECT
+                id, username, email, phone, first_name, last_name, role, avatar_url, date_created, date_modified
+            FROM users WHERE id = $1;
+        "#)
+            .bind(id)
+            .map(|row: PgRow| User {
+                id: row.get::<i32, _>("id"),
+                username: row.get::<String, _>("username"),
+                email: row.get::<String, _>("email"),
+                phone: row.get::<String, _>("phone"),
+                first_name: row.get::<String, _>("first_name"),
+                last_name: row.get::<String, _>("last_name"),
+                role: row.get::<UserRoles, _>("role"),
+                avatar_url: row.get::<String, _>("avatar_url"),
+                date_created: row.get::<NaiveDateTime, _>("date_created"),
+                date_modified: row.get::<NaiveDateTime, _>("date_modified"),
+            })
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn is_registered(&self,
+        username: &str,
+        email: &str,
+    ) -> Result<bool, anyhow::Error> {
+
+        let row: (bool, ) = sqlx::query_as(r#"
+            SELECT EXISTS(SELECT 1 FROM users WHERE username = $1 OR LOWER(email) = LOWER($2));
+        "#)
+            .bind(username)
+            .bind(email)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.0)
     }
 
-    fn add(&self) {
-        // Implementation to add a new user
+    pub async fn add(&self, user: &NewUser) -> Result<i32, anyhow::Error> {
+
+        let hash = hash_password(&user.password).await?;
+
+        let user_id: i32 = sqlx::query(r#"
+            INSERT INTO users (username, email, password, first_name, last_name, role)
+            VALUES ($1, $2, $3, $4, $5, $6) RETURNING id;
+        "#)
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(&hash)
+            .bind(&user.first_name)
+            .bind(&user.last_name)
+            .bind(&user.role)
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        Ok(user_id)
     }
 
     pub async fn get_page(&self,
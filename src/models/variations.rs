@@ -0,0 +1,452 @@
+//
+// Description: Attributes and variations of variable products
+// Last Modification: 2026-10-18 11:40:43
+//
+
+use crate::models::audit;
+use crate::models::products::StockStatus;
+
+use anyhow;
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+
+use sqlx::{
+    postgres::PgRow,
+    types::{Decimal, Json},
+    Row,
+};
+
+use serde::{
+    Serialize,
+    Deserialize
+};
+use serde_json::{Map, Value as JsonValue};
+
+// Every combination of options becomes a variation, keep the grid editable
+const MAX_VARIATIONS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: String, // e.g. Size
+    pub options: Vec<String>, // e.g. ["S", "M", "L"]
+}
+
+impl Attribute {
+    // "S | M | L" -> ["S", "M", "L"]
+    pub fn parse_options(options: &str) -> Vec<String> {
+        let mut parsed: Vec<String> = vec![];
+        for option in options.split('|').map(|option| option.trim()) {
+            if !option.is_empty() && !parsed.iter().any(|o| o == option) {
+                parsed.push(option.to_string());
+            }
+        }
+        parsed
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variation {
+    pub id: i32, // 0 for a variation not saved yet
+    pub sku: String,
+    pub attributes: BTreeMap<String, String>, // attribute name -> option
+    pub price: f32, // current variation price (read-only)
+    pub regular_price: f32,
+    pub sale_price: f32,
+    pub on_sale: bool, // read-only
+    pub stock_quantity: i32,
+    pub stock_status: StockStatus, // read-only
+    pub weight: u32,
+    pub media_id: i32, // 0 uses the product image
+    pub image_src: String, // read-only
+}
+
+impl Variation {
+    // Same rules as the product form, a sale price only counts below the regular price
+    pub fn set_prices(&mut self, regular_price: f32, sale_price: Option<f32>) {
+        self.regular_price = regular_price;
+        self.price = regular_price;
+        self.sale_price = 0.00;
+        self.on_sale = false;
+
+        if let Some(sale_price) = sale_price {
+            if sale_price >= 0.00 && sale_price < regular_price {
+                self.sale_price = sale_price;
+                self.price = sale_price;
+                self.on_sale = true;
+            }
+        }
+
+        self.stock_status = if self.stock_quantity > 0 {
+            StockStatus::InStock
+        } else {
+            StockStatus::OutOfStock
+        };
+    }
+
+    pub fn new() -> Self {
+        Variation {
+            id: 0,
+            sku: "".to_string(),
+            attributes: BTreeMap::new(),
+            price: 0.00,
+            regular_price: 0.00,
+            sale_price: 0.00,
+            on_sale: false,
+            stock_quantity: 0,
+            stock_status: StockStatus::OutOfStock,
+            weight: 0,
+            media_id: 0,
+            image_src: "".to_string(),
+        }
+    }
+}
+
+fn decimal(row: &PgRow, column: &str) -> f32 {
+    row.get::<Decimal, _>(column).to_f32().unwrap_or(0.00)
+}
+
+// All the combinations of the attribute options, in the attributes order
+fn combinations(attributes: &[Attribute]) -> Vec<BTreeMap<String, String>> {
+    attributes.iter().fold(vec![BTreeMap::new()], |combinations, attribute| {
+        combinations.iter()
+            .flat_map(|combination| attribute.options.iter().map(move |option| {
+                let mut combination = combination.clone();
+                combination.insert(attribute.name.clone(), option.clone());
+                combination
+            }))
+            .collect()
+    })
+}
+
+fn attributes_snapshot(attributes: &[Attribute]) -> JsonValue {
+    let mut snapshot = Map::new();
+    for attribute in attributes {
+        snapshot.insert(attribute.name.clone(), serde_json::json!(attribute.options));
+    }
+    JsonValue::Object(snapshot)
+}
+
+// Keyed by id so the audit log only keeps the variations that changed
+fn variations_snapshot(variations: &[Variation]) -> JsonValue {
+    let mut snapshot = Map::new();
+    for variation in variations {
+        snapshot.insert(variation.id.to_string(), serde_json::json!({
+            "sku": variation.sku,
+            "attributes": variation.attributes,
+            "regular_price": format!("{:.2}", variation.regular_price),
+            "sale_price": format!("{:.2}", variation.sale_price),
+            "stock_quantity": variation.stock_quantity,
+            "weight": variation.weight,
+            "media_id": variation.media_id,
+        }));
+    }
+    JsonValue::Object(snapshot)
+}
+
+// The variable product takes the price of its cheapest variation and the
+// total stock of its variations, listings and price filters keep working on
+// the products table. The regular price comes from the same variation, so the
+// "was" price is only shown when the cheapest variation is discounted, while
+// on_sale flags a discount on any variation.
+pub async fn sync_product<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<(), anyhow::Error> {
+    sqlx::query(r#"
+        UPDATE products
+        SET price = variations.price, regular_price = variations.regular_price,
+            on_sale = variations.on_sale,
+            sale_price = CASE WHEN variations.price < variations.regular_price THEN variations.price ELSE 0.00 END,
+            stock_quantity = variations.stock_quantity,
+            stock_status = CASE WHEN variations.stock_quantity > 0
+                THEN 'instock'::stock_status ELSE 'outofstock'::stock_status END
+        FROM (
+            SELECT cheapest.price, cheapest.regular_price, totals.on_sale, totals.stock_quantity
+            FROM (
+                SELECT price, regular_price FROM product_variations WHERE product_id = $1
+                ORDER BY price, regular_price, id LIMIT 1
+            ) AS cheapest, (
+                SELECT BOOL_OR(price < regular_price) AS on_sale, SUM(stock_quantity)::INT AS stock_quantity
+                FROM product_variations WHERE product_id = $1
+            ) AS totals
+        ) AS variations
+        WHERE products.id = $1;
+    "#)
+        .bind(product_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+// The variations as the edit form shows them, e.g. before and after a change in a transaction
+async fn fetch_all<'c, E: sqlx::PgExecutor<'c>>(executor: E, product_id: i32) -> Result<Vec<Variation>, anyhow::Error> {
+    let variations = sqlx::query(r#"
+        SELECT
+            product_variations.id, product_variations.sku, product_variations.attributes,
+            product_variations.price, product_variations.regular_price, product_variations.sale_price,
+            product_variations.on_sale, product_variations.stock_quantity, product_variations.stock_status,
+            product_variations.weight, product_variations.media_id, COALESCE(media.src, '') AS image_src
+        FROM product_variations
+        LEFT JOIN media ON media.id = product_variations.media_id
+        WHERE product_variations.product_id = $1
+        ORDER BY product_variations.position, product_variations.id;
+    "#)
+        .bind(product_id)
+        .map(|row: PgRow| Variation {
+            id: row.get::<i32, _>("id"),
+            sku: row.get::<String, _>("sku"),
+            attributes: row.get::<Json<BTreeMap<String, String>>, _>("attributes").0,
+            price: decimal(&row, "price"),
+            regular_price: decimal(&row, "regular_price"),
+            sale_price: decimal(&row, "sale_price"),
+            on_sale: row.get::<bool, _>("on_sale"),
+            stock_quantity: row.get::<i32, _>("stock_quantity"),
+            stock_status: row.get::<StockStatus, _>("stock_status"),
+            weight: row.get::<i32, _>("weight") as u32,
+            media_id: row.get::<i32, _>("media_id"),
+            image_src: row.get::<String, _>("image_src"),
+        })
+        .fetch_all(executor)
+        .await?;
+
+    Ok(variations)
+}
+
+pub struct Variations {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl Variations {
+
+    pub async fn attributes(&self, product_id: i32) -> Result<Vec<Attribute>, anyhow::Error> {
+        let attributes = sqlx::query(r#"
+            SELECT name, options FROM product_attributes
+            WHERE product_id = $1
+            ORDER BY position, id;
+        "#)
+            .bind(product_id)
+            .map(|row: PgRow| Attribute {
+                name: row.get::<String, _>("name"),
+                options: row.get::<Json<Vec<String>>, _>("options").0,
+            })
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(attributes)
+    }
+
+    // Replaces the product attributes, the variations forget the removed ones
+    pub async fn save_attributes(&self,
+        product_id: i32,
+        attributes: &[Attribute]) -> Result<(), anyhow::Error> {
+
+        let before = attributes_snapshot(&self.attributes(product_id).await?);
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(r#"
+            DELETE FROM product_attributes WHERE product_id = $1;
+        "#)
+            .bind(product_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        for (position, attribute) in attributes.iter().enumerate() {
+            sqlx::query(r#"
+                INSERT INTO product_attributes (product_id, name, options, position)
+                VALUES ($1, $2, $3, $4);
+            "#)
+                .bind(product_id)
+                .bind(&attribute.name)
+                .bind(Json(&attribute.options))
+                .bind(position as i32)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        let names: Vec<&str> = attributes.iter().map(|attribute| attribute.name.as_str()).collect();
+        sqlx::query(r#"
+            UPDATE product_variations
+            SET attributes = (
+                SELECT COALESCE(jsonb_object_agg(key, value), '{}')
+                FROM jsonb_each(product_variations.attributes)
+                WHERE key = ANY($2))
+            WHERE product_id = $1;
+        "#)
+            .bind(product_id)
+            .bind(&names)
+            .execute(&mut *transaction)
+            .await?;
+
+        audit::record_with(&mut *transaction, "product.attributes", "product", product_id,
+            Some(before), Some(attributes_snapshot(attributes)))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn get_all(&self, product_id: i32) -> Result<Vec<Variation>, anyhow::Error> {
+        fetch_all(&self.pool, product_id).await
+    }
+
+    // The variation matching the chosen options, all the attributes must be chosen
+    pub async fn find(&self,
+        product_id: i32,
+        attributes: &BTreeMap<String, String>) -> Result<Option<i32>, anyhow::Error> {
+
+        let row = sqlx::query(r#"
+            SELECT id FROM product_variations
+            WHERE product_id = $1 AND attributes = $2
+            ORDER BY position, id LIMIT 1;
+        "#)
+            .bind(product_id)
+            .bind(Json(attributes))
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.get::<i32, _>("id")))
+    }
+
+    // Saves the edited variations and removes the listed ones
+    pub async fn save(&self,
+        product_id: i32,
+        variations: &[Variation],
+        removed: &[i32]) -> Result<(), anyhow::Error> {
+
+        let mut transaction = self.pool.begin().await?;
+
+        let before = variations_snapshot(&fetch_all(&mut *transaction, product_id).await?);
+
+        for (position, variation) in variations.iter().enumerate() {
+            if removed.contains(&variation.id) {
+                continue;
+            }
+
+            if variation.id == 0 {
+                sqlx::query(r#"
+                    INSERT INTO product_variations (
+                        product_id, sku, attributes, price, regular_price, sale_price, on_sale,
+                        stock_quantity, stock_status, weight, media_id, position
+                    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12);
+                "#)
+                    .bind(product_id)
+                    .bind(&variation.sku)
+                    .bind(Json(&variation.attributes))
+                    .bind(variation.price)
+                    .bind(variation.regular_price)
+                    .bind(variation.sale_price)
+                    .bind(variation.on_sale)
+                    .bind(variation.stock_quantity)
+                    .bind(&variation.stock_status)
+                    .bind(variation.weight as i32)
+                    .bind(variation.media_id)
+                    .bind(position as i32)
+                    .execute(&mut *transaction)
+                    .await?;
+            } else {
+                sqlx::query(r#"
+                    UPDATE product_variations
+                    SET sku = $1, attributes = $2, price = $3, regular_price = $4, sale_price = $5, on_sale = $6,
+                        stock_quantity = $7, stock_status = $8, weight = $9, media_id = $10, position = $11
+                    WHERE id = $12 AND product_id = $13;
+                "#)
+                    .bind(&variation.sku)
+                    .bind(Json(&variation.attributes))
+                    .bind(variation.price)
+                    .bind(variation.regular_price)
+                    .bind(variation.sale_price)
+                    .bind(variation.on_sale)
+                    .bind(variation.stock_quantity)
+                    .bind(&variation.stock_status)
+                    .bind(variation.weight as i32)
+                    .bind(variation.media_id)
+                    .bind(position as i32)
+                    .bind(variation.id)
+                    .bind(product_id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+        }
+
+        sqlx::query(r#"
+            DELETE FROM product_variations WHERE product_id = $1 AND id = ANY($2);
+        "#)
+            .bind(product_id)
+            .bind(removed)
+            .execute(&mut *transaction)
+            .await?;
+
+        // Checked once all the rows are saved, two variations may swap their options
+        let duplicated = sqlx::query(r#"
+            SELECT attributes FROM product_variations WHERE product_id = $1
+            GROUP BY attributes HAVING COUNT(*) > 1 LIMIT 1;
+        "#)
+            .bind(product_id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+        if duplicated.is_some() {
+            return Err(anyhow::anyhow!("two variations have the same options"));
+        }
+
+        sync_product(&mut *transaction, product_id).await?;
+
+        let after = variations_snapshot(&fetch_all(&mut *transaction, product_id).await?);
+        audit::record_with(&mut *transaction, "product.variations", "product", product_id, Some(before), Some(after))
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    // Adds a variation for every combination of options that has none yet,
+    // they start with the product price and weight and without stock
+    pub async fn generate(&self, product_id: i32) -> Result<usize, anyhow::Error> {
+        let attributes = self.attributes(product_id).await?;
+        if attributes.is_empty() {
+            return Ok(0);
+        }
+
+        let combinations = combinations(&attributes);
+        if combinations.len() > MAX_VARIATIONS {
+            return Err(anyhow::anyhow!("{} combinations, the limit is {} variations", combinations.len(), MAX_VARIATIONS));
+        }
+
+        let row = sqlx::query(r#"
+            SELECT regular_price, weight FROM products WHERE id = $1;
+        "#)
+            .bind(product_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut variations = self.get_all(product_id).await?;
+        let count = variations.len();
+
+        for combination in combinations {
+            if variations.iter().any(|variation| variation.attributes == combination) {
+                continue;
+            }
+
+            let mut variation = Variation::new();
+            variation.attributes = combination;
+            variation.weight = row.get::<Option<i32>, _>("weight").unwrap_or(0) as u32;
+            variation.set_prices(decimal(&row, "regular_price"), None);
+            variations.push(variation);
+        }
+
+        let added = variations.len() - count;
+        if added > 0 {
+            self.save(product_id, &variations, &[]).await?;
+        }
+
+        Ok(added)
+    }
+
+    // Called after the product form is saved, it does not know the variations
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
+        Variations {
+            pool,
+        }
+    }
+}
@@ -0,0 +1,1072 @@
+//
+// Description: Import of a WooCommerce shop from its REST API (JSON) exports
+// Last Modification: 2026-10-18 11:44:25
+//
+
+use crate::models::orders;
+use crate::models::products::{
+    self,
+    CatalogVisibility,
+    ProductType,
+    Status,
+    StockStatus,
+};
+use crate::models::users;
+
+use anyhow;
+use chrono::NaiveDateTime;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use sqlx::{
+    types::Json,
+    Acquire,
+    PgConnection,
+    Row,
+};
+use strum::IntoEnumIterator;
+use uuid::Uuid;
+
+// The files of an export directory, in import order. Each one holds the JSON
+// array returned by the REST API, e.g. GET /wp-json/wc/v3/products?per_page=100
+pub const FILES: [&str; 4] = ["categories.json", "products.json", "customers.json", "orders.json"];
+
+// Fields computed by WooCommerce, they are not reported as unmapped
+const DERIVED_FIELDS: [&str; 5] = ["permalink", "price_html", "purchasable", "related_ids", "count"];
+
+// "kg", "g", "lbs" or "oz", the weights are stored in grams
+pub fn grams_per_unit(unit: &str) -> Option<f32> {
+    match unit {
+        "kg" => Some(1000.0),
+        "g" => Some(1.0),
+        "lbs" => Some(453.592),
+        "oz" => Some(28.3495),
+        _ => None,
+    }
+}
+
+type Fields = BTreeMap<String, JsonValue>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooImage {
+    src: String,
+    name: String,
+    alt: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WooCategory {
+    id: i32,
+    name: String,
+    #[serde(default)]
+    slug: String,
+    #[serde(default)]
+    parent: i32,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    image: Option<WooImage>,
+    #[serde(flatten)]
+    other: Fields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooTerm {
+    id: i32,
+    name: String,
+    slug: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooAttribute {
+    name: String,
+    position: i32,
+    options: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooDimensions {
+    length: String,
+    width: String,
+    height: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct WooProduct {
+    id: i32,
+    name: String,
+    slug: String,
+    #[serde(rename = "type")]
+    product_type: String,
+    status: String,
+    featured: bool,
+    catalog_visibility: String,
+    description: String,
+    short_description: String,
+    sku: String,
+    price: String,
+    regular_price: String,
+    sale_price: String,
+    date_on_sale_from: Option<String>,
+    date_on_sale_to: Option<String>,
+    on_sale: bool,
+    total_sales: JsonValue, // a number, or a string in older versions
+    external_url: String,
+    button_text: String,
+    manage_stock: bool,
+    stock_quantity: Option<i32>,
+    stock_status: String,
+    weight: String,
+    dimensions: WooDimensions,
+    date_created: Option<String>,
+    date_modified: Option<String>,
+    categories: Vec<WooTerm>,
+    tags: Vec<WooTerm>,
+    images: Vec<WooImage>,
+    attributes: Vec<WooAttribute>,
+    grouped_products: Vec<i32>,
+    upsell_ids: Vec<i32>,
+    cross_sell_ids: Vec<i32>,
+    #[serde(flatten)]
+    other: Fields,
+}
+
+impl Default for WooProduct {
+    fn default() -> Self {
+        WooProduct {
+            id: 0,
+            name: "".to_string(),
+            slug: "".to_string(),
+            product_type: "simple".to_string(),
+            status: "publish".to_string(),
+            featured: false,
+            catalog_visibility: "visible".to_string(),
+            description: "".to_string(),
+            short_description: "".to_string(),
+            sku: "".to_string(),
+            price: "".to_string(),
+            regular_price: "".to_string(),
+            sale_price: "".to_string(),
+            date_on_sale_from: None,
+            date_on_sale_to: None,
+            on_sale: false,
+            total_sales: JsonValue::Null,
+            external_url: "".to_string(),
+            button_text: "".to_string(),
+            manage_stock: false,
+            stock_quantity: None,
+            stock_status: "instock".to_string(),
+            weight: "".to_string(),
+            dimensions: WooDimensions::default(),
+            date_created: None,
+            date_modified: None,
+            categories: vec![],
+            tags: vec![],
+            images: vec![],
+            attributes: vec![],
+            grouped_products: vec![],
+            upsell_ids: vec![],
+            cross_sell_ids: vec![],
+            other: Fields::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooAddress {
+    first_name: String,
+    last_name: String,
+    address_1: String,
+    address_2: String,
+    city: String,
+    postcode: String,
+    country: String,
+    email: String,
+    phone: String,
+    #[serde(flatten)]
+    other: Fields, // e.g. company and state
+}
+
+impl WooAddress {
+    fn address(&self) -> String {
+        [self.address_1.trim(), self.address_2.trim()]
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    fn billing(&self) -> orders::Billing {
+        orders::Billing {
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            address: self.address(),
+            city: self.city.clone(),
+            postcode: self.postcode.clone(),
+            country_code: self.country.clone(),
+            email: self.email.clone(),
+            phone: self.phone.clone(),
+            tax_id_number: "".to_string(),
+        }
+    }
+
+    fn shipping(&self) -> orders::Shipping {
+        orders::Shipping {
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            address: self.address(),
+            city: self.city.clone(),
+            postcode: self.postcode.clone(),
+            country_code: self.country.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WooCustomer {
+    id: i32,
+    email: String,
+    #[serde(default)]
+    first_name: String,
+    #[serde(default)]
+    last_name: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    billing: WooAddress,
+    #[serde(default)]
+    shipping: WooAddress,
+    #[serde(default)]
+    avatar_url: String,
+    #[serde(default)]
+    date_created: Option<String>,
+    #[serde(flatten)]
+    other: Fields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooLineItem {
+    name: String,
+    product_id: i32,
+    quantity: i32,
+    sku: String,
+    price: JsonValue, // a number
+    subtotal: String,
+    subtotal_tax: String,
+    total: String,
+    total_tax: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WooShippingLine {
+    total: String,
+    total_tax: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WooOrder {
+    id: i32,
+    #[serde(default)]
+    order_key: String,
+    status: String,
+    #[serde(default)]
+    currency: String,
+    #[serde(default)]
+    date_created: Option<String>,
+    #[serde(default)]
+    date_modified: Option<String>,
+    #[serde(default)]
+    discount_total: String,
+    #[serde(default)]
+    discount_tax: String,
+    #[serde(default)]
+    shipping_total: String,
+    #[serde(default)]
+    shipping_tax: String,
+    #[serde(default)]
+    cart_tax: String,
+    #[serde(default)]
+    total: String,
+    #[serde(default)]
+    total_tax: String,
+    #[serde(default)]
+    prices_include_tax: bool,
+    #[serde(default)]
+    customer_id: i32,
+    #[serde(default)]
+    customer_ip_address: String,
+    #[serde(default)]
+    customer_user_agent: String,
+    #[serde(default)]
+    customer_note: String,
+    #[serde(default)]
+    billing: WooAddress,
+    #[serde(default)]
+    shipping: WooAddress,
+    #[serde(default)]
+    payment_method: String,
+    #[serde(default)]
+    payment_method_title: String,
+    #[serde(default)]
+    date_paid: Option<String>,
+    #[serde(default)]
+    date_completed: Option<String>,
+    #[serde(default)]
+    cart_hash: String,
+    #[serde(default)]
+    line_items: Vec<WooLineItem>,
+    #[serde(default)]
+    shipping_lines: Vec<WooShippingLine>,
+    #[serde(flatten)]
+    other: Fields,
+}
+
+// "2024-03-22T16:28:02", the local time of the shop. Empty is none.
+fn date(value: &Option<String>) -> Result<Option<NaiveDateTime>, anyhow::Error> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("{:?} is not a date", value)),
+    }
+}
+
+// Amounts are strings, "19.99". Empty is 0.
+fn amount(value: &str) -> Result<f32, anyhow::Error> {
+    match value.trim() {
+        "" => Ok(0.00),
+        value => value.parse::<f32>()
+            .ok()
+            .filter(|amount| amount.is_finite())
+            .ok_or(anyhow::anyhow!("{:?} is not an amount", value)),
+    }
+}
+
+// A value other than the default of its type, worth reporting when unmapped
+fn has_value(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(value) => *value,
+        JsonValue::Number(value) => value.as_f64() != Some(0.0),
+        JsonValue::String(value) => !value.is_empty(),
+        JsonValue::Array(values) => !values.is_empty(),
+        JsonValue::Object(values) => !values.is_empty(),
+    }
+}
+
+// What happened to a record
+enum Outcome {
+    Imported, // under its WooCommerce id
+    Renumbered(i32), // the id was taken
+    Existing, // already in the shop, e.g. imported by a previous run
+}
+
+#[derive(Debug, Default)]
+pub struct ResourceReport {
+    pub file: &'static str,
+    pub imported: usize,
+    pub existing: usize,
+    pub renumbered: Vec<(i32, i32)>, // WooCommerce id => new id
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>, // one per record left out
+    pub unmapped: BTreeMap<String, usize>, // field => records with a value in it
+}
+
+impl ResourceReport {
+    fn unmapped(&mut self, prefix: &str, fields: &Fields) {
+        for (name, value) in fields {
+            if name.starts_with('_') || name.ends_with("_gmt") || DERIVED_FIELDS.contains(&name.as_str()) {
+                continue;
+            }
+            if has_value(value) {
+                *self.unmapped.entry(format!("{}{}", prefix, name)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn outcome(&mut self, woo_id: i32, outcome: &Outcome) {
+        match outcome {
+            Outcome::Imported => self.imported += 1,
+            Outcome::Renumbered(id) => {
+                self.imported += 1;
+                self.renumbered.push((woo_id, *id));
+            },
+            Outcome::Existing => self.existing += 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub resources: Vec<ResourceReport>,
+}
+
+fn read<T: for<'de> Deserialize<'de>>(directory: &Path, file: &str) -> Result<Option<Vec<T>>, anyhow::Error> {
+    let path = directory.join(file);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(&path)?;
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("{}: {}", file, e))
+}
+
+// The WooCommerce id when it is free, a new one otherwise
+async fn free_id(connection: &mut PgConnection, table: &str, id: i32) -> Result<Option<i32>, anyhow::Error> {
+    let taken: bool = sqlx::query(&format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = $1);", table))
+        .bind(id)
+        .fetch_one(&mut *connection)
+        .await?
+        .get(0);
+
+    Ok(if taken || id <= 0 { None } else { Some(id) })
+}
+
+async fn media(connection: &mut PgConnection, image: &WooImage) -> Result<i32, anyhow::Error> {
+    let existing: Option<i32> = sqlx::query("SELECT id FROM media WHERE src = $1 ORDER BY id LIMIT 1;")
+        .bind(&image.src)
+        .fetch_optional(&mut *connection)
+        .await?
+        .map(|row| row.get(0));
+
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+
+    let id: i32 = sqlx::query("INSERT INTO media (src, name, alt) VALUES ($1, $2, $3) RETURNING id;")
+        .bind(&image.src)
+        .bind(&image.name)
+        .bind(&image.alt)
+        .fetch_one(&mut *connection)
+        .await?
+        .get(0);
+
+    Ok(id)
+}
+
+pub struct WooCommerce {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    dry_run: bool,
+    grams_per_unit: f32,
+    // WooCommerce id => id in the shop
+    categories: HashMap<i32, i32>,
+    products: HashMap<i32, i32>,
+    customers: HashMap<i32, i32>,
+}
+
+impl WooCommerce {
+
+    // Everything is imported in one transaction, a dry run rolls it back.
+    // A record that fails is left out and reported, the others are kept.
+    pub async fn import(&mut self, directory: &Path) -> Result<ImportReport, anyhow::Error> {
+        let categories: Option<Vec<WooCategory>> = read(directory, FILES[0])?;
+        let products: Option<Vec<WooProduct>> = read(directory, FILES[1])?;
+        let customers: Option<Vec<WooCustomer>> = read(directory, FILES[2])?;
+        let orders: Option<Vec<WooOrder>> = read(directory, FILES[3])?;
+
+        if categories.is_none() && products.is_none() && customers.is_none() && orders.is_none() {
+            return Err(anyhow::anyhow!("none of {} is in {}", FILES.join(", "), directory.display()));
+        }
+
+        let mut transaction = self.pool.begin().await?;
+
+        // The new ids, of the records whose id is taken, are above the
+        // WooCommerce ones so they do not take the id of a later record.
+        // The sequences are not rolled back, a dry run leaves a gap.
+        for (table, ids) in [
+            ("categories", categories.iter().flatten().map(|category| category.id).max()),
+            ("products", products.iter().flatten().map(|product| product.id).max()),
+            ("users", customers.iter().flatten().map(|customer| customer.id).max()),
+            ("orders", orders.iter().flatten().map(|order| order.id).max()),
+        ] {
+            sqlx::query(&format!(r#"
+                SELECT setval(pg_get_serial_sequence('{table}', 'id'),
+                    GREATEST((SELECT COALESCE(MAX(id), 0) FROM {table}), $1, 1));
+            "#, table = table))
+                .bind(ids.unwrap_or(0))
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        let mut report = ImportReport {
+            dry_run: self.dry_run,
+            resources: vec![],
+        };
+
+        if let Some(categories) = categories {
+            let mut resource = ResourceReport { file: FILES[0], ..Default::default() };
+
+            // The parents first
+            let mut pending: Vec<&WooCategory> = categories.iter().collect();
+            while !pending.is_empty() {
+                let (ready, waiting): (Vec<&WooCategory>, Vec<&WooCategory>) = pending.into_iter()
+                    .partition(|category| category.parent == 0 || self.categories.contains_key(&category.parent)
+                        || !categories.iter().any(|parent| parent.id == category.parent));
+                if ready.is_empty() {
+                    for category in waiting {
+                        resource.errors.push(format!("category {}: its parent {} can not be imported", category.id, category.parent));
+                    }
+                    break;
+                }
+                for category in ready {
+                    let mut savepoint = transaction.begin().await?;
+                    match self.category(&mut savepoint, category, &mut resource).await {
+                        Ok(outcome) => {
+                            savepoint.commit().await?;
+                            resource.outcome(category.id, &outcome);
+                        },
+                        Err(e) => {
+                            savepoint.rollback().await?;
+                            resource.errors.push(format!("category {}: {}", category.id, e));
+                        },
+                    }
+                }
+                pending = waiting;
+            }
+            report.resources.push(resource);
+        }
+
+        if let Some(products) = products {
+            let mut resource = ResourceReport { file: FILES[1], ..Default::default() };
+            let mut imported = vec![];
+
+            for product in products.iter() {
+                let mut savepoint = transaction.begin().await?;
+                match self.product(&mut savepoint, product, &mut resource).await {
+                    Ok(outcome) => {
+                        savepoint.commit().await?;
+                        resource.outcome(product.id, &outcome);
+                        if !matches!(outcome, Outcome::Existing) {
+                            imported.push(product);
+                        }
+                    },
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        resource.errors.push(format!("product {}: {}", product.id, e));
+                    },
+                }
+            }
+
+            // Grouped products, upsells and cross-sells once every product has its id
+            for product in imported {
+                self.product_links(&mut transaction, product, &mut resource).await?;
+            }
+            report.resources.push(resource);
+        }
+
+        if let Some(customers) = customers {
+            let mut resource = ResourceReport { file: FILES[2], ..Default::default() };
+            for customer in customers.iter() {
+                let mut savepoint = transaction.begin().await?;
+                match self.customer(&mut savepoint, customer, &mut resource).await {
+                    Ok(outcome) => {
+                        savepoint.commit().await?;
+                        resource.outcome(customer.id, &outcome);
+                    },
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        resource.errors.push(format!("customer {}: {}", customer.id, e));
+                    },
+                }
+            }
+            report.resources.push(resource);
+        }
+
+        if let Some(orders) = orders {
+            let mut resource = ResourceReport { file: FILES[3], ..Default::default() };
+            for order in orders.iter() {
+                let mut savepoint = transaction.begin().await?;
+                match self.order(&mut savepoint, order, &mut resource).await {
+                    Ok(outcome) => {
+                        savepoint.commit().await?;
+                        resource.outcome(order.id, &outcome);
+                    },
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        resource.errors.push(format!("order {}: {}", order.id, e));
+                    },
+                }
+            }
+            report.resources.push(resource);
+        }
+
+        if self.dry_run {
+            transaction.rollback().await?;
+        } else {
+            transaction.commit().await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn category(&mut self,
+        connection: &mut PgConnection,
+        category: &WooCategory,
+        resource: &mut ResourceReport) -> Result<Outcome, anyhow::Error> {
+
+        let slug = if category.slug.is_empty() { slug::slugify(&category.name) } else { category.slug.clone() };
+
+        // The slugs are unique, a category of the same slug is the same one
+        let existing: Option<i32> = sqlx::query("SELECT id FROM categories WHERE slug = $1;")
+            .bind(&slug)
+            .fetch_optional(&mut *connection)
+            .await?
+            .map(|row| row.get(0));
+
+        if let Some(id) = existing {
+            self.categories.insert(category.id, id);
+            return Ok(Outcome::Existing);
+        }
+
+        let parent = match category.parent {
+            0 => 0,
+            parent => match self.categories.get(&parent) {
+                Some(id) => *id,
+                None => {
+                    resource.warnings.push(format!("category {}: the parent {} is not in the export, it is a top category", category.id, parent));
+                    0
+                },
+            },
+        };
+
+        let media_id = match category.image.as_ref().filter(|image| !image.src.is_empty()) {
+            Some(image) => Some(media(connection, image).await?),
+            None => None,
+        };
+
+        let woo_id = free_id(connection, "categories", category.id).await?;
+        let id: i32 = sqlx::query(r#"
+            INSERT INTO categories (id, name, slug, parent, description, media_id)
+            VALUES (COALESCE($1, nextval(pg_get_serial_sequence('categories', 'id'))), $2, $3, $4, $5, $6)
+            RETURNING id;
+        "#)
+            .bind(woo_id)
+            .bind(&category.name)
+            .bind(&slug)
+            .bind(parent)
+            .bind(&category.description)
+            .bind(media_id)
+            .fetch_one(&mut *connection)
+            .await?
+            .get(0);
+
+        resource.unmapped("", &category.other);
+        self.categories.insert(category.id, id);
+
+        Ok(if woo_id.is_some() { Outcome::Imported } else { Outcome::Renumbered(id) })
+    }
+
+    async fn product(&mut self,
+        connection: &mut PgConnection,
+        product: &WooProduct,
+        resource: &mut ResourceReport) -> Result<Outcome, anyhow::Error> {
+
+        let existing: Option<i32> = sqlx::query(r#"
+            SELECT id FROM products
+            WHERE slug = $2 AND (id = $1 OR (sku = $3 AND sku <> ''))
+            ORDER BY id LIMIT 1;
+        "#)
+            .bind(product.id)
+            .bind(&product.slug)
+            .bind(&product.sku)
+            .fetch_optional(&mut *connection)
+            .await?
+            .map(|row| row.get(0));
+
+        if let Some(id) = existing {
+            self.products.insert(product.id, id);
+            return Ok(Outcome::Existing);
+        }
+
+        let product_type = ProductType::iter()
+            .find(|option| option.as_str() == product.product_type)
+            .ok_or(anyhow::anyhow!("the type {:?} is not supported", product.product_type))?;
+        // The variations are not in products.json, without them it could not be bought
+        if product_type == ProductType::Variable {
+            return Err(anyhow::anyhow!("the variable products are not imported, the export has no variations"));
+        }
+        let status = Status::iter()
+            .find(|option| option.as_str() == product.status)
+            .ok_or(anyhow::anyhow!("the status {:?} is not supported", product.status))?;
+        let catalog_visibility = CatalogVisibility::iter()
+            .find(|option| option.as_str() == product.catalog_visibility)
+            .ok_or(anyhow::anyhow!("the catalog visibility {:?} is not supported", product.catalog_visibility))?;
+        let stock_status = StockStatus::iter()
+            .find(|option| option.as_str() == product.stock_status)
+            .ok_or(anyhow::anyhow!("the stock status {:?} is not supported", product.stock_status))?;
+
+        let regular_price = amount(&product.regular_price)?;
+        let sale_price = amount(&product.sale_price)?;
+        let price = match product.price.trim() {
+            "" => regular_price,
+            price => amount(price)?,
+        };
+        let weight = (amount(&product.weight)? * self.grams_per_unit).round() as i32;
+        let total_sales = match &product.total_sales {
+            JsonValue::String(value) => value.parse::<i32>().unwrap_or(0),
+            value => value.as_i64().unwrap_or(0) as i32,
+        };
+
+        let name = if product.name.is_empty() { format!("Product {}", product.id) } else { product.name.clone() };
+        let slug = products::unique_slug(&mut *connection, if product.slug.is_empty() { &name } else { &product.slug }).await?;
+        if !product.slug.is_empty() && slug != product.slug {
+            resource.warnings.push(format!("product {}: the slug {:?} is taken, it is now {:?}", product.id, product.slug, slug));
+        }
+
+        let categories: Vec<i32> = product.categories.iter()
+            .filter_map(|category| match self.categories.get(&category.id) {
+                Some(id) => Some(*id),
+                None => {
+                    resource.warnings.push(format!("product {}: the category {} ({}) was not imported", product.id, category.id, category.name));
+                    None
+                },
+            })
+            .collect();
+
+        let woo_id = free_id(connection, "products", product.id).await?;
+        let id: i32 = sqlx::query(r#"
+            INSERT INTO products (
+                id, sku, name, slug, permalink, type, status, featured, catalog_visibility,
+                description, short_description, price, regular_price, sale_price, on_sale,
+                date_on_sale_from, date_on_sale_to, manage_stock, stock_quantity, stock_status, weight,
+                date_created, date_modified, primary_category, external_url, button_text, total_sales
+            ) VALUES (
+                COALESCE($1, nextval(pg_get_serial_sequence('products', 'id'))), $2, $3, $4, $5, $6, $7, $8, $9,
+                $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21,
+                COALESCE($22, CURRENT_TIMESTAMP), COALESCE($23, CURRENT_TIMESTAMP), $24, $25, $26, $27
+            ) RETURNING id;
+        "#)
+            .bind(woo_id)
+            .bind(&product.sku)
+            .bind(&name)
+            .bind(&slug)
+            .bind(format!("/product/{}", slug))
+            .bind(&product_type)
+            .bind(&status)
+            .bind(product.featured)
+            .bind(&catalog_visibility)
+            .bind(&product.description)
+            .bind(&product.short_description)
+            .bind(price)
+            .bind(regular_price)
+            .bind(sale_price)
+            .bind(product.on_sale)
+            .bind(date(&product.date_on_sale_from)?)
+            .bind(date(&product.date_on_sale_to)?)
+            .bind(product.manage_stock)
+            .bind(product.stock_quantity.unwrap_or(0))
+            .bind(&stock_status)
+            .bind(weight)
+            .bind(date(&product.date_created)?)
+            .bind(date(&product.date_modified)?)
+            .bind(categories.first().copied().unwrap_or(0))
+            .bind(&product.external_url)
+            .bind(&product.button_text)
+            .bind(total_sales)
+            .fetch_one(&mut *connection)
+            .await?
+            .get(0);
+
+        for category_id in categories.iter() {
+            sqlx::query("INSERT INTO product_categories (product_id, category_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;")
+                .bind(id)
+                .bind(category_id)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        for tag in product.tags.iter() {
+            let slug = if tag.slug.is_empty() { slug::slugify(&tag.name) } else { tag.slug.clone() };
+            let tag_id: i32 = sqlx::query(r#"
+                INSERT INTO tags (name, slug) VALUES ($1, $2)
+                ON CONFLICT (slug) DO UPDATE SET name = tags.name
+                RETURNING id;
+            "#)
+                .bind(&tag.name)
+                .bind(&slug)
+                .fetch_one(&mut *connection)
+                .await?
+                .get(0);
+
+            sqlx::query("INSERT INTO product_tags (product_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;")
+                .bind(id)
+                .bind(tag_id)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        for (position, image) in product.images.iter().filter(|image| !image.src.is_empty()).enumerate() {
+            let media_id = media(connection, image).await?;
+            sqlx::query("INSERT INTO product_media (product_id, media_id, position) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING;")
+                .bind(id)
+                .bind(media_id)
+                .bind(position as i32)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        for attribute in product.attributes.iter() {
+            sqlx::query("INSERT INTO product_attributes (product_id, name, options, position) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING;")
+                .bind(id)
+                .bind(&attribute.name)
+                .bind(Json(&attribute.options))
+                .bind(attribute.position)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        let dimensions = &product.dimensions;
+        if [&dimensions.length, &dimensions.width, &dimensions.height].iter().any(|value| !value.is_empty()) {
+            sqlx::query("INSERT INTO dimentions (product_id, length, width, height) VALUES ($1, $2, $3, $4);")
+                .bind(id)
+                .bind(amount(&dimensions.length)?.round() as i32)
+                .bind(amount(&dimensions.width)?.round() as i32)
+                .bind(amount(&dimensions.height)?.round() as i32)
+                .execute(&mut *connection)
+                .await?;
+        }
+
+        resource.unmapped("", &product.other);
+        self.products.insert(product.id, id);
+
+        Ok(if woo_id.is_some() { Outcome::Imported } else { Outcome::Renumbered(id) })
+    }
+
+    async fn product_links(&self,
+        connection: &mut PgConnection,
+        product: &WooProduct,
+        resource: &mut ResourceReport) -> Result<(), anyhow::Error> {
+
+        let id = self.products[&product.id];
+        for (table, link_type, woo_ids) in [
+            ("grouped_products", None, &product.grouped_products),
+            ("linked_products", Some(products::LinkType::Upsell), &product.upsell_ids),
+            ("linked_products", Some(products::LinkType::CrossSell), &product.cross_sell_ids),
+        ] {
+            for (position, woo_id) in woo_ids.iter().enumerate() {
+                let linked_id = match self.products.get(woo_id) {
+                    Some(linked_id) if *linked_id != id => *linked_id,
+                    _ => {
+                        resource.warnings.push(format!("product {}: the linked product {} was not imported", product.id, woo_id));
+                        continue;
+                    },
+                };
+
+                match link_type.as_ref() {
+                    None => sqlx::query(&format!("INSERT INTO {} (product_id, child_id, position) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING;", table))
+                        .bind(id)
+                        .bind(linked_id)
+                        .bind(position as i32)
+                        .execute(&mut *connection)
+                        .await?,
+                    Some(link_type) => sqlx::query(&format!("INSERT INTO {} (product_id, linked_id, link_type, position) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING;", table))
+                        .bind(id)
+                        .bind(linked_id)
+                        .bind(link_type)
+                        .bind(position as i32)
+                        .execute(&mut *connection)
+                        .await?,
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    // The customers set a password with a reset, WooCommerce does not export them
+    async fn customer(&mut self,
+        connection: &mut PgConnection,
+        customer: &WooCustomer,
+        resource: &mut ResourceReport) -> Result<Outcome, anyhow::Error> {
+
+        let existing: Option<i32> = sqlx::query("SELECT id FROM users WHERE LOWER(email) = LOWER($1);")
+            .bind(&customer.email)
+            .fetch_optional(&mut *connection)
+            .await?
+            .map(|row| row.get(0));
+
+        if let Some(id) = existing {
+            self.customers.insert(customer.id, id);
+            return Ok(Outcome::Existing);
+        }
+
+        if !customer.role.is_empty() && customer.role != "customer" {
+            resource.warnings.push(format!("customer {}: the role {:?} is imported as a customer", customer.id, customer.role));
+        }
+
+        let woo_id = free_id(connection, "users", customer.id).await?;
+        let id: i32 = sqlx::query(r#"
+            INSERT INTO users (
+                id, username, email, password, first_name, last_name, role, avatar_url,
+                billing, shipping, phone, date_created
+            ) VALUES (
+                COALESCE($1, nextval(pg_get_serial_sequence('users', 'id'))), $2, $3, $4, $5, $6, $7, $8,
+                $9, $10, $11, COALESCE($12, CURRENT_TIMESTAMP)
+            ) RETURNING id;
+        "#)
+            .bind(woo_id)
+            .bind(if customer.username.is_empty() { &customer.email } else { &customer.username })
+            .bind(&customer.email)
+            .bind(users::hash_password(&Uuid::new_v4().to_string()).await?)
+            .bind(&customer.first_name)
+            .bind(&customer.last_name)
+            .bind(users::UserRoles::Customer)
+            .bind(&customer.avatar_url)
+            .bind(Json(customer.billing.billing()))
+            .bind(Json(customer.shipping.shipping()))
+            .bind(&customer.billing.phone)
+            .bind(date(&customer.date_created)?)
+            .fetch_one(&mut *connection)
+            .await?
+            .get(0);
+
+        resource.unmapped("", &customer.other);
+        resource.unmapped("billing.", &customer.billing.other);
+        resource.unmapped("shipping.", &customer.shipping.other);
+        self.customers.insert(customer.id, id);
+
+        Ok(if woo_id.is_some() { Outcome::Imported } else { Outcome::Renumbered(id) })
+    }
+
+    async fn order(&mut self,
+        connection: &mut PgConnection,
+        order: &WooOrder,
+        resource: &mut ResourceReport) -> Result<Outcome, anyhow::Error> {
+
+        let existing: bool = sqlx::query("SELECT EXISTS (SELECT 1 FROM orders WHERE order_key = $1 AND order_key <> '');")
+            .bind(&order.order_key)
+            .fetch_one(&mut *connection)
+            .await?
+            .get(0);
+
+        if existing {
+            return Ok(Outcome::Existing);
+        }
+
+        let status = orders::OrderStatus::parse(&order.status)
+            .ok_or(anyhow::anyhow!("the status {:?} is not supported", order.status))?;
+        let currency: orders::Currency = serde_json::from_value(JsonValue::String(order.currency.clone()))
+            .map_err(|_| anyhow::anyhow!("the currency {:?} is not supported", order.currency))?;
+
+        let customer_id = match order.customer_id {
+            0 => 0,
+            customer_id => match self.customers.get(&customer_id) {
+                Some(id) => *id,
+                None => {
+                    resource.warnings.push(format!("order {}: the customer {} was not imported, it is a guest order", order.id, customer_id));
+                    0
+                },
+            },
+        };
+
+        let mut line_items = vec![];
+        for line in order.line_items.iter() {
+            let product_id = match self.products.get(&line.product_id) {
+                Some(id) => *id,
+                None => {
+                    resource.warnings.push(format!("order {}: the product {} ({}) was not imported", order.id, line.product_id, line.name));
+                    0
+                },
+            };
+            line_items.push(orders::LineItem {
+                product_id,
+                variation_id: 0, // the variable products are not imported
+                sku: line.sku.clone(),
+                name: line.name.clone(),
+                price: line.price.as_f64().unwrap_or(0.0) as f32,
+                quantity: line.quantity,
+                subtotal: amount(&line.subtotal)?,
+                subtotal_tax: amount(&line.subtotal_tax)?,
+                total: amount(&line.total)?,
+                total_tax: amount(&line.total_tax)?,
+            });
+        }
+
+        let mut shipping_lines = vec![];
+        for line in order.shipping_lines.iter() {
+            shipping_lines.push(orders::ShippingLine {
+                total: amount(&line.total)?,
+                total_tax: amount(&line.total_tax)?,
+            });
+        }
+
+        let woo_id = free_id(connection, "orders", order.id).await?;
+        let id: i32 = sqlx::query(r#"
+            INSERT INTO orders (
+                id, order_key, customer_id, customer_ip_address, customer_user_agent, customer_note,
+                billing, shipping, line_items, shipping_lines,
+                payment_method, payment_method_title, status, currency,
+                discount_total, discount_tax, shipping_total, shipping_tax, cart_tax, total, total_tax,
+                prices_include_tax, cart_hash, date_created, date_modified, date_paid, date_completed
+            ) VALUES (
+                COALESCE($1, nextval(pg_get_serial_sequence('orders', 'id'))), $2, $3, $4, $5, $6,
+                $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21,
+                $22, $23, COALESCE($24, CURRENT_TIMESTAMP), COALESCE($25, CURRENT_TIMESTAMP), $26, $27
+            ) RETURNING id;
+        "#)
+            .bind(woo_id)
+            .bind(&order.order_key)
+            .bind(customer_id)
+            .bind(&order.customer_ip_address)
+            .bind(&order.customer_user_agent)
+            .bind(&order.customer_note)
+            .bind(Json(order.billing.billing()))
+            .bind(Json(order.shipping.shipping()))
+            .bind(Json(&line_items))
+            .bind(Json(&shipping_lines))
+            .bind(&order.payment_method)
+            .bind(&order.payment_method_title)
+            .bind(&status)
+            .bind(&currency)
+            .bind(amount(&order.discount_total)?)
+            .bind(amount(&order.discount_tax)?)
+            .bind(amount(&order.shipping_total)?)
+            .bind(amount(&order.shipping_tax)?)
+            .bind(amount(&order.cart_tax)?)
+            .bind(amount(&order.total)?)
+            .bind(amount(&order.total_tax)?)
+            .bind(order.prices_include_tax)
+            .bind(&order.cart_hash)
+            .bind(date(&order.date_created)?)
+            .bind(date(&order.date_modified)?)
+            .bind(date(&order.date_paid)?)
+            .bind(date(&order.date_completed)?)
+            .fetch_one(&mut *connection)
+            .await?
+            .get(0);
+
+        resource.unmapped("", &order.other);
+        resource.unmapped("billing.", &order.billing.other);
+        resource.unmapped("shipping.", &order.shipping.other);
+
+        Ok(if woo_id.is_some() { Outcome::Imported } else { Outcome::Renumbered(id) })
+    }
+
+    pub fn new(pool: sqlx::Pool<sqlx::Postgres>, dry_run: bool, grams_per_unit: f32) -> Self {
+        WooCommerce {
+            pool,
+            dry_run,
+            grams_per_unit,
+            categories: HashMap::new(),
+            products: HashMap::new(),
+            customers: HashMap::new(),
+        }
+    }
+}
@@ -6,51 +6,86 @@
 // https://www.courier.com/guides/rust-send-email/
 //
 
+use anyhow::Result;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::Message;
 use lettre::message::{SinglePart, header::ContentType};
 use lettre::AsyncSmtpTransport;
 use lettre::AsyncTransport;
+use sqlx::Row;
+
+// Set to print the emails to the console when no SMTP server is configured,
+// for development only: the emails hold password reset and confirmation links
+const PRINT_EMAILS_VAR: &str = "STORE_PRINT_EMAILS";
 
 pub struct SMTP {
-    username: String,
-    password: String,
-    server: String,
+    pool: sqlx::Pool<sqlx::Postgres>,
 }
 
 impl SMTP {
-    pub async fn send(&self) {
+    pub async fn send(&self,
+        to: &str,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), anyhow::Error> {
+
+        let row = sqlx::query(r#"
+            SELECT smtp_server, smtp_port, smtp_username, smtp_password, smtp_use_tls
+            FROM settings ORDER BY id LIMIT 1;
+        "#)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        // A settings row with a blank server is not configured either
+        let row = match row {
+            Some(row) if !row.get::<String, _>("smtp_server").trim().is_empty() => row,
+            _ => {
+                if std::env::var_os(PRINT_EMAILS_VAR).is_some() {
+                    println!("SMTP not configured, email to {}:\n{}\n{}", to, subject, body);
+                    return Ok(());
+                }
+                return Err(anyhow::anyhow!("SMTP is not configured, the email \"{}\" to {} was not sent", subject, to));
+            },
+        };
+
+        let username = row.get::<String, _>("smtp_username");
+
         let email = Message::builder()
-            .from("henrique.ribeiro.dias@gmail.com".parse().unwrap())
-            .to("henrique.ribeiro.dias@gmail.com".parse().unwrap())
-            .subject("Test email from Store")
+            .from(username.parse()?)
+            .to(to.parse()?)
+            .subject(subject)
             .singlepart(SinglePart::builder()
             .header(ContentType::TEXT_PLAIN)
-            .body("Hello, this is a test email sent from a test store!".to_string()))
-            .unwrap();
+            .body(body.to_string()))?;
 
         // Set up the SMTP client
-        let credentials = Credentials::new(self.username.clone(), self.password.clone());
+        let credentials = Credentials::new(username, row.get::<String, _>("smtp_password"));
+
+        let server = row.get::<String, _>("smtp_server").trim().to_string();
+        let builder = if row.get::<Option<bool>, _>("smtp_use_tls").unwrap_or(false) {
+            AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(&server)?
+        } else {
+            AsyncSmtpTransport::<lettre::Tokio1Executor>::starttls_relay(&server)?
+        };
 
-        let mailer = AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(&self.server)
-            .unwrap()
+        let builder = match row.get::<Option<i32>, _>("smtp_port").unwrap_or(0) {
+            0 => builder,
+            port => builder.port(port as u16),
+        };
+
+        let mailer = builder
             .credentials(credentials)
             .build();
 
         // Send the email
-        match mailer.send(email).await {
-            Ok(_) => println!("Email sent successfully!"),
-            Err(e) => eprintln!("Failed to send email: {:?}", e),
-        }
+        mailer.send(email).await?;
+
+        Ok(())
     }
 
     pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
-
-        // https://myaccount.google.com/apppasswords
         SMTP {
-            username: "henrique.ribeiro.dias@gmail.com".to_string(),
-            password: "zszfozsnuxoeohoz".to_string(),
-            server: "smtp.gmail.com".to_string(),
+            pool,
         }
     }
-}
+}
\ No newline at end of file
@@ -14,4 +14,10 @@ pub fn round_and_format_filter(value: &Value, params: &HashMap<String, Value>) -
     Ok(Value::String(format!("{:.1$}", num, decimal_places as usize)))
 }
 
-
+// Compare two byte slices without short-circuiting on the first mismatch
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
\ No newline at end of file